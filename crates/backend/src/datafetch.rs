@@ -0,0 +1,183 @@
+use std::{
+    env,
+    str::FromStr,
+    sync::{
+        Mutex,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use axum::response::Response;
+use reqwest::{StatusCode, header};
+
+static DATAFETCH_URL: std::sync::LazyLock<String> = std::sync::LazyLock::new(|| {
+    std::env::var("DATAFETCH_URL").unwrap_or(String::from("http://0.0.0.0:6380"))
+});
+
+fn env_or<T: FromStr>(key: &str, default: T) -> T {
+    env::var(key)
+        .ok()
+        .and_then(|val| val.parse().ok())
+        .unwrap_or(default)
+}
+
+/// Stops calling the data fetcher for `cooldown` after `threshold` consecutive failures.
+#[derive(Debug)]
+struct CircuitBreaker {
+    threshold: u32,
+    cooldown: Duration,
+    failures: AtomicU32,
+    open_until: Mutex<Option<Instant>>,
+}
+
+impl CircuitBreaker {
+    fn new(threshold: u32, cooldown: Duration) -> Self {
+        CircuitBreaker {
+            threshold,
+            cooldown,
+            failures: AtomicU32::new(0),
+            open_until: Mutex::new(None),
+        }
+    }
+
+    fn is_open(&self) -> bool {
+        let mut open_until = self.open_until.lock().unwrap();
+        match *open_until {
+            Some(until) if Instant::now() < until => true,
+            Some(_) => {
+                // cooldown is over, let the next call through
+                *open_until = None;
+                false
+            }
+            None => false,
+        }
+    }
+
+    fn record_success(&self) {
+        self.failures.store(0, Ordering::Relaxed);
+    }
+
+    fn record_failure(&self) {
+        let failures = self.failures.fetch_add(1, Ordering::Relaxed) + 1;
+        if failures >= self.threshold {
+            self.failures.store(0, Ordering::Relaxed);
+            *self.open_until.lock().unwrap() = Some(Instant::now() + self.cooldown);
+            tracing::warn!(
+                "{failures} consecutive data fetcher failures, pausing calls for {}s",
+                self.cooldown.as_secs()
+            );
+        }
+    }
+}
+
+/// HTTP client for the data fetcher service at `DATAFETCH_URL`.
+///
+/// Configured through the environment:
+/// - `DATAFETCH_TIMEOUT_SECS`: per-request timeout (default 5)
+/// - `DATAFETCH_BREAKER_THRESHOLD`: consecutive failures before fast-failing (default 5)
+/// - `DATAFETCH_BREAKER_COOLDOWN_SECS`: how long to fast-fail with 503 (default 30)
+#[derive(Debug)]
+pub struct DataFetchClient {
+    client: reqwest::Client,
+    breaker: CircuitBreaker,
+}
+
+impl DataFetchClient {
+    pub fn from_env() -> Self {
+        DataFetchClient {
+            client: reqwest::Client::builder()
+                .timeout(Duration::from_secs(env_or("DATAFETCH_TIMEOUT_SECS", 5)))
+                .build()
+                .unwrap(),
+            breaker: CircuitBreaker::new(
+                env_or("DATAFETCH_BREAKER_THRESHOLD", 5),
+                Duration::from_secs(env_or("DATAFETCH_BREAKER_COOLDOWN_SECS", 30)),
+            ),
+        }
+    }
+
+    /// Whether the data fetcher answers its `/ping` route. Bypasses the circuit breaker so the
+    /// health check reflects the service itself.
+    pub async fn reachable(&self) -> bool {
+        self.client
+            .get(format!("{}/ping", DATAFETCH_URL.to_owned()))
+            .send()
+            .await
+            .is_ok_and(|response| response.status().is_success())
+    }
+
+    /// Forwards a GET for `path` to the data fetcher and relays its JSON response.
+    pub async fn proxy(&self, path: &str) -> Result<Response, StatusCode> {
+        if self.breaker.is_open() {
+            return Err(StatusCode::SERVICE_UNAVAILABLE);
+        }
+
+        let response = match self
+            .client
+            .get(format!("{}{path}", DATAFETCH_URL.to_owned()))
+            .send()
+            .await
+        {
+            Ok(response) if response.status().is_server_error() => {
+                self.breaker.record_failure();
+                response
+            }
+            Ok(response) => {
+                self.breaker.record_success();
+                response
+            }
+            Err(err) => {
+                self.breaker.record_failure();
+                return Err(if err.is_timeout() {
+                    StatusCode::GATEWAY_TIMEOUT
+                } else {
+                    StatusCode::INTERNAL_SERVER_ERROR
+                });
+            }
+        };
+
+        let builder = Response::builder()
+            .status(response.status())
+            .header(header::CONTENT_TYPE, "application/json");
+
+        let body = response
+            .bytes()
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+        Ok(builder.body(body.into()).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_breaker_opens_after_threshold() {
+        let breaker = CircuitBreaker::new(3, Duration::from_secs(60));
+        breaker.record_failure();
+        breaker.record_failure();
+        assert!(!breaker.is_open());
+
+        breaker.record_failure();
+        assert!(breaker.is_open());
+    }
+
+    #[test]
+    fn test_breaker_success_resets_count() {
+        let breaker = CircuitBreaker::new(2, Duration::from_secs(60));
+        breaker.record_failure();
+        breaker.record_success();
+        breaker.record_failure();
+        assert!(!breaker.is_open());
+    }
+
+    #[test]
+    fn test_breaker_closes_after_cooldown() {
+        let breaker = CircuitBreaker::new(1, Duration::ZERO);
+        breaker.record_failure();
+        assert!(!breaker.is_open());
+    }
+}
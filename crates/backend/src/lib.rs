@@ -1,26 +1,29 @@
-use std::{collections::{HashMap, HashSet}, env, sync::Arc};
+use std::{collections::{HashMap, HashSet}, env, sync::Arc, time::Duration};
 
 use axum::{
-    Router,
+    Json, Router,
     extract::{Path, Query, State},
-    response::Response,
+    response::{IntoResponse, Response},
     routing::get,
 };
 use esi::{
-    universe::{self, StationID, Stations, Structure}, ESIClient
+    universe::{self, Items, LocationId, NameCategory, NamedId, StationID, Stations}, ESIClient
 };
-use reqwest::{StatusCode, header};
+use reqwest::StatusCode;
+use serde::Deserialize;
 use tokio::sync::RwLock;
 
-static DATAFETCH_URL: std::sync::LazyLock<String> = std::sync::LazyLock::new(|| {
-    std::env::var("DATAFETCH_URL").unwrap_or(String::from("http://0.0.0.0:6380"))
-});
+use datafetch::DataFetchClient;
+
+mod datafetch;
 
 #[derive(Clone)]
 struct AppState {
     esi_client: Arc<RwLock<ESIClient>>,
     stations: Arc<Stations>,
+    items: Arc<Items>,
     public_structs: Arc<RwLock<HashSet<StationID>>>,
+    datafetch: Arc<DataFetchClient>,
 }
 
 pub async fn market_data() -> Router {
@@ -31,6 +34,10 @@ pub async fn market_data() -> Router {
     )));
     let stations_client = Arc::new(ESIClient::new("backend", std::env::consts::OS, 8));
 
+    // names for /universe/search, from the cache the data fetcher and clients fill
+    let items = Items::new(stations_client.clone());
+    items.load_from_cache().await;
+
     let mut allowed: HashSet<StationID> = esi_client.read().await.esi_get("/universe/structures/?datasource=tranquility&filter=market").await.unwrap().json().await.unwrap();
     allowed.insert(StationID::try_from(1042508032148).unwrap());
     allowed.insert(StationID::try_from(1042499803831).unwrap());
@@ -74,115 +81,225 @@ pub async fn market_data() -> Router {
     allowed.insert(StationID::try_from(1039479389173).unwrap());
     allowed.insert(StationID::try_from(1041517397230).unwrap());
 
+    tokio::spawn(refresh_auth_tok(esi_client.clone()));
+
     let state = AppState {
         esi_client: esi_client.clone(),
         stations: Arc::new(Stations::new(stations_client)),
+        items: Arc::new(items),
         public_structs: Arc::new(RwLock::new(allowed)),
+        datafetch: Arc::new(DataFetchClient::from_env()),
     };
 
 
     Router::new()
-        .route(
-            "/ping",
-            get(|| async { format!("OK {}", chrono::Utc::now().to_rfc2822()) }),
-        )
+        .route("/ping", get(live))
+        .route("/livez", get(live))
+        .route("/readyz", get(get_ready))
+        .route("/health", get(get_health))
         .route("/orders/{id}", get(get_orders))
         .route("/orders/updateTime", get(get_update_time))
         .route("/universe/struct_names/", get(get_structures))
+        .route("/universe/search", get(search))
         .with_state(state)
 }
 
-async fn get_orders(Path(id): Path<String>) -> Result<Response, StatusCode> {
-    let response = reqwest::get(format!("{}/market/{id}", DATAFETCH_URL.to_owned()))
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// The process is up. Answers without touching the data fetcher or ESI.
+async fn live() -> String {
+    format!("OK {}", chrono::Utc::now().to_rfc2822())
+}
 
-    let builder = Response::builder()
-        .status(response.status())
-        .header(header::CONTENT_TYPE, "application/json");
+/// Body and status of `/readyz`: ready only once every check passes.
+fn readiness(
+    datafetch_reachable: bool,
+    esi_token_valid: bool,
+) -> (StatusCode, Json<serde_json::Value>) {
+    let ready = datafetch_reachable && esi_token_valid;
+    let status = if ready {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
 
-    let body = response
-        .bytes()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    (
+        status,
+        Json(serde_json::json!({
+            "ready": ready,
+            "datafetch_reachable": datafetch_reachable,
+            "esi_token_valid": esi_token_valid,
+        })),
+    )
+}
+
+/// Whether this instance can serve traffic: the data fetcher answers `/ping` and there is a
+/// valid ESI token. The token is only checked here, [`refresh_auth_tok`] keeps it fresh.
+async fn get_ready(State(state): State<AppState>) -> Response {
+    let (datafetch_reachable, esi_token_valid) = tokio::join!(state.datafetch.reachable(), async {
+        state.esi_client.read().await.auth_tok_valid().await
+    });
 
-    Ok(builder.body(body.into()).unwrap())
+    readiness(datafetch_reachable, esi_token_valid).into_response()
 }
 
-async fn get_update_time() -> Result<Response, StatusCode> {
-    let response = reqwest::get(format!("{}/refresh_intervals", DATAFETCH_URL.to_owned()))
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+async fn get_health(State(state): State<AppState>) -> Response {
+    let datafetch_reachable = state.datafetch.reachable().await;
+    let status = if datafetch_reachable {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+
+    (
+        status,
+        Json(serde_json::json!({
+            "healthy": datafetch_reachable,
+            "datafetch_reachable": datafetch_reachable,
+        })),
+    )
+        .into_response()
+}
 
-    let builder = Response::builder()
-        .status(response.status())
-        .header(header::CONTENT_TYPE, "application/json");
+/// Orders returned by `/orders/{id}` when no `limit` is given.
+const DEFAULT_ORDER_LIMIT: usize = 5000;
 
-    let body = response
-        .bytes()
-        .await
-        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+/// Turns the `limit`, `offset`, `buy_only` and `sell_only` params of `/orders/{id}` into the
+/// query string of the data fetcher's `/market/{id}`, which does the paging.
+fn market_query(params: &HashMap<String, String>) -> Result<String, StatusCode> {
+    fn param<T: std::str::FromStr>(
+        params: &HashMap<String, String>,
+        key: &str,
+    ) -> Result<Option<T>, StatusCode> {
+        params
+            .get(key)
+            .map(|val| val.parse().map_err(|_| StatusCode::BAD_REQUEST))
+            .transpose()
+    }
 
-    Ok(builder.body(body.into()).unwrap())
+    let limit = param(params, "limit")?.unwrap_or(DEFAULT_ORDER_LIMIT);
+    let offset: usize = param(params, "offset")?.unwrap_or(0);
+    let side = match (
+        param(params, "buy_only")?.unwrap_or(false),
+        param(params, "sell_only")?.unwrap_or(false),
+    ) {
+        (true, true) => return Err(StatusCode::BAD_REQUEST),
+        (true, false) => "&side=buy",
+        (false, true) => "&side=sell",
+        (false, false) => "",
+    };
+
+    Ok(format!("limit={limit}&offset={offset}{side}"))
 }
 
-async fn get_structures(
+/// A page of an item's orders: buy orders first, highest price first, then sell orders, lowest
+/// price first. `limit` defaults to [`DEFAULT_ORDER_LIMIT`].
+async fn get_orders(
     State(state): State<AppState>,
+    Path(id): Path<String>,
     Query(params): Query<HashMap<String, String>>,
 ) -> Result<Response, StatusCode> {
-    if !params.contains_key("id") {
-        return Err(StatusCode::BAD_REQUEST);
+    let path = format!("/market/{id}?{}", market_query(&params)?);
+    state.datafetch.proxy(&path).await
+}
+
+async fn get_update_time(State(state): State<AppState>) -> Result<Response, StatusCode> {
+    state.datafetch.proxy("/refresh_intervals").await
+}
+
+/// Params of `/universe/struct_names/`. A missing or non-numeric `id` is rejected by the extractor.
+#[derive(Debug, Deserialize)]
+struct StructureQuery {
+    id: u64,
+}
+
+/// The status to answer with when looking up `id` on ESI failed: 404 if ESI doesn't know it,
+/// 502 for anything else.
+fn lookup_failed(id: u64, err: impl std::fmt::Display, status: Option<StatusCode>) -> StatusCode {
+    tracing::error!("Failed to look up location {id}: {err}");
+    match status {
+        Some(StatusCode::NOT_FOUND) => StatusCode::NOT_FOUND,
+        _ => StatusCode::BAD_GATEWAY,
     }
+}
 
-    let id: u64 = params
-        .get("id")
-        .ok_or(StatusCode::BAD_REQUEST)?
-        .parse()
-        .map_err(|_| StatusCode::BAD_REQUEST)?;
-
-    if let Ok(station_id) = StationID::try_from(id) {
-        if station_id.get() < 64_000_000 {
-            let station = state.stations.get_station(station_id).await.unwrap();
-            let builder = Response::builder().status(StatusCode::OK);
-
-            Ok(builder
-                .body(serde_json::to_string(&station).unwrap().into())
-                .unwrap())
-        } else {
-            let structure;
+/// Reads one of the credentials needed to look up public structures.
+fn credential(name: &str) -> Result<String, StatusCode> {
+    env::var(name).map_err(|_| {
+        tracing::error!("{name} isn't set, can't look up public structures");
+        StatusCode::INTERNAL_SERVER_ERROR
+    })
+}
 
-            if state.public_structs.read().await.contains(&station_id) {
-                dotenvy::dotenv().unwrap();
+/// Makes sure `esi_client` holds a valid token for public structure lookups, refreshing it
+/// if it is missing or expired.
+async fn ensure_auth_tok(esi_client: &RwLock<ESIClient>) -> Result<(), StatusCode> {
+    // Check if auth token is valid with read lock first
+    if esi_client.read().await.auth_tok_valid().await {
+        return Ok(());
+    }
 
-                // Check if auth token is valid with read lock first
-                let auth_valid = {
-                    let esi_client = state.esi_client.read().await;
-                    esi_client.auth_tok_valid().await
-                };
+    // Only acquire write lock if we need to update the token, and check again once it's ours,
+    // since whoever held it before may have just refreshed the token
+    let mut esi_client = esi_client.write().await;
+    if esi_client.auth_tok_valid().await {
+        return Ok(());
+    }
+    esi_client
+        .load_auth_tok(
+            credential("PUB_STRUCT_ESI_REFRESH")?,
+            credential("CLIENT_ID")?,
+            credential("CLIENT_SECRET")?,
+        )
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to refresh the ESI token: {err}");
+            StatusCode::BAD_GATEWAY
+        })
+}
+
+/// How often [`refresh_auth_tok`] checks the public structure token.
+const TOKEN_CHECK_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keeps the public structure token fresh in the background, so `/readyz` only has to look at
+/// it. Failed refreshes are logged by [`ensure_auth_tok`] and tried again on the next check.
+async fn refresh_auth_tok(esi_client: Arc<RwLock<ESIClient>>) {
+    let mut checks = tokio::time::interval(TOKEN_CHECK_INTERVAL);
+    loop {
+        checks.tick().await;
+        let _ = ensure_auth_tok(&esi_client).await;
+    }
+}
 
-                // Only acquire write lock if we need to update the token
-                if !auth_valid {
-                    let mut esi_client = state.esi_client.write().await;
-                    esi_client
-                        .load_auth_tok(
-                            env::var("PUB_STRUCT_ESI_REFRESH").unwrap(),
-                            env::var("CLIENT_ID").unwrap(),
-                            env::var("CLIENT_SECRET").unwrap(),
-                        )
-                        .await
-                        .unwrap();
-                }
+async fn get_structures(
+    State(state): State<AppState>,
+    Query(StructureQuery { id }): Query<StructureQuery>,
+) -> Result<Response, StatusCode> {
+    // IDs outside both the station and the structure range aren't locations at all
+    match LocationId::try_from(id).map_err(|_| StatusCode::BAD_REQUEST)? {
+        LocationId::Station(station_id) => {
+            let station = state
+                .stations
+                .get_station(station_id)
+                .await
+                .map_err(|err| lookup_failed(id, &err, err.status()))?;
+
+            Ok(Json(station).into_response())
+        }
+        LocationId::Structure(station_id) => {
+            let structure;
+
+            if state.public_structs.read().await.contains(&station_id) {
+                ensure_auth_tok(&state.esi_client).await?;
 
                 let esi_client = state.esi_client.read().await;
 
                 // we just depend on http cacache for this one
                 let req = esi_client
-                    .esi_get(&format!("/universe/structures/{}/", station_id.get()))
+                    .esi_get(&format!("/universe/structures/{station_id}/"))
                     .await
-                    .unwrap()
+                    .map_err(|err| lookup_failed(id, &err, err.status()))?
                     .json::<universe::StructureAPIResponse>()
                     .await
-                    .unwrap();
+                    .map_err(|err| lookup_failed(id, err, None))?;
 
                 structure = universe::Structure {
                     id: station_id,
@@ -199,13 +316,204 @@ async fn get_structures(
                 };
             }
 
-            let builder = Response::builder().status(StatusCode::OK);
-
-            Ok(builder
-                .body(serde_json::to_string(&structure).unwrap().into())
-                .unwrap())
+            Ok(Json(structure).into_response())
         }
-    } else {
+    }
+}
+
+/// Most local item matches `/universe/search` returns.
+const SEARCH_LIMIT: usize = 50;
+
+/// Params of `/universe/search`, `category` narrows the results to one kind of entity.
+#[derive(Debug, Deserialize)]
+struct SearchQuery {
+    q: String,
+    category: Option<NameCategory>,
+}
+
+/// Finds `{id, name}` pairs for `q`. Types are first looked up by name prefix among the cached
+/// items, anything else is resolved by exact name through ESI's `/universe/ids/`.
+async fn search(
+    State(state): State<AppState>,
+    Query(SearchQuery { q, category }): Query<SearchQuery>,
+) -> Result<Json<Vec<NamedId>>, StatusCode> {
+    let q = q.trim();
+    if q.is_empty() || category == Some(NameCategory::Unknown) {
         return Err(StatusCode::BAD_REQUEST);
     }
+
+    if matches!(category, None | Some(NameCategory::InventoryType)) {
+        let local: Vec<NamedId> = state
+            .items
+            .search(q)
+            .into_iter()
+            .take(SEARCH_LIMIT)
+            .map(|(id, name)| NamedId {
+                id: id.get().into(),
+                name,
+            })
+            .collect();
+        if !local.is_empty() {
+            return Ok(Json(local));
+        }
+    }
+
+    let resolved = state
+        .esi_client
+        .read()
+        .await
+        .resolve_ids(&[q])
+        .await
+        .map_err(|err| {
+            tracing::error!("Failed to resolve {q:?}: {err}");
+            StatusCode::BAD_GATEWAY
+        })?;
+
+    Ok(Json(match category {
+        Some(category) => resolved.in_category(category).to_vec(),
+        None => resolved.into_all(),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use esi::{ResponseCache, universe::FileStore};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, method, path},
+    };
+
+    fn params(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(key, val)| (String::from(*key), String::from(*val)))
+            .collect()
+    }
+
+    #[test]
+    fn test_market_query_defaults_and_sides() {
+        assert_eq!(
+            market_query(&params(&[])).unwrap(),
+            format!("limit={DEFAULT_ORDER_LIMIT}&offset=0")
+        );
+        let sells = params(&[("limit", "50"), ("offset", "100"), ("sell_only", "true")]);
+        assert_eq!(
+            market_query(&sells).unwrap(),
+            "limit=50&offset=100&side=sell"
+        );
+    }
+
+    #[test]
+    fn test_readiness_needs_every_check() {
+        for (datafetch, token) in [(true, false), (false, true), (false, false)] {
+            let (status, Json(body)) = readiness(datafetch, token);
+            assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(body["ready"], false);
+            assert_eq!(body["esi_token_valid"], token);
+        }
+
+        let (status, Json(body)) = readiness(true, true);
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body["ready"], true);
+    }
+
+    #[test]
+    fn test_structure_query_requires_numeric_id() {
+        let query = |uri: &str| Query::<StructureQuery>::try_from_uri(&uri.parse().unwrap());
+
+        let jita_4_4 = query("/universe/struct_names/?id=60003760").unwrap();
+        assert_eq!(jita_4_4.id, 60003760);
+        assert!(query("/universe/struct_names/").is_err());
+        assert!(query("/universe/struct_names/?id=jita").is_err());
+        assert!(query("/universe/struct_names/?id=-1").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_structures_route_by_location_kind() {
+        // a JWT that expires in 2100, only its payload is looked at
+        let token = "header.eyJleHAiOjQxMDI0NDQ4MDB9.signature";
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/stations/60003760/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "station_id": 60003760, "system_id": 30000142, "name": "Jita IV - Moon 4",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/universe/structures/1042508032148/"))
+            .and(header("authorization", format!("Bearer {token}")))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "solar_system_id": 30000144, "type_id": 35834, "name": "Perimeter - Keepstar",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = || {
+            ESIClient::builder()
+                .component_name("backend_tests")
+                .cache_mode(ResponseCache::Memory)
+                .base_url(&server.uri())
+        };
+        let stations_client = Arc::new(client().build());
+        let dir = std::env::temp_dir().join(format!("backend-structures-{}", std::process::id()));
+        let stations = Stations::with_store(stations_client.clone(), FileStore::new(&dir));
+        let public = StationID::try_from(1042508032148).unwrap();
+        let state = AppState {
+            esi_client: Arc::new(RwLock::new(client().auth_token(token).build())),
+            stations: Arc::new(stations),
+            items: Arc::new(Items::new(stations_client)),
+            public_structs: Arc::new(RwLock::new(HashSet::from([public]))),
+            datafetch: Arc::new(DataFetchClient::from_env()),
+        };
+        let lookup = |id: u64| {
+            let state = state.clone();
+            async move {
+                let response = get_structures(State(state), Query(StructureQuery { id })).await?;
+                let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                Ok::<_, StatusCode>(serde_json::from_slice::<serde_json::Value>(&body).unwrap())
+            }
+        };
+
+        let station = lookup(60003760).await.unwrap();
+        assert_eq!(station["name"], "Jita IV - Moon 4");
+        let structure = lookup(1042508032148).await.unwrap();
+        assert_eq!(structure["name"], "Perimeter - Keepstar");
+        assert_eq!(structure["system_id"], 30000144);
+        // not on the allowlist, so ESI isn't asked
+        let private = lookup(1000000000001).await.unwrap();
+        assert_eq!(private["name"], "Unknown Private Structure");
+        assert_eq!(lookup(30000142).await, Err(StatusCode::BAD_REQUEST));
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn test_search_query_parses_category() {
+        let query = |uri: &str| Query::<SearchQuery>::try_from_uri(&uri.parse().unwrap());
+
+        let jita = query("/universe/search?q=Jita&category=solar_system").unwrap();
+        assert_eq!(jita.q, "Jita");
+        assert_eq!(jita.category, Some(NameCategory::SolarSystem));
+        assert_eq!(query("/universe/search?q=Trit").unwrap().category, None);
+        assert!(query("/universe/search?category=region").is_err());
+    }
+
+    #[test]
+    fn test_market_query_rejects_malformed_params() {
+        for pairs in [
+            [("limit", "-1")],
+            [("offset", "lots")],
+            [("buy_only", "yes")],
+        ] {
+            assert_eq!(market_query(&params(&pairs)), Err(StatusCode::BAD_REQUEST));
+        }
+        let both = params(&[("buy_only", "true"), ("sell_only", "true")]);
+        assert_eq!(market_query(&both), Err(StatusCode::BAD_REQUEST));
+    }
 }
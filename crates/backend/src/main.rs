@@ -1,9 +1,22 @@
 use axum::{Router};
+use tracing_subscriber::EnvFilter;
 
 use backend::market_data;
 
 #[tokio::main]
 async fn main() {
+    // log level is set through RUST_LOG, e.g. RUST_LOG=backend=debug
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
+    // the public structure credentials can come from a .env file, read once up front
+    if let Err(err) = dotenvy::dotenv() {
+        tracing::debug!("No .env file loaded: {err}");
+    }
+
     let api_routes = Router::new()
         .merge(market_data().await);
 
@@ -0,0 +1,205 @@
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{
+        Arc, RwLock,
+        atomic::{AtomicU64, Ordering},
+    },
+};
+
+use esi::{market::Market, universe::TypeID};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+/// A price level to be alerted about: the best sell of `type_id` dropping below `threshold`, or
+/// with `is_buy` its best buy rising above it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PriceThreshold {
+    pub type_id: TypeID,
+    pub is_buy: bool,
+    pub threshold: f64,
+}
+
+impl PriceThreshold {
+    /// Whether `price` is past the threshold.
+    fn crossed_by(&self, price: f64) -> bool {
+        if self.is_buy {
+            price > self.threshold
+        } else {
+            price < self.threshold
+        }
+    }
+}
+
+/// Broadcast by [`AlertRegistry`] when a best price crosses one of its thresholds.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct AlertEvent {
+    pub alert_id: u64,
+    #[serde(flatten)]
+    pub alert: PriceThreshold,
+    /// The best price that crossed the threshold.
+    pub price: f64,
+}
+
+#[derive(Debug)]
+struct Subscription {
+    alert: PriceThreshold,
+    /// Whether the price was past the threshold at the last check, so each crossing only fires
+    /// once.
+    crossed: bool,
+}
+
+/// Registered price thresholds, checked against the merged book by
+/// [`crate::update_market_data`] for the items each refresh touched.
+///
+/// Like [`crate::PriceWatches`] thresholds are only checked on refresh boundaries. An alert
+/// fires when its price crosses the threshold and again only after it has gone back.
+#[derive(Clone, Debug)]
+pub struct AlertRegistry {
+    alerts: Arc<RwLock<HashMap<u64, Subscription>>>,
+    next_id: Arc<AtomicU64>,
+    events: broadcast::Sender<AlertEvent>,
+}
+
+impl AlertRegistry {
+    pub fn new(capacity: usize) -> Self {
+        AlertRegistry {
+            alerts: Arc::default(),
+            next_id: Arc::new(AtomicU64::new(1)),
+            events: broadcast::channel(capacity).0,
+        }
+    }
+
+    /// Starts checking `alert`, returning the ID its events carry.
+    pub fn register(&self, alert: PriceThreshold) -> u64 {
+        let alert_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        self.alerts.write().unwrap().insert(
+            alert_id,
+            Subscription {
+                alert,
+                crossed: false,
+            },
+        );
+        alert_id
+    }
+
+    /// Stops checking an alert, returning whether it was registered.
+    pub fn remove(&self, alert_id: u64) -> bool {
+        self.alerts.write().unwrap().remove(&alert_id).is_some()
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<AlertEvent> {
+        self.events.subscribe()
+    }
+
+    /// Checks the alerts on `touched` items against the best prices in `market`.
+    pub(crate) fn check(&self, market: &Market, touched: &HashSet<TypeID>) {
+        let mut alerts = self.alerts.write().unwrap();
+
+        for (&alert_id, subscription) in alerts.iter_mut() {
+            let alert = subscription.alert;
+            if !touched.contains(&alert.type_id) {
+                continue;
+            }
+
+            let best = market.items.get(&alert.type_id).and_then(|book| {
+                let order = if alert.is_buy {
+                    book.best_buy()
+                } else {
+                    book.best_sell()
+                };
+                order.map(|order| order.price)
+            });
+            let crossed = best.is_some_and(|price| alert.crossed_by(price));
+
+            if let Some(price) = best
+                && crossed
+                && !subscription.crossed
+            {
+                tracing::info!(
+                    "Alert {alert_id}: best price of type {} is {price}, past {}",
+                    alert.type_id,
+                    alert.threshold
+                );
+                // failing to send just means nobody has subscribed
+                let _ = self.events.send(AlertEvent {
+                    alert_id,
+                    alert,
+                    price,
+                });
+            }
+            subscription.crossed = crossed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{TimeDelta, Utc};
+    use esi::{
+        market::{MarketOrderRange, Order, OrderBook},
+        universe::{StationID, SystemID},
+    };
+
+    fn market_with_sell(type_id: TypeID, price: f64) -> Market {
+        let issued = Utc::now();
+        let market = Market::new();
+        let mut book = OrderBook::new(type_id);
+        let order = Order {
+            id: 1,
+            is_buy_order: false,
+            price,
+            issued,
+            expiry: issued + TimeDelta::days(90),
+            location_id: StationID::try_from(60_003_760).unwrap(),
+            system_id: SystemID::try_from(30_000_142).unwrap(),
+            min_volume: 1,
+            range: MarketOrderRange::Region,
+            volume_remain: 100,
+            volume_total: 100,
+        };
+        book.orders.insert(order.id, order);
+        market.items.insert(type_id, book);
+        market
+    }
+
+    #[test]
+    fn test_alert_fires_once_per_crossing() {
+        let registry = AlertRegistry::new(8);
+        let mut events = registry.subscribe();
+        let tritanium = TypeID::from(34);
+        let touched = HashSet::from([tritanium]);
+        let alert_id = registry.register(PriceThreshold {
+            type_id: tritanium,
+            is_buy: false,
+            threshold: 5.0,
+        });
+        let buy_alert = registry.register(PriceThreshold {
+            type_id: tritanium,
+            is_buy: true,
+            threshold: 1.0,
+        });
+
+        registry.check(&market_with_sell(tritanium, 5.5), &touched);
+        assert!(events.try_recv().is_err());
+
+        registry.check(&market_with_sell(tritanium, 4.9), &touched);
+        let event = events.try_recv().unwrap();
+        assert_eq!(event.alert_id, alert_id);
+        assert_eq!(event.price, 4.9);
+
+        // still below, and untouched items aren't checked
+        registry.check(&market_with_sell(tritanium, 4.8), &touched);
+        registry.check(&market_with_sell(tritanium, 5.5), &HashSet::new());
+        assert!(events.try_recv().is_err());
+
+        // back above re-arms it
+        registry.check(&market_with_sell(tritanium, 5.5), &touched);
+        registry.check(&market_with_sell(tritanium, 4.0), &touched);
+        assert_eq!(events.try_recv().unwrap().price, 4.0);
+
+        assert!(registry.remove(alert_id));
+        assert!(!registry.remove(alert_id));
+        assert!(registry.remove(buy_alert));
+    }
+}
@@ -0,0 +1,173 @@
+use std::{collections::HashMap, time::Duration};
+
+use esi::{
+    market::{MarketDiff, Order},
+    universe::{RegionID, TypeID},
+};
+use futures::{Stream, StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpStream, time};
+use tokio_tungstenite::{MaybeTlsStream, WebSocketStream, connect_async, tungstenite::Message};
+
+/// The changes to one item's orders from a single region refresh, as sent over
+/// `/ws/market/{id}`.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MarketUpdate {
+    pub type_id: TypeID,
+    pub region_id: RegionID,
+    pub new: Vec<Order>,
+    pub modified: Vec<Order>,
+    /// IDs of the orders that were filled, cancelled or expired.
+    pub removed: Vec<u64>,
+}
+
+impl MarketUpdate {
+    /// The part of a region's `diff` that touches `type_id`, or `None` if it doesn't.
+    pub fn from_diff(region_id: RegionID, type_id: TypeID, diff: &MarketDiff) -> Option<Self> {
+        let orders =
+            |map: &HashMap<TypeID, Vec<Order>>| map.get(&type_id).cloned().unwrap_or_default();
+
+        let update = MarketUpdate {
+            type_id,
+            region_id,
+            new: orders(&diff.new),
+            modified: orders(&diff.modified),
+            removed: diff.removed.get(&type_id).cloned().unwrap_or_default(),
+        };
+        let touched =
+            !(update.new.is_empty() && update.modified.is_empty() && update.removed.is_empty());
+
+        touched.then_some(update)
+    }
+}
+
+type Socket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// Delay before the `attempt`th reconnect in a row. Starts at 1 second and doubles with each
+/// attempt, up to 1 minute.
+fn reconnect_backoff(attempt: u32) -> Duration {
+    const BASE_DELAY: Duration = Duration::from_secs(1);
+    const MAX_DELAY: Duration = Duration::from_secs(60);
+
+    BASE_DELAY
+        .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+        .min(MAX_DELAY)
+}
+
+/// Connects to a data fetcher's `/ws/market/{id}` feed at `url` and yields its updates.
+///
+/// The stream never ends: whenever the connection drops or can't be established it reconnects,
+/// backing off exponentially. Messages that aren't a [`MarketUpdate`] are skipped.
+pub fn connect_feed(url: impl Into<String>) -> impl Stream<Item = MarketUpdate> {
+    let state: (String, Option<Socket>, u32) = (url.into(), None, 0);
+
+    stream::unfold(state, |(url, mut socket, mut attempt)| async move {
+        loop {
+            let ws = match socket.as_mut() {
+                Some(ws) => ws,
+                None => {
+                    if attempt > 0 {
+                        time::sleep(reconnect_backoff(attempt)).await;
+                    }
+
+                    match connect_async(url.as_str()).await {
+                        Ok((ws, _)) => {
+                            attempt = 0;
+                            socket.insert(ws)
+                        }
+                        Err(err) => {
+                            attempt = attempt.saturating_add(1);
+                            tracing::warn!(
+                                "Failed to connect to {url}: {err}, retrying in {} secs",
+                                reconnect_backoff(attempt).as_secs()
+                            );
+                            continue;
+                        }
+                    }
+                }
+            };
+
+            match ws.next().await {
+                Some(Ok(Message::Text(text))) => {
+                    match serde_json::from_str::<MarketUpdate>(text.as_str()) {
+                        Ok(update) => return Some((update, (url, socket, attempt))),
+                        Err(err) => tracing::warn!("Skipping malformed update: {err}"),
+                    }
+                }
+                // pings are answered by tungstenite, a close is followed by the end of the stream
+                Some(Ok(_)) => {}
+                Some(Err(err)) => {
+                    tracing::warn!("Connection to {url} failed: {err}, reconnecting");
+                    socket = None;
+                    attempt = 1;
+                }
+                None => {
+                    tracing::info!("Connection to {url} closed, reconnecting");
+                    socket = None;
+                    attempt = 1;
+                }
+            }
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        Router,
+        extract::{Path, WebSocketUpgrade, ws},
+        response::Response,
+        routing::get,
+    };
+
+    fn make_update(type_id: TypeID) -> MarketUpdate {
+        MarketUpdate {
+            type_id,
+            region_id: RegionID::try_from(10000002).unwrap(),
+            new: Vec::new(),
+            modified: Vec::new(),
+            removed: vec![1, 2, 3],
+        }
+    }
+
+    /// Sends a malformed message and a single update for the requested item, then hangs up.
+    async fn send_one_update(Path(id): Path<u32>, upgrade: WebSocketUpgrade) -> Response {
+        upgrade.on_upgrade(move |mut socket| async move {
+            let update = serde_json::to_string(&make_update(id.into())).unwrap();
+            for text in [String::from("not json"), update] {
+                socket.send(ws::Message::Text(text.into())).await.unwrap();
+            }
+        })
+    }
+
+    async fn serve_one_update_per_connection() -> String {
+        let app = Router::new().route("/ws/market/{id}", get(send_one_update));
+
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        format!("ws://{addr}/ws/market/34")
+    }
+
+    #[test]
+    fn test_reconnect_backoff_doubles_up_to_cap() {
+        assert_eq!(reconnect_backoff(1), Duration::from_secs(1));
+        assert_eq!(reconnect_backoff(4), Duration::from_secs(8));
+        assert_eq!(reconnect_backoff(u32::MAX), Duration::from_secs(60));
+    }
+
+    #[tokio::test]
+    async fn test_feed_reconnects_after_disconnect() {
+        let url = serve_one_update_per_connection().await;
+
+        let updates: Vec<MarketUpdate> =
+            time::timeout(Duration::from_secs(10), connect_feed(url).take(2).collect())
+                .await
+                .expect("feed did not reconnect");
+
+        let update = make_update(34.into());
+        assert_eq!(updates, vec![update.clone(), update]);
+    }
+}
@@ -1,131 +1,695 @@
-use std::{sync::Arc, time::Duration};
+use std::{
+    collections::{HashSet, VecDeque},
+    path::PathBuf,
+    str::FromStr,
+    sync::{Arc, RwLock},
+    time::Duration,
+};
 
 use chrono::{DateTime, TimeDelta, Utc};
 use dashmap::DashMap;
 use esi::{
     ESIClient,
-    market::{Market, OrderBook},
-    universe::{Region, RegionID},
+    market::{Market, MarketDiff, OrderBook, PageCache, PageLimit},
+    universe::{Region, RegionID, TypeID},
 };
+use serde::Serialize;
 use tokio::{
+    io::AsyncWriteExt,
     sync::{
         Mutex,
         broadcast::{self, Receiver},
-        mpsc,
+        mpsc, watch,
     },
+    task::JoinSet,
     time,
 };
 
+pub mod alerts;
+pub mod feed;
+pub mod metrics;
 pub mod server;
 
-/// Message broadcast when a region is refreshed
+pub use alerts::{AlertEvent, AlertRegistry, PriceThreshold};
+pub use feed::{MarketUpdate, connect_feed};
+pub use metrics::{FetcherMetrics, record_refresh_events};
+
+/// Fired when a watched item's best sell price moves by more than its threshold.
+#[derive(Debug, Clone)]
+pub struct PriceAlert {
+    pub type_id: TypeID,
+    pub region_id: RegionID,
+    pub previous: f64,
+    pub current: f64,
+    pub change_pct: f64,
+}
+
+type PriceCallback = Arc<dyn Fn(&PriceAlert) + Send + Sync>;
+
+#[derive(Clone)]
+struct PriceWatch {
+    type_id: TypeID,
+    region_id: RegionID,
+    threshold_pct: f64,
+    callback: PriceCallback,
+}
+
+/// Registered best-sell price watches, checked by [`update_market_data`].
+///
+/// Watches are only evaluated on refresh boundaries: the new snapshot of a region is compared
+/// against the previous one, so moves that revert between two refreshes are never reported.
+#[derive(Clone, Default)]
+pub struct PriceWatches {
+    watches: Arc<RwLock<Vec<PriceWatch>>>,
+}
+
+impl PriceWatches {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Calls `callback` whenever the best sell price of `type_id` in `region_id` changes by more
+    /// than `threshold_pct` percent between two refreshes of that region.
+    pub fn watch_price(
+        &self,
+        type_id: TypeID,
+        region_id: RegionID,
+        threshold_pct: f64,
+        callback: impl Fn(&PriceAlert) + Send + Sync + 'static,
+    ) {
+        self.watches.write().unwrap().push(PriceWatch {
+            type_id,
+            region_id,
+            threshold_pct,
+            callback: Arc::new(callback),
+        });
+    }
+
+    fn check(&self, region_id: RegionID, previous: &Market, current: &Market) {
+        // callbacks run without the lock, so they can register watches of their own
+        let watches: Vec<PriceWatch> = {
+            let watches = self.watches.read().unwrap();
+            watches
+                .iter()
+                .filter(|watch| watch.region_id == region_id)
+                .cloned()
+                .collect()
+        };
+
+        for watch in watches {
+            let (Some(prev_price), Some(cur_price)) = (
+                best_sell_price(previous, watch.type_id),
+                best_sell_price(current, watch.type_id),
+            ) else {
+                continue;
+            };
+            // there's no percentage change from a zero price
+            if prev_price <= 0.0 {
+                continue;
+            }
+
+            let change_pct = (cur_price - prev_price) / prev_price * 100.0;
+            if change_pct.abs() > watch.threshold_pct {
+                (watch.callback)(&PriceAlert {
+                    type_id: watch.type_id,
+                    region_id,
+                    previous: prev_price,
+                    current: cur_price,
+                    change_pct,
+                });
+            }
+        }
+    }
+}
+
+fn best_sell_price(market: &Market, type_id: TypeID) -> Option<f64> {
+    market.items.get(&type_id).and_then(|book| {
+        book.orders
+            .values()
+            .filter(|order| !order.is_buy_order)
+            .map(|order| order.price)
+            .min_by(f64::total_cmp)
+    })
+}
+
+/// How many liquidity samples are kept per region: 24 hours at the usual 5 minute refresh.
+pub const LIQUIDITY_SAMPLES: usize = 288;
+
+/// Size of one region's order book at one refresh.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub struct LiquiditySample {
+    /// When ESI last updated the snapshot.
+    pub timestamp: DateTime<Utc>,
+    /// Sum of `price * volume_remain` over every buy and sell order.
+    pub book_value_isk: f64,
+    pub order_count: usize,
+}
+
+impl LiquiditySample {
+    pub fn from_market(market: &Market) -> Self {
+        let mut book_value_isk = 0.0;
+        let mut order_count = 0;
+        for book in market.items.iter() {
+            for order in book.orders.values() {
+                book_value_isk += order.price * f64::from(order.volume_remain);
+                order_count += 1;
+            }
+        }
+
+        LiquiditySample {
+            timestamp: market.last_modified,
+            book_value_isk,
+            order_count,
+        }
+    }
+}
+
+/// The last [`LIQUIDITY_SAMPLES`] liquidity samples of each region, recorded by
+/// [`update_market_data`].
+///
+/// Samples are only kept in memory, so the history starts over whenever the fetcher restarts.
+#[derive(Clone, Default)]
+pub struct LiquidityHistory {
+    samples: Arc<DashMap<RegionID, VecDeque<LiquiditySample>>>,
+}
+
+impl LiquidityHistory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a sample for `region_id`, dropping the oldest one once the window is full.
+    pub fn record(&self, region_id: RegionID, sample: LiquiditySample) {
+        let mut samples = self.samples.entry(region_id).or_default();
+        if samples.len() == LIQUIDITY_SAMPLES {
+            samples.pop_front();
+        }
+        samples.push_back(sample);
+    }
+
+    /// The recorded samples of `region_id`, oldest first.
+    pub fn samples(&self, region_id: RegionID) -> Option<Vec<LiquiditySample>> {
+        self.samples
+            .get(&region_id)
+            .map(|samples| samples.iter().copied().collect())
+    }
+}
+
+/// Message broadcast by [`update_market_data`] once a region's refresh has been applied.
 #[derive(Debug, Clone)]
 pub struct RegionRefreshEvent {
     pub id: RegionID,
     pub expires: DateTime<Utc>,
+    pub new_orders: usize,
+    pub modified_orders: usize,
+    pub removed_orders: usize,
+    /// Orders in the region after the refresh.
+    pub total_orders: usize,
+    /// The changes the refresh applied, shared by every subscriber.
+    pub diff: Arc<MarketDiff>,
+}
+
+/// Message broadcast by [`update_market_data`] for each item a region's refresh changed. The
+/// same update `/ws/market/{id}` sends its clients.
+pub type ItemDiffEvent = MarketUpdate;
+
+/// The channels [`update_market_data`] broadcasts on.
+#[derive(Clone, Debug)]
+pub struct UpdateBroadcasts {
+    pub regions: broadcast::Sender<RegionRefreshEvent>,
+    /// Sees every item of every refresh, so it needs a lot more room than `regions`.
+    pub items: broadcast::Sender<ItemDiffEvent>,
+    /// Broadcasts an [`AlertEvent`] whenever a refresh crosses one of its thresholds.
+    pub alerts: AlertRegistry,
+}
+
+impl UpdateBroadcasts {
+    pub fn new(region_capacity: usize, item_capacity: usize) -> Self {
+        UpdateBroadcasts {
+            regions: broadcast::channel(region_capacity).0,
+            items: broadcast::channel(item_capacity).0,
+            alerts: AlertRegistry::new(region_capacity),
+        }
+    }
+}
+
+/// How [`refresh_region_data`] paces its fetches.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FetcherConfig {
+    /// Delay before retrying a failed fetch, doubling with each failure in a row.
+    pub error_retry_delay: Duration,
+    /// How long past a snapshot's `expires` to wait before fetching the next one.
+    pub expiry_padding: Duration,
+    /// Upper bound of the random delay added to each refresh.
+    pub refresh_jitter: Duration,
+    /// Most pages of orders fetched per region, see [`PageLimit`].
+    pub max_pages: usize,
+    /// Reuse order pages ESI reports unchanged instead of downloading them again, see
+    /// [`PageCache`]. The client needs [`ESIClient::enable_etags`] for this to do anything.
+    pub reuse_unchanged_pages: bool,
+}
+
+impl Default for FetcherConfig {
+    fn default() -> Self {
+        FetcherConfig {
+            error_retry_delay: Duration::from_secs(15),
+            expiry_padding: Duration::from_secs(1),
+            refresh_jitter: Duration::from_secs(30),
+            max_pages: PageLimit::default().max_pages,
+            reuse_unchanged_pages: false,
+        }
+    }
+}
+
+impl FetcherConfig {
+    /// Reads the config from `ERROR_RETRY_DELAY_SECS`, `EXPIRY_PADDING_SECS`,
+    /// `REFRESH_JITTER_SECS`, `MAX_PAGES` and `REUSE_UNCHANGED_PAGES`, keeping the default for
+    /// any that are unset or unreadable.
+    pub fn from_env() -> Self {
+        fn var<T: FromStr>(name: &str) -> Option<T> {
+            std::env::var(name)
+                .ok()
+                .and_then(|value| value.parse().ok())
+        }
+
+        let default = FetcherConfig::default();
+        FetcherConfig {
+            error_retry_delay: var("ERROR_RETRY_DELAY_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.error_retry_delay),
+            expiry_padding: var("EXPIRY_PADDING_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.expiry_padding),
+            refresh_jitter: var("REFRESH_JITTER_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(default.refresh_jitter),
+            max_pages: var("MAX_PAGES").unwrap_or(default.max_pages),
+            reuse_unchanged_pages: var("REUSE_UNCHANGED_PAGES")
+                .unwrap_or(default.reuse_unchanged_pages),
+        }
+    }
+}
+
+/// Appends every computed [`MarketDiff`] to an NDJSON file, one line per region refresh.
+///
+/// Once the file would grow past `max_bytes` it is renamed to `<path>.1`, replacing any previous
+/// rotation, and a fresh file is started.
+#[derive(Clone, Debug)]
+pub struct DiffExport {
+    path: PathBuf,
+    max_bytes: u64,
+    // serializes appends and rotation between region tasks
+    lock: Arc<Mutex<()>>,
+}
+
+/// One line of the diff export.
+#[derive(Serialize)]
+struct DiffRecord<'a> {
+    region_id: RegionID,
+    timestamp: DateTime<Utc>,
+    #[serde(flatten)]
+    diff: &'a MarketDiff,
+}
+
+impl DiffExport {
+    pub const DEFAULT_MAX_BYTES: u64 = 256 * 1024 * 1024;
+
+    pub fn new(path: impl Into<PathBuf>, max_bytes: u64) -> Self {
+        DiffExport {
+            path: path.into(),
+            max_bytes,
+            lock: Arc::new(Mutex::new(())),
+        }
+    }
+
+    /// Reads the export path from `DIFF_EXPORT_PATH` and the rotation size from
+    /// `DIFF_EXPORT_MAX_BYTES`. Returns `None`, disabling the export, if no path is set.
+    pub fn from_env() -> Option<Self> {
+        let path = std::env::var("DIFF_EXPORT_PATH").ok()?;
+        let max_bytes = std::env::var("DIFF_EXPORT_MAX_BYTES")
+            .ok()
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(Self::DEFAULT_MAX_BYTES);
+
+        Some(DiffExport::new(path, max_bytes))
+    }
+
+    /// Serializes `diff` now and appends it in the background, so a slow disk never holds up
+    /// applying the refresh.
+    pub fn export(&self, region_id: RegionID, timestamp: DateTime<Utc>, diff: &MarketDiff) {
+        let record = DiffRecord {
+            region_id,
+            timestamp,
+            diff,
+        };
+        let mut line = match serde_json::to_vec(&record) {
+            Ok(line) => line,
+            Err(err) => {
+                tracing::error!("Failed to serialize diff for region {region_id}: {err}");
+                return;
+            }
+        };
+        line.push(b'\n');
+
+        let export = self.clone();
+        tokio::spawn(async move {
+            if let Err(err) = export.append(&line).await {
+                tracing::error!(
+                    "Failed to write diff export to {}: {err}",
+                    export.path.display()
+                );
+            }
+        });
+    }
+
+    async fn append(&self, line: &[u8]) -> std::io::Result<()> {
+        let _guard = self.lock.lock().await;
+
+        let current_len = match tokio::fs::metadata(&self.path).await {
+            Ok(metadata) => metadata.len(),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        if current_len > 0 && current_len + line.len() as u64 > self.max_bytes {
+            let mut rotated = self.path.clone().into_os_string();
+            rotated.push(".1");
+            tokio::fs::rename(&self.path, rotated).await?;
+        }
+
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .await?;
+        file.write_all(line).await?;
+        file.flush().await
+    }
+}
+
+/// Keeps the latest snapshot of each region on disk as `<dir>/<region_id>.json`, so a restart
+/// can pick up from them instead of refetching every region.
+#[derive(Clone, Debug)]
+pub struct MarketSnapshots {
+    dir: PathBuf,
+}
+
+impl MarketSnapshots {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        MarketSnapshots { dir: dir.into() }
+    }
+
+    /// Reads the snapshot directory from `MARKET_SNAPSHOT_DIR`. Returns `None`, disabling
+    /// snapshots, if it isn't set.
+    pub fn from_env() -> Option<Self> {
+        std::env::var("MARKET_SNAPSHOT_DIR")
+            .ok()
+            .map(MarketSnapshots::new)
+    }
+
+    fn path(&self, region_id: RegionID) -> PathBuf {
+        self.dir.join(format!("{region_id}.json"))
+    }
+
+    /// Loads the saved snapshot of `region_id`, if there is one ESI hasn't expired yet. An expired
+    /// snapshot would be refetched straight away, so it isn't worth restoring.
+    pub async fn load(&self, region_id: RegionID) -> Option<Market> {
+        let path = self.path(region_id);
+        if !tokio::fs::try_exists(&path).await.unwrap_or(false) {
+            return None;
+        }
+
+        match Market::load_snapshot(&path).await {
+            Ok(market) if market.expires > Utc::now() => Some(market),
+            Ok(market) => {
+                tracing::debug!(
+                    "Ignoring snapshot of region {region_id}, expired at {}",
+                    market.expires
+                );
+                None
+            }
+            Err(err) => {
+                tracing::warn!("Ignoring snapshot of region {region_id}: {err:#}");
+                None
+            }
+        }
+    }
+
+    pub async fn save(&self, region_id: RegionID, market: &Market) {
+        if let Err(err) = market.save_snapshot(&self.path(region_id)).await {
+            tracing::error!("Failed to save snapshot of region {region_id}: {err:#}");
+        }
+    }
+}
+
+/// Delay before retrying a region that has failed `consecutive_errors` times in a row. Starts at
+/// `base_delay` and doubles with each failure, up to 10 minutes.
+fn error_backoff(consecutive_errors: u32, base_delay: Duration) -> Duration {
+    const MAX_DELAY: Duration = Duration::from_secs(10 * 60);
+
+    base_delay
+        .saturating_mul(2u32.saturating_pow(consecutive_errors.saturating_sub(1)))
+        .min(MAX_DELAY)
+}
+
+/// Resolves once the process receives SIGINT (Ctrl+C) or, on unix, SIGTERM.
+pub async fn shutdown_signal() {
+    let ctrl_c = async {
+        if let Err(err) = tokio::signal::ctrl_c().await {
+            tracing::error!("Failed to listen for Ctrl+C: {err}");
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{SignalKind, signal};
+        match signal(SignalKind::terminate()) {
+            Ok(mut sigterm) => {
+                sigterm.recv().await;
+            }
+            Err(err) => {
+                tracing::error!("Failed to listen for SIGTERM: {err}");
+                std::future::pending::<()>().await;
+            }
+        }
+    };
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {}
+        _ = terminate => {}
+    }
+    tracing::info!("Shutdown requested");
+}
+
+/// Resolves once `shutdown` is set to `true`, or its sender is gone.
+pub async fn shutdown_requested(shutdown: &mut watch::Receiver<bool>) {
+    let _ = shutdown.wait_for(|stop| *stop).await;
 }
 
 /// This function updates the data for a region whenever it expires.
+///
+/// A random delay of up to `config.refresh_jitter` is added after each expiry so regions sharing
+/// the same ESI cache boundary don't all refresh at once. The jitter only ever delays a refresh.
+///
+/// With `resume_at`, typically the `expires` of a snapshot restored from [`MarketSnapshots`], the
+/// first fetch waits until then instead of starting right away.
+///
+/// Returns once `shutdown` fires. A fetch still in flight at that point is dropped, snapshots
+/// already sent on `channel` are still applied.
+#[tracing::instrument(skip_all, fields(region = %region.name))]
 pub async fn refresh_region_data(
     region: Region,
     client: Arc<ESIClient>,
     channel: mpsc::Sender<(Market, Region)>,
-    broadcast_tx: broadcast::Sender<RegionRefreshEvent>,
+    config: FetcherConfig,
+    metrics: FetcherMetrics,
+    resume_at: Option<DateTime<Utc>>,
+    mut shutdown: watch::Receiver<bool>,
 ) {
-    const ERROR_RETRY_DELAY: Duration = Duration::from_secs(15);
+    if let Some(resume_at) = resume_at {
+        let padding = TimeDelta::from_std(config.expiry_padding).unwrap_or_default();
+        let wait = (resume_at - Utc::now() + padding)
+            .to_std()
+            .unwrap_or_default();
+        tracing::info!(
+            "Resuming from snapshot, next fetch in {} secs",
+            wait.as_secs()
+        );
+
+        tokio::select! {
+            _ = time::sleep(wait) => {}
+            _ = shutdown_requested(&mut shutdown) => return,
+        }
+    }
+
+    let mut consecutive_errors: u32 = 0;
+    let page_limit = PageLimit {
+        max_pages: config.max_pages,
+        ..PageLimit::default()
+    };
+    let page_cache = config.reuse_unchanged_pages.then(PageCache::new);
 
     loop {
-        let data = Market::fetch_region(&region, client.clone()).await;
+        let fetch = Market::fetch_region_with_cache(
+            &region,
+            client.clone(),
+            page_limit,
+            page_cache.as_ref(),
+        );
+        let started = time::Instant::now();
+        let data = tokio::select! {
+            data = fetch => data,
+            _ = shutdown_requested(&mut shutdown) => break,
+        };
+        metrics.record_fetch(
+            region.id,
+            started.elapsed(),
+            data.is_ok(),
+            client.error_budget(),
+        );
 
         match data {
             Ok(data) => {
+                consecutive_errors = 0;
+
                 // Extract the expiry time before sending the data
                 let expiry_time = data.expires;
 
-                let sleep_dur = (expiry_time - Utc::now() + TimeDelta::new(1, 0).unwrap())
+                let max_jitter = config.refresh_jitter.as_millis();
+                let jitter = Duration::from_millis(rand::random_range(
+                    0..=max_jitter.try_into().unwrap_or(u64::MAX),
+                ));
+                let padding = TimeDelta::from_std(config.expiry_padding).unwrap_or_default();
+                let sleep_dur = (expiry_time - Utc::now() + padding)
                     .to_std()
-                    .unwrap_or(std::time::Duration::from_secs(30));
+                    .unwrap_or(std::time::Duration::from_secs(30))
+                    + jitter;
 
-                println!(
-                    "Region {} sleeping for {} secs",
-                    region.name.clone(),
-                    sleep_dur.as_secs()
+                tracing::info!(
+                    "Sleeping for {} secs (jitter {}ms)",
+                    sleep_dur.as_secs(),
+                    jitter.as_millis()
                 );
 
                 // Send the market data through the channel for processing
-                if let Err(_) = channel.send((data, region.clone())).await {
-                    eprintln!("Failed to send market data for region {}", region.name);
+                if channel.send((data, region.clone())).await.is_err() {
+                    tracing::error!("Failed to send market data, stopping refreshes");
                     break; // Exit if the receiver is dropped
                 }
 
-                // Broadcast the region refresh event
-                let refresh_event = RegionRefreshEvent {
-                    id: region.id,
-                    expires: expiry_time,
-                };
-
-                // failing to send broadcasts just means nobody has subscribed
-                if let Err(_) = broadcast_tx.send(refresh_event) {}
-
-                time::sleep(sleep_dur).await;
+                tokio::select! {
+                    _ = time::sleep(sleep_dur) => {}
+                    _ = shutdown_requested(&mut shutdown) => break,
+                }
             }
             Err(err) => {
-                eprintln!("{:?}", err);
+                consecutive_errors = consecutive_errors.saturating_add(1);
+                let backoff = error_backoff(consecutive_errors, config.error_retry_delay);
+
+                tracing::error!(
+                    "Fetch of {} failed {consecutive_errors} times in a row, retrying in {} secs: {err:#}",
+                    region.name,
+                    backoff.as_secs()
+                );
 
-                time::sleep(ERROR_RETRY_DELAY).await;
+                tokio::select! {
+                    _ = time::sleep(backoff) => {}
+                    _ = shutdown_requested(&mut shutdown) => break,
+                }
             }
         }
     }
+
+    tracing::debug!("Stopped refreshing {}", region.name);
 }
 
+/// Applies each region snapshot received on `rx` to `book`.
+///
+/// Returns once every sender of `rx` is gone and all received snapshots have been applied, so
+/// awaiting it after stopping the [`refresh_region_data`] loops drains the channel.
+///
+/// With `snapshots`, each region's latest snapshot is also saved to disk once applied.
 pub async fn update_market_data(
     book: Arc<Mutex<Market>>,
     mut rx: mpsc::Receiver<(Market, Region)>,
+    watches: PriceWatches,
+    liquidity: LiquidityHistory,
+    broadcasts: UpdateBroadcasts,
+    diff_export: Option<DiffExport>,
+    snapshots: Option<MarketSnapshots>,
 ) {
-    let regions: Arc<DashMap<Region, Market>> = Arc::new(DashMap::new());
+    let regions: Arc<DashMap<RegionID, Market>> = Arc::new(DashMap::new());
+    let mut tasks = JoinSet::new();
 
     while let Some((new_market, region)) = rx.recv().await {
+        // reap finished updates so the set doesn't grow for the life of the process
+        while tasks.try_join_next().is_some() {}
+
         let regions = regions.clone();
         let book = book.clone();
+        let watches = watches.clone();
+        let liquidity = liquidity.clone();
+        let broadcasts = broadcasts.clone();
+        let diff_export = diff_export.clone();
+        let snapshots = snapshots.clone();
 
-        tokio::spawn(async move {
+        tasks.spawn(async move {
             // Store timestamps from the new market
             let new_last_modified = new_market.last_modified;
             let new_expires = new_market.expires;
 
-            println!("Processing market update for region {}", region.name);
+            tracing::debug!("Processing market update for region {}", region.name);
+
+            let sample = LiquiditySample::from_market(&new_market);
+            liquidity.record(region.id, sample);
 
             // Calculate the diff between previous and new market data
-            let diff = match regions.get(&region) {
+            let (diff, summary) = match regions.get(&region.id) {
                 Some(prev_market_ref) => {
-                    println!("Computing delta for region {} (update)", region.name);
-                    prev_market_ref.delta(&new_market)
+                    tracing::debug!("Computing delta for region {} (update)", region.name);
+                    let diff = prev_market_ref.delta(&new_market);
+                    let summary = diff.summary(&prev_market_ref);
+                    (diff, summary)
                 }
                 None => {
                     // First time seeing this region - everything is "new"
                     let empty_market = Market::new();
-                    println!("Computing delta for region {} (first time)", region.name);
-                    empty_market.delta(&new_market)
+                    tracing::debug!("Computing delta for region {} (first time)", region.name);
+                    let diff = empty_market.delta(&new_market);
+                    let summary = diff.summary(&empty_market);
+                    (diff, summary)
                 }
             };
 
+            if let Some(diff_export) = &diff_export {
+                diff_export.export(region.id, new_last_modified, &diff);
+            }
+            let diff = Arc::new(diff);
+
+            let touched_items: HashSet<TypeID> = diff
+                .new
+                .keys()
+                .chain(diff.modified.keys())
+                .chain(diff.removed.keys())
+                .copied()
+                .collect();
+
             // Apply the diff to the global market book
             let mut global_book = book.lock().await;
 
             // Process removed orders
-            let mut removed_ordercount = 0;
-            for (item_type, removed_order_ids) in diff.removed {
-                if let Some(mut order_book) = global_book.items.get_mut(&item_type) {
+            for (item_type, removed_order_ids) in &diff.removed {
+                if let Some(mut order_book) = global_book.items.get_mut(item_type) {
                     for order_id in removed_order_ids {
-                        removed_ordercount += 1;
-                        order_book.orders.remove(&order_id);
+                        order_book.orders.remove(order_id);
                     }
                 }
             }
 
             // Process new orders
-            let mut new_ordercount = 0;
-            for (item_type, new_orders) in diff.new {
+            for (&item_type, new_orders) in &diff.new {
                 // Ensure the orderbook exists for this item type
                 if !global_book.items.contains_key(&item_type) {
                     global_book
@@ -135,15 +699,13 @@ pub async fn update_market_data(
 
                 if let Some(mut order_book) = global_book.items.get_mut(&item_type) {
                     for order in new_orders {
-                        order_book.orders.insert(order.id, order);
-                        new_ordercount += 1;
+                        order_book.orders.insert(order.id, order.clone());
                     }
                 }
             }
 
             // Process modified orders
-            let mut modified_ordercount = 0;
-            for (item_type, modified_orders) in diff.modified {
+            for (&item_type, modified_orders) in &diff.modified {
                 // Ensure the orderbook exists for this item type
                 if !global_book.items.contains_key(&item_type) {
                     global_book
@@ -153,17 +715,38 @@ pub async fn update_market_data(
 
                 if let Some(mut order_book) = global_book.items.get_mut(&item_type) {
                     for order in modified_orders {
-                        order_book.orders.insert(order.id, order);
-                        modified_ordercount += 1;
+                        order_book.orders.insert(order.id, order.clone());
+                    }
+                }
+            }
+
+            // Track which regions still contribute orders to each touched item, and how fresh
+            // its orders are
+            for &item_type in &touched_items {
+                if let Some(mut order_book) = global_book.items.get_mut(&item_type) {
+                    order_book.last_updated = order_book.last_updated.max(new_last_modified);
+                    if new_market.items.contains_key(&item_type) {
+                        order_book.regions.insert(region.id);
+                    } else {
+                        order_book.regions.remove(&region.id);
                     }
                 }
             }
 
-            println!(
-                "Applied delta for region {} - {new_ordercount} new orders, {modified_ordercount} modified orders, {removed_ordercount} removed orders",
-                region.name
+            tracing::info!(
+                "Applied delta for region {} - {} new orders ({} units, {:.2} ISK), {} modified orders, {} removed orders ({} units, {:.2} ISK)",
+                region.name,
+                summary.new_orders,
+                summary.volume_added,
+                summary.isk_added,
+                summary.modified_orders,
+                summary.removed_orders,
+                summary.volume_removed,
+                summary.isk_removed
             );
 
+            broadcasts.alerts.check(&global_book, &touched_items);
+
             // Update global market timestamps if this market is newer
             if new_last_modified > global_book.last_modified {
                 global_book.last_modified = new_last_modified;
@@ -175,10 +758,39 @@ pub async fn update_market_data(
             // Release the global book lock
             drop(global_book);
 
+            if let Some(prev_market) = regions.get(&region.id) {
+                watches.check(region.id, &prev_market, &new_market);
+            }
+
+            if let Some(snapshots) = &snapshots {
+                snapshots.save(region.id, &new_market).await;
+            }
+
             // Store the new regional market data
-            regions.insert(region, new_market);
+            regions.insert(region.id, new_market);
+
+            // failing to send broadcasts just means nobody has subscribed
+            if broadcasts.items.receiver_count() > 0 {
+                for &item_type in &touched_items {
+                    if let Some(event) = ItemDiffEvent::from_diff(region.id, item_type, &diff) {
+                        let _ = broadcasts.items.send(event);
+                    }
+                }
+            }
+            let _ = broadcasts.regions.send(RegionRefreshEvent {
+                id: region.id,
+                expires: new_expires,
+                new_orders: summary.new_orders,
+                modified_orders: summary.modified_orders,
+                removed_orders: summary.removed_orders,
+                total_orders: sample.order_count,
+                diff,
+            });
         });
     }
+
+    while tasks.join_next().await.is_some() {}
+    tracing::info!("Applied all pending region updates");
 }
 
 pub async fn get_refresh_intervals(
@@ -189,3 +801,387 @@ pub async fn get_refresh_intervals(
         intervals_map.insert(event.id.get(), Some(event.expires));
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use esi::{
+        market::{MarketOrderRange, Order},
+        universe::{StationID, SystemID},
+    };
+
+    fn make_order(id: u64, price: f64, volume_remain: u32, is_buy_order: bool) -> Order {
+        let issued = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        Order {
+            id,
+            is_buy_order,
+            price,
+            issued,
+            expiry: issued + TimeDelta::days(90),
+            location_id: StationID::try_from(60_003_760).unwrap(),
+            system_id: SystemID::try_from(30_000_142).unwrap(),
+            min_volume: 1,
+            range: MarketOrderRange::Region,
+            volume_remain,
+            volume_total: volume_remain,
+        }
+    }
+
+    fn market_with_sell(price: f64) -> Market {
+        let market = Market::new();
+        let mut book = OrderBook::new(TypeID::from(34));
+        let order = make_order(1, price, 100, false);
+        book.orders.insert(order.id, order);
+        market.items.insert(book.item, book);
+        market
+    }
+
+    #[test]
+    fn test_price_watch_threshold_and_region() {
+        let watches = PriceWatches::new();
+        let alerts: Arc<RwLock<Vec<PriceAlert>>> = Arc::default();
+        let forge = RegionID::try_from(10_000_002).unwrap();
+        let domain = RegionID::try_from(10_000_043).unwrap();
+        {
+            let alerts = alerts.clone();
+            let nested = watches.clone();
+            watches.watch_price(TypeID::from(34), forge, 10.0, move |alert| {
+                alerts.write().unwrap().push(alert.clone());
+                // registering from inside a callback must not deadlock the check
+                nested.watch_price(TypeID::from(35), forge, 50.0, |_| {});
+            });
+        }
+
+        // a 5% move stays under the threshold
+        watches.check(forge, &market_with_sell(100.0), &market_with_sell(105.0));
+        assert!(alerts.read().unwrap().is_empty());
+
+        // only watches on the refreshed region are checked
+        watches.check(domain, &market_with_sell(100.0), &market_with_sell(50.0));
+        assert!(alerts.read().unwrap().is_empty());
+
+        watches.check(forge, &market_with_sell(100.0), &market_with_sell(80.0));
+        let alert = alerts.read().unwrap()[0].clone();
+        assert_eq!((alert.type_id, alert.region_id), (TypeID::from(34), forge));
+        assert_eq!(alert.change_pct, -20.0);
+
+        // no percentage change from a zero price
+        watches.check(forge, &market_with_sell(0.0), &market_with_sell(80.0));
+        assert_eq!(alerts.read().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_liquidity_sample_sums_both_sides() {
+        let market = Market::new();
+        let mut book = OrderBook::new(TypeID::from(34));
+        for order in [
+            make_order(1, 5.0, 100, false),
+            make_order(2, 4.5, 200, true),
+        ] {
+            book.orders.insert(order.id, order);
+        }
+        market.items.insert(book.item, book);
+
+        let sample = LiquiditySample::from_market(&market);
+        assert_eq!(sample.book_value_isk, 1400.0);
+        assert_eq!(sample.order_count, 2);
+        assert_eq!(sample.timestamp, market.last_modified);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_event_counts_changes() {
+        let book = Arc::new(Mutex::new(Market::new()));
+        let (tx, rx) = mpsc::channel(8);
+        let broadcasts = UpdateBroadcasts::new(8, 8);
+        let mut event_rx = broadcasts.regions.subscribe();
+        tokio::spawn(update_market_data(
+            book,
+            rx,
+            PriceWatches::new(),
+            LiquidityHistory::new(),
+            broadcasts,
+            None,
+            None,
+        ));
+
+        let region = Region {
+            id: RegionID::try_from(10000002).unwrap(),
+            name: String::from("The Forge"),
+        };
+        let market = Market::new();
+        let mut orders = OrderBook::new(TypeID::from(34));
+        for order in [
+            make_order(1, 5.0, 100, false),
+            make_order(2, 4.5, 200, true),
+        ] {
+            orders.orders.insert(order.id, order);
+        }
+        market.items.insert(orders.item, orders);
+        tx.send((market, region.clone())).await.unwrap();
+
+        let event = time::timeout(Duration::from_secs(5), event_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.id, region.id);
+        assert_eq!(event.new_orders, 2);
+        assert_eq!(event.modified_orders, 0);
+        assert_eq!(event.removed_orders, 0);
+        assert_eq!(event.total_orders, 2);
+    }
+
+    #[tokio::test]
+    async fn test_item_events_per_changed_type() {
+        let book = Arc::new(Mutex::new(Market::new()));
+        let (tx, rx) = mpsc::channel(8);
+        let broadcasts = UpdateBroadcasts::new(8, 8);
+        let mut region_rx = broadcasts.regions.subscribe();
+        let mut item_rx = broadcasts.items.subscribe();
+        tokio::spawn(update_market_data(
+            book,
+            rx,
+            PriceWatches::new(),
+            LiquidityHistory::new(),
+            broadcasts,
+            None,
+            None,
+        ));
+
+        let region = Region {
+            id: RegionID::try_from(10000002).unwrap(),
+            name: String::from("The Forge"),
+        };
+        let market_with = |orders: &[(u32, Order)]| {
+            let market = Market::new();
+            for (type_id, order) in orders {
+                market
+                    .items
+                    .entry(TypeID::from(*type_id))
+                    .or_insert_with(|| OrderBook::new(TypeID::from(*type_id)))
+                    .orders
+                    .insert(order.id, order.clone());
+            }
+            market
+        };
+
+        let first = market_with(&[
+            (34, make_order(1, 5.0, 100, false)),
+            (35, make_order(2, 9.0, 100, false)),
+        ]);
+        tx.send((first, region.clone())).await.unwrap();
+        time::timeout(Duration::from_secs(5), region_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        let mut events = [item_rx.recv().await.unwrap(), item_rx.recv().await.unwrap()];
+        events.sort_by_key(|event| event.type_id);
+        assert_eq!(events[0].type_id, TypeID::from(34));
+        assert_eq!(events[0].new.len(), 1);
+        assert_eq!(events[1].type_id, TypeID::from(35));
+
+        // only the changed type is sent, with its removed order
+        let second = market_with(&[
+            (34, make_order(1, 5.0, 100, false)),
+            (35, make_order(3, 8.5, 100, false)),
+        ]);
+        tx.send((second, region.clone())).await.unwrap();
+        let event = time::timeout(Duration::from_secs(5), item_rx.recv())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(event.type_id, TypeID::from(35));
+        assert_eq!(event.region_id, region.id);
+        assert_eq!(event.new[0].id, 3);
+        assert_eq!(event.removed, vec![2]);
+        assert!(item_rx.try_recv().is_err());
+    }
+
+    #[test]
+    fn test_liquidity_history_keeps_last_window() {
+        let history = LiquidityHistory::new();
+        let forge = RegionID::try_from(10000002).unwrap();
+        let start = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+
+        for i in 0..LIQUIDITY_SAMPLES + 10 {
+            history.record(
+                forge,
+                LiquiditySample {
+                    timestamp: start + TimeDelta::minutes(5 * i as i64),
+                    book_value_isk: i as f64,
+                    order_count: i,
+                },
+            );
+        }
+
+        let samples = history.samples(forge).unwrap();
+        assert_eq!(samples.len(), LIQUIDITY_SAMPLES);
+        assert_eq!(samples[0].order_count, 10);
+        assert_eq!(samples.last().unwrap().order_count, LIQUIDITY_SAMPLES + 9);
+
+        let unseen = RegionID::try_from(10000043).unwrap();
+        assert!(history.samples(unseen).is_none());
+    }
+
+    #[test]
+    fn test_error_backoff_doubles_up_to_cap() {
+        let base = FetcherConfig::default().error_retry_delay;
+        assert_eq!(error_backoff(1, base), Duration::from_secs(15));
+        assert_eq!(error_backoff(2, base), Duration::from_secs(30));
+        assert_eq!(error_backoff(4, base), Duration::from_secs(120));
+        assert_eq!(error_backoff(6, base), Duration::from_secs(480));
+        assert_eq!(error_backoff(7, base), Duration::from_secs(600));
+        assert_eq!(error_backoff(u32::MAX, base), Duration::from_secs(600));
+
+        let fast = Duration::from_secs(1);
+        assert_eq!(error_backoff(3, fast), Duration::from_secs(4));
+    }
+
+    #[tokio::test]
+    async fn test_diff_export_writes_lines_and_rotates() {
+        let dir = std::env::temp_dir().join(format!("diff-export-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("diffs.ndjson");
+        let region_id = RegionID::try_from(10_000_002).unwrap();
+        let timestamp = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        let mut diff = MarketDiff::new();
+        diff.new
+            .insert(TypeID::from(34), vec![make_order(1, 5.0, 10, false)]);
+        diff.removed.insert(TypeID::from(35), vec![2]);
+        let record = DiffRecord {
+            region_id,
+            timestamp,
+            diff: &diff,
+        };
+        let mut line = serde_json::to_vec(&record).unwrap();
+        line.push(b'\n');
+
+        let parsed: serde_json::Value = serde_json::from_slice(&line).unwrap();
+        assert_eq!(parsed["region_id"], 10_000_002);
+        assert_eq!(parsed["new"]["34"][0]["price"], 5.0);
+        assert_eq!(parsed["removed"]["35"][0], 2);
+
+        // room for two lines before rotating
+        let export = DiffExport::new(&path, 2 * line.len() as u64);
+        for _ in 0..3 {
+            export.append(&line).await.unwrap();
+        }
+
+        let current = std::fs::read(&path).unwrap();
+        let rotated = std::fs::read(dir.join("diffs.ndjson.1")).unwrap();
+        assert_eq!(current, line);
+        assert_eq!(rotated.len(), 2 * line.len());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_snapshots_skip_expired_regions() {
+        let dir = std::env::temp_dir().join(format!("market-snapshots-{}", std::process::id()));
+        let snapshots = MarketSnapshots::new(&dir);
+        let forge = RegionID::try_from(10_000_002).unwrap();
+        let domain = RegionID::try_from(10_000_043).unwrap();
+        let never_saved = RegionID::try_from(10_000_030).unwrap();
+        let book_id = TypeID::from(34);
+
+        let mut fresh = Market::new();
+        fresh.last_modified = Utc::now();
+        fresh.expires = fresh.last_modified + TimeDelta::minutes(5);
+        let mut book = OrderBook::new(book_id);
+        let order = make_order(1, 5.0, 100, false);
+        book.orders.insert(order.id, order);
+        fresh.items.insert(book.item, book);
+        snapshots.save(forge, &fresh).await;
+
+        let mut expired = Market::new();
+        expired.expires = Utc::now() - TimeDelta::minutes(1);
+        snapshots.save(domain, &expired).await;
+
+        let restored = snapshots.load(forge).await.unwrap();
+        assert_eq!(restored.expires, fresh.expires);
+        assert_eq!(restored.items.get(&book_id).unwrap().orders.len(), 1);
+        assert!(snapshots.load(domain).await.is_none());
+        assert!(snapshots.load(never_saved).await.is_none());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_update_drains_channel_before_returning() {
+        let book = Arc::new(Mutex::new(Market::new()));
+        let (tx, rx) = mpsc::channel(8);
+        let updater = tokio::spawn(update_market_data(
+            book.clone(),
+            rx,
+            PriceWatches::new(),
+            LiquidityHistory::new(),
+            UpdateBroadcasts::new(8, 8),
+            None,
+            None,
+        ));
+
+        for (region_id, order_id) in [(10000002, 1), (10000043, 2)] {
+            let region = Region {
+                id: RegionID::try_from(region_id).unwrap(),
+                name: region_id.to_string(),
+            };
+            let market = Market::new();
+            let mut orders = OrderBook::new(TypeID::from(34));
+            orders
+                .orders
+                .insert(order_id, make_order(order_id, 5.0, 100, false));
+            market.items.insert(orders.item, orders);
+            tx.send((market, region)).await.unwrap();
+        }
+        drop(tx);
+
+        time::timeout(Duration::from_secs(5), updater)
+            .await
+            .unwrap()
+            .unwrap();
+        let book = book.lock().await;
+        assert_eq!(book.items.get(&TypeID::from(34)).unwrap().orders.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_refresh_stops_mid_fetch_on_shutdown() {
+        // accepts connections but never answers, so the fetch hangs
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut sockets = Vec::new();
+            while let Ok((socket, _)) = listener.accept().await {
+                sockets.push(socket);
+            }
+        });
+
+        let mut client = ESIClient::in_memory("test", "test", 1);
+        client.set_base_url(&format!("http://{addr}"));
+        let region = Region {
+            id: RegionID::try_from(10000002).unwrap(),
+            name: String::from("The Forge"),
+        };
+        let (tx, mut rx) = mpsc::channel(1);
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let refresh = tokio::spawn(refresh_region_data(
+            region,
+            Arc::new(client),
+            tx,
+            FetcherConfig::default(),
+            FetcherMetrics::new(),
+            None,
+            shutdown_rx,
+        ));
+
+        time::sleep(Duration::from_millis(100)).await;
+        shutdown_tx.send(true).unwrap();
+        time::timeout(Duration::from_secs(5), refresh)
+            .await
+            .unwrap()
+            .unwrap();
+
+        // the refresh dropped its sender without sending anything
+        assert!(rx.recv().await.is_none());
+    }
+}
@@ -1,18 +1,30 @@
-use std::{sync::Arc};
+use std::{sync::Arc, time::Duration};
 use chrono::{DateTime, Utc};
 use dashmap::DashMap;
-use data_fetcher::{get_refresh_intervals, server::data_server};
+use data_fetcher::{
+    DiffExport, FetcherConfig, FetcherMetrics, LiquidityHistory, MarketSnapshots, PriceWatches,
+    UpdateBroadcasts, get_refresh_intervals, record_refresh_events, server::data_server,
+    shutdown_signal,
+};
 use esi::{
-    ESIClient,
+    Datasource, ESIClient,
     market::Market,
-    universe::{Regions},
+    universe::{Items, Region, Regions},
 };
-use tokio::sync::{Mutex, broadcast, mpsc};
+use tokio::sync::{Mutex, mpsc, watch};
+use tracing_subscriber::EnvFilter;
 
 // so much DI smh
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    // log level is set through RUST_LOG, e.g. RUST_LOG=data_fetcher=debug,esi=debug
+    tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .init();
+
     // Set up file descriptor limits in the main thread
     let max_fds: usize = {
         #[cfg(unix)]
@@ -32,32 +44,104 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    let client = Arc::new(ESIClient::new(
-        "market_data_fetcher",
-        std::env::consts::OS,
-        max_fds,
-    ));
+    // retry delay, expiry padding, jitter and page cap of each region refresh
+    let fetcher_config = FetcherConfig::from_env();
+
+    let mut client = ESIClient::new("market_data_fetcher", std::env::consts::OS, max_fds);
+    if fetcher_config.reuse_unchanged_pages {
+        client.enable_etags();
+    }
+    // point at a proxy or the test server with e.g. ESI_DATASOURCE=singularity
+    if let Ok(base_url) = std::env::var("ESI_BASE_URL") {
+        client.set_base_url(&base_url);
+    }
+    if let Ok(datasource) = std::env::var("ESI_DATASOURCE") {
+        client.set_datasource(datasource.parse::<Datasource>()?);
+    }
+    let client = Arc::new(client);
     let regions = Regions::get_all(client.clone()).await?;
 
-    // Set up broadcast channel for region refresh events
-    let (region_upd_tx, region_upd_rx) = broadcast::channel(128);
+    // set on SIGINT/SIGTERM, stops the server and the refresh loops
+    let (shutdown_tx, shutdown_rx) = watch::channel(false);
+    tokio::spawn(async move {
+        shutdown_signal().await;
+        let _ = shutdown_tx.send(true);
+    });
+
+    // Set up broadcast channels for region refresh and per-item diff events
+    let broadcasts = UpdateBroadcasts::new(128, 4096);
+    let region_upd_tx = broadcasts.regions.clone();
+    let region_upd_rx = region_upd_tx.subscribe();
+
+    // delay between starting each region's refresh task, to spread out the first fetches
+    let spawn_stagger = Duration::from_millis(
+        std::env::var("REGION_SPAWN_STAGGER_MS")
+            .ok()
+            .and_then(|millis| millis.parse().ok())
+            .unwrap_or(100),
+    );
+
+    // per-region snapshots to resume from after a restart, see MARKET_SNAPSHOT_DIR
+    let snapshots = MarketSnapshots::from_env();
 
     // set up orderbook fetching
     let market_books = Arc::new(Mutex::new(Market::new()));
-    {
+    let liquidity = LiquidityHistory::new();
+    let metrics = FetcherMetrics::new();
+    tokio::spawn(record_refresh_events(
+        metrics.clone(),
+        region_upd_tx.subscribe(),
+    ));
+    let updater = {
         let (tx, rx) = mpsc::channel(128);
-        for region in regions.region_map.clone().iter() {
+
+        // orderbook reassembler, started first so it doesn't wait on the staggered spawns
+        let updater = tokio::spawn(data_fetcher::update_market_data(
+            market_books.clone(),
+            rx,
+            PriceWatches::new(),
+            liquidity.clone(),
+            broadcasts.clone(),
+            DiffExport::from_env(),
+            snapshots.clone(),
+        ));
+
+        let mut sorted_regions: Vec<Region> = regions
+            .region_map
+            .iter()
+            .map(|region| region.clone())
+            .collect();
+        sorted_regions.sort_by_key(|region| region.id.get());
+
+        for region in sorted_regions {
+            if *shutdown_rx.borrow() {
+                break;
+            }
+
+            // apply a snapshot that's still fresh as if it was just fetched, the first refresh
+            // then waits for it to expire and only the changes since are applied
+            let mut resume_at = None;
+            if let Some(snapshots) = &snapshots
+                && let Some(market) = snapshots.load(region.id).await
+            {
+                resume_at = Some(market.expires);
+                let _ = tx.send((market, region.clone())).await;
+            }
+
             tokio::spawn(data_fetcher::refresh_region_data(
-                region.clone(),
+                region,
                 client.clone(),
                 tx.clone(),
-                region_upd_tx.clone(),
+                fetcher_config,
+                metrics.clone(),
+                resume_at,
+                shutdown_rx.clone(),
             ));
+            tokio::time::sleep(spawn_stagger).await;
         }
 
-        // orderbook reassembler
-        tokio::spawn(data_fetcher::update_market_data(market_books.clone(), rx));
-    }
+        updater
+    };
 
     // Handle interval refresh state
     let refresh_intervals: Arc<DashMap<u32, Option<DateTime<Utc>>>> =
@@ -65,7 +149,22 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     tokio::spawn(get_refresh_intervals(refresh_intervals.clone(), region_upd_rx));
 
 
-    data_server(refresh_intervals, market_books).await.unwrap();
+    let items = Arc::new(Items::new(client.clone()));
+
+    data_server(
+        refresh_intervals,
+        market_books,
+        items,
+        liquidity,
+        metrics,
+        broadcasts,
+        shutdown_rx,
+    )
+    .await
+    .unwrap();
+
+    // the refresh loops have stopped with the server, wait for their last snapshots to be applied
+    updater.await?;
 
     Ok(())
 }
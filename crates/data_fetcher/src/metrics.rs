@@ -0,0 +1,262 @@
+use std::{
+    fmt::Write,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, Ordering},
+    },
+    time::Duration,
+};
+
+use chrono::{DateTime, Utc};
+use dashmap::DashMap;
+use esi::universe::RegionID;
+use tokio::sync::broadcast::{Receiver, error::RecvError};
+
+use crate::RegionRefreshEvent;
+
+/// What the fetcher has done for one region since it started.
+#[derive(Clone, Copy, Debug, Default)]
+struct RegionMetrics {
+    fetches: u64,
+    fetch_errors: u64,
+    last_fetch_duration: Duration,
+    new_orders: u64,
+    modified_orders: u64,
+    removed_orders: u64,
+    /// Orders in the region after its last refresh.
+    orders: usize,
+    last_refresh: Option<DateTime<Utc>>,
+    expires: Option<DateTime<Utc>>,
+}
+
+/// A metric reported once per region, labelled with the region's ID.
+struct RegionMetric {
+    name: &'static str,
+    kind: &'static str,
+    help: &'static str,
+    /// `None` leaves the region out, for timestamps it doesn't have yet.
+    value: fn(&RegionMetrics) -> Option<f64>,
+}
+
+const REGION_METRICS: [RegionMetric; 9] = [
+    RegionMetric {
+        name: "fetches_total",
+        kind: "counter",
+        help: "Region fetches attempted.",
+        value: |m| Some(m.fetches as f64),
+    },
+    RegionMetric {
+        name: "fetch_errors_total",
+        kind: "counter",
+        help: "Region fetches that failed.",
+        value: |m| Some(m.fetch_errors as f64),
+    },
+    RegionMetric {
+        name: "fetch_duration_seconds",
+        kind: "gauge",
+        help: "How long the last fetch of the region took.",
+        value: |m| Some(m.last_fetch_duration.as_secs_f64()),
+    },
+    RegionMetric {
+        name: "orders_new_total",
+        kind: "counter",
+        help: "Orders added by refreshes.",
+        value: |m| Some(m.new_orders as f64),
+    },
+    RegionMetric {
+        name: "orders_modified_total",
+        kind: "counter",
+        help: "Orders changed by refreshes.",
+        value: |m| Some(m.modified_orders as f64),
+    },
+    RegionMetric {
+        name: "orders_removed_total",
+        kind: "counter",
+        help: "Orders removed by refreshes.",
+        value: |m| Some(m.removed_orders as f64),
+    },
+    RegionMetric {
+        name: "region_orders",
+        kind: "gauge",
+        help: "Orders in the region after its last refresh.",
+        value: |m| Some(m.orders as f64),
+    },
+    RegionMetric {
+        name: "last_refresh_timestamp_seconds",
+        kind: "gauge",
+        help: "When the region's last refresh was applied.",
+        value: |m| m.last_refresh.map(|at| at.timestamp() as f64),
+    },
+    RegionMetric {
+        name: "expires_timestamp_seconds",
+        kind: "gauge",
+        help: "When ESI expires the region's last snapshot.",
+        value: |m| m.expires.map(|at| at.timestamp() as f64),
+    },
+];
+
+/// Counters and gauges of the refresh loops, served in the Prometheus text format on `/metrics`.
+///
+/// Like [`crate::LiquidityHistory`] everything is kept in memory, so counters start over whenever
+/// the fetcher restarts.
+#[derive(Clone, Debug)]
+pub struct FetcherMetrics {
+    regions: Arc<DashMap<RegionID, RegionMetrics>>,
+    esi_error_budget: Arc<AtomicU32>,
+}
+
+impl Default for FetcherMetrics {
+    fn default() -> Self {
+        FetcherMetrics {
+            regions: Arc::default(),
+            // ESI's budget per window, until a response says otherwise
+            esi_error_budget: Arc::new(AtomicU32::new(100)),
+        }
+    }
+}
+
+impl FetcherMetrics {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Records one fetch of `region_id` that took `duration`, and the error budget the client
+    /// had left after it.
+    pub fn record_fetch(
+        &self,
+        region_id: RegionID,
+        duration: Duration,
+        succeeded: bool,
+        error_budget: u32,
+    ) {
+        let mut region = self.regions.entry(region_id).or_default();
+        region.fetches += 1;
+        if !succeeded {
+            region.fetch_errors += 1;
+        }
+        region.last_fetch_duration = duration;
+        drop(region);
+
+        self.esi_error_budget.store(error_budget, Ordering::Relaxed);
+    }
+
+    /// Records the order counts of a refresh [`crate::update_market_data`] applied.
+    pub fn record_refresh(&self, event: &RegionRefreshEvent, refreshed_at: DateTime<Utc>) {
+        let mut region = self.regions.entry(event.id).or_default();
+        region.new_orders += event.new_orders as u64;
+        region.modified_orders += event.modified_orders as u64;
+        region.removed_orders += event.removed_orders as u64;
+        region.orders = event.total_orders;
+        region.last_refresh = Some(refreshed_at);
+        region.expires = Some(event.expires);
+    }
+
+    /// Renders every metric in the Prometheus text exposition format. `book_orders` is the size
+    /// of the merged book, which only the server holds.
+    pub fn render(&self, book_orders: usize) -> String {
+        let mut regions: Vec<(RegionID, RegionMetrics)> = self
+            .regions
+            .iter()
+            .map(|entry| (*entry.key(), *entry.value()))
+            .collect();
+        regions.sort_by_key(|(id, _)| id.get());
+
+        let mut out = String::new();
+        header(
+            &mut out,
+            "book_orders",
+            "gauge",
+            "Orders in the merged book.",
+        );
+        let _ = writeln!(out, "data_fetcher_book_orders {book_orders}");
+        header(
+            &mut out,
+            "esi_error_budget",
+            "gauge",
+            "Errors ESI still accepts in its current window.",
+        );
+        let budget = self.esi_error_budget.load(Ordering::Relaxed);
+        let _ = writeln!(out, "data_fetcher_esi_error_budget {budget}");
+
+        for metric in REGION_METRICS {
+            header(&mut out, metric.name, metric.kind, metric.help);
+            for (id, metrics) in &regions {
+                if let Some(value) = (metric.value)(metrics) {
+                    let name = metric.name;
+                    let _ = writeln!(out, "data_fetcher_{name}{{region=\"{id}\"}} {value}");
+                }
+            }
+        }
+
+        out
+    }
+}
+
+fn header(out: &mut String, name: &str, kind: &str, help: &str) {
+    let _ = writeln!(out, "# HELP data_fetcher_{name} {help}");
+    let _ = writeln!(out, "# TYPE data_fetcher_{name} {kind}");
+}
+
+/// Feeds every [`RegionRefreshEvent`] into `metrics`, until the sender is gone.
+pub async fn record_refresh_events(
+    metrics: FetcherMetrics,
+    mut broadcast_rx: Receiver<RegionRefreshEvent>,
+) {
+    loop {
+        match broadcast_rx.recv().await {
+            Ok(event) => metrics.record_refresh(&event, Utc::now()),
+            Err(RecvError::Lagged(skipped)) => {
+                tracing::warn!("Metrics missed {skipped} region refreshes");
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use esi::market::MarketDiff;
+
+    #[test]
+    fn test_render_sums_refreshes_per_region() {
+        let metrics = FetcherMetrics::new();
+        let forge = RegionID::try_from(10000002).unwrap();
+        let expires = Utc.with_ymd_and_hms(2024, 1, 1, 0, 5, 0).unwrap();
+        let refreshed_at = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+
+        metrics.record_fetch(forge, Duration::from_millis(1500), true, 100);
+        metrics.record_fetch(forge, Duration::from_millis(250), false, 97);
+        for (new_orders, total_orders) in [(10, 10), (3, 12)] {
+            let event = RegionRefreshEvent {
+                id: forge,
+                expires,
+                new_orders,
+                modified_orders: 2,
+                removed_orders: 1,
+                total_orders,
+                diff: Arc::new(MarketDiff::new()),
+            };
+            metrics.record_refresh(&event, refreshed_at);
+        }
+
+        let rendered = metrics.render(42);
+        for line in [
+            "# TYPE data_fetcher_book_orders gauge",
+            "data_fetcher_book_orders 42",
+            "data_fetcher_esi_error_budget 97",
+            "data_fetcher_fetches_total{region=\"10000002\"} 2",
+            "data_fetcher_fetch_errors_total{region=\"10000002\"} 1",
+            "data_fetcher_fetch_duration_seconds{region=\"10000002\"} 0.25",
+            "data_fetcher_orders_new_total{region=\"10000002\"} 13",
+            "data_fetcher_orders_modified_total{region=\"10000002\"} 4",
+            "data_fetcher_orders_removed_total{region=\"10000002\"} 2",
+            "data_fetcher_region_orders{region=\"10000002\"} 12",
+            "data_fetcher_last_refresh_timestamp_seconds{region=\"10000002\"} 1704067200",
+            "data_fetcher_expires_timestamp_seconds{region=\"10000002\"} 1704067500",
+        ] {
+            assert!(rendered.lines().any(|l| l == line), "missing {line}");
+        }
+    }
+}
@@ -1,43 +1,461 @@
 use std::{env, sync::Arc};
 
-use axum::{Json, Router, extract::Path, response::IntoResponse, routing::get};
-use chrono::{DateTime, Utc};
+use axum::{
+    Json, Router,
+    body::{Body, Bytes},
+    extract::{
+        Path, Query, WebSocketUpgrade,
+        ws::{Message, WebSocket},
+    },
+    http::{StatusCode, header::CONTENT_TYPE},
+    response::IntoResponse,
+    routing::{MethodRouter, delete, get, post},
+};
+use chrono::{DateTime, TimeDelta, Utc};
 use dashmap::DashMap;
-use esi::market::{Market, Order};
-use tokio::sync::Mutex;
+use esi::{
+    market::{ItemOrderBook, Market, Order, OrderBook},
+    universe::{Items, RegionID, TypeID},
+};
+use futures::{Stream, stream};
+use serde::{Deserialize, Serialize};
+use tokio::sync::{
+    Mutex,
+    broadcast::{self, error::RecvError},
+    watch,
+};
+
+use crate::{
+    AlertRegistry, FetcherMetrics, LiquidityHistory, MarketUpdate, PriceThreshold,
+    RegionRefreshEvent, UpdateBroadcasts, shutdown_requested,
+};
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Side {
+    Buy,
+    Sell,
+}
+
+/// Query parameters accepted by `/market/{id}`.
+#[derive(Deserialize, Debug, Default)]
+pub struct MarketQuery {
+    pub limit: Option<usize>,
+    pub offset: Option<usize>,
+    pub side: Option<Side>,
+}
+
+/// A page of an item's orders. `total` counts every order matching the side filter.
+///
+/// `last_modified` and `expires` tell clients how fresh the book is and when to poll again,
+/// `last_updated` how fresh this item's orders are, and `region_ids` lists the regions the
+/// orders come from.
+#[derive(Serialize, Debug)]
+pub struct MarketPage {
+    pub total: usize,
+    pub offset: usize,
+    pub orders: Vec<Order>,
+    pub last_modified: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+    pub last_updated: DateTime<Utc>,
+    pub region_ids: Vec<RegionID>,
+}
+
+/// Orders serialized per chunk of a streamed [`MarketPage`].
+const STREAM_CHUNK_ORDERS: usize = 256;
+
+/// Everything in a [`MarketPage`] but the orders, written ahead of them when streaming.
+#[derive(Serialize)]
+struct MarketPageMeta<'a> {
+    total: usize,
+    offset: usize,
+    last_modified: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    last_updated: DateTime<Utc>,
+    region_ids: &'a [RegionID],
+}
+
+impl MarketPage {
+    /// Serializes the page as the same JSON object its `Serialize` impl produces, `chunk_orders`
+    /// orders at a time, so the first bytes go out before the last order is serialized and the
+    /// whole body is never held in memory at once. The page's own orders are, they're cloned out
+    /// of the book up front.
+    fn into_json_stream(
+        self,
+        chunk_orders: usize,
+    ) -> impl Stream<Item = Result<Bytes, serde_json::Error>> {
+        let meta = serde_json::to_vec(&MarketPageMeta {
+            total: self.total,
+            offset: self.offset,
+            last_modified: self.last_modified,
+            expires: self.expires,
+            last_updated: self.last_updated,
+            region_ids: &self.region_ids,
+        })
+        .map(|mut meta| {
+            // reopen the object to append the orders
+            meta.pop();
+            meta.extend_from_slice(b",\"orders\":[");
+            Bytes::from(meta)
+        });
+
+        let mut orders = self.orders.into_iter().peekable();
+        let mut first = true;
+        let chunks = std::iter::from_fn(move || {
+            orders.peek()?;
+
+            let mut chunk = Vec::new();
+            for order in orders.by_ref().take(chunk_orders.max(1)) {
+                if !first {
+                    chunk.push(b',');
+                }
+                first = false;
+                if let Err(err) = serde_json::to_writer(&mut chunk, &order) {
+                    return Some(Err(err));
+                }
+            }
+            Some(Ok(Bytes::from(chunk)))
+        });
+
+        stream::iter(
+            std::iter::once(meta)
+                .chain(chunks)
+                .chain(std::iter::once(Ok(Bytes::from_static(b"]}")))),
+        )
+    }
+}
+
+/// Buy orders come first, highest price first, followed by sell orders, lowest price first.
+fn market_page(market: &Market, book: &OrderBook, query: &MarketQuery) -> MarketPage {
+    let (mut buys, mut sells) = book.split();
+    match query.side {
+        Some(Side::Buy) => sells.clear(),
+        Some(Side::Sell) => buys.clear(),
+        None => {}
+    }
+
+    let total = buys.len() + sells.len();
+    let offset = query.offset.unwrap_or(0);
+    let orders = buys
+        .into_iter()
+        .chain(sells)
+        .skip(offset)
+        .take(query.limit.unwrap_or(usize::MAX))
+        .cloned()
+        .collect();
+
+    let mut region_ids: Vec<RegionID> = book.regions.iter().copied().collect();
+    region_ids.sort_by_key(RegionID::get);
+
+    MarketPage {
+        total,
+        offset,
+        orders,
+        last_modified: market.last_modified,
+        expires: market.expires,
+        last_updated: book.last_updated,
+        region_ids,
+    }
+}
+
+/// Body of `/market/{id}/depth`, see [`OrderBook::depth_curve`].
+#[derive(Serialize, Debug)]
+pub struct MarketDepth {
+    pub buy: Vec<(f64, u64)>,
+    pub sell: Vec<(f64, u64)>,
+}
+
+/// How long past its `expires` a region may go without refreshing before it counts as stuck.
+const OVERDUE_GRACE: TimeDelta = TimeDelta::minutes(10);
+
+/// Share of regions that must have refreshed at least once for `/health` to pass, so a few
+/// regions failing their first fetch don't keep the whole service out of rotation.
+const HEALTHY_REFRESHED_FRACTION: f64 = 0.9;
+
+/// Body of `/health`.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Health {
+    pub healthy: bool,
+    pub regions_total: usize,
+    /// Regions that have completed at least one refresh.
+    pub regions_refreshed: usize,
+    /// Regions whose data expired more than [`OVERDUE_GRACE`] ago.
+    pub regions_overdue: Vec<u32>,
+}
+
+/// Healthy once [`HEALTHY_REFRESHED_FRACTION`] of the regions have refreshed at least once and
+/// none of those are stuck.
+fn health_report(
+    refresh_intervals: &DashMap<u32, Option<DateTime<Utc>>>,
+    now: DateTime<Utc>,
+) -> Health {
+    let mut regions_refreshed = 0;
+    let mut regions_overdue = Vec::new();
+
+    for entry in refresh_intervals.iter() {
+        if let Some(expires) = *entry.value() {
+            regions_refreshed += 1;
+            if expires + OVERDUE_GRACE < now {
+                regions_overdue.push(*entry.key());
+            }
+        }
+    }
+    regions_overdue.sort();
+
+    let regions_total = refresh_intervals.len();
+    Health {
+        healthy: regions_refreshed as f64 >= regions_total as f64 * HEALTHY_REFRESHED_FRACTION
+            && regions_overdue.is_empty(),
+        regions_total,
+        regions_refreshed,
+        regions_overdue,
+    }
+}
+
+/// Sends `socket` a [`MarketUpdate`] for each refresh on `events` that touches `type_id`, until
+/// the client goes away or `shutdown` fires.
+///
+/// A client that falls behind the channel is disconnected rather than sent a diff on top of ones
+/// it missed, it has to fetch `/market/{id}` again after reconnecting anyway.
+async fn forward_market_updates(
+    mut socket: WebSocket,
+    type_id: TypeID,
+    mut events: broadcast::Receiver<RegionRefreshEvent>,
+    mut shutdown: watch::Receiver<bool>,
+) {
+    loop {
+        tokio::select! {
+            event = events.recv() => match event {
+                Ok(event) => {
+                    let Some(update) = MarketUpdate::from_diff(event.id, type_id, &event.diff)
+                    else {
+                        continue;
+                    };
+                    let text = match serde_json::to_string(&update) {
+                        Ok(text) => text,
+                        Err(err) => {
+                            tracing::error!("Failed to serialize update for type {type_id}: {err}");
+                            continue;
+                        }
+                    };
+                    if socket.send(Message::Text(text.into())).await.is_err() {
+                        break;
+                    }
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    tracing::warn!("Feed of type {type_id} missed {missed} refreshes, disconnecting");
+                    break;
+                }
+                Err(RecvError::Closed) => break,
+            },
+            message = socket.recv() => match message {
+                // nothing is expected from the client, pings are answered by axum
+                Some(Ok(Message::Close(_)) | Err(_)) | None => break,
+                Some(Ok(_)) => {}
+            },
+            _ = shutdown_requested(&mut shutdown) => break,
+        }
+    }
+
+    let _ = socket.send(Message::Close(None)).await;
+}
+
+/// `/ws/market/{id}`: a live feed of the changes to one item's orders, see
+/// [`crate::connect_feed`] for the client side.
+fn market_feed(
+    events: broadcast::Sender<RegionRefreshEvent>,
+    shutdown: watch::Receiver<bool>,
+) -> MethodRouter {
+    get(
+        move |Path(id): Path<String>, upgrade: WebSocketUpgrade| async move {
+            let Ok(id) = id.parse::<u32>() else {
+                return (StatusCode::BAD_REQUEST, "Invalid ID format").into_response();
+            };
+            let type_id = TypeID::from(id);
+
+            // subscribe before upgrading so no refresh falls between the two
+            let events = events.subscribe();
+            upgrade
+                .on_upgrade(move |socket| forward_market_updates(socket, type_id, events, shutdown))
+        },
+    )
+}
+
+/// `/market/{id}/named`: the orders of one item with its [`esi::universe::Item`] attached. The
+/// book is copied out of the market first, so resolving the item never holds the market lock.
+fn named_orders(market: Arc<Mutex<Market>>, items: Arc<Items>) -> MethodRouter {
+    get(move |Path(id): Path<String>| async move {
+        let Ok(id) = id.parse::<u32>() else {
+            return (StatusCode::BAD_REQUEST, "Invalid ID format").into_response();
+        };
+        let id = TypeID::from(id);
+
+        let book = market.lock().await.items.get(&id).map(|book| book.clone());
+        let Some(book) = book else {
+            return (StatusCode::NOT_FOUND, "Item Type Not Found").into_response();
+        };
+
+        match items.get_item(id).await {
+            Ok(item) => Json(ItemOrderBook::new(item, &book)).into_response(),
+            Err(err) => {
+                tracing::error!("Failed to attach item names for type {id}: {err}");
+                (StatusCode::BAD_GATEWAY, "Item lookup failed").into_response()
+            }
+        }
+    })
+}
+
+/// Response of `POST /alerts`.
+#[derive(Serialize, Deserialize, Debug)]
+struct AlertCreated {
+    id: u64,
+}
+
+/// `POST /alerts`: registers a [`PriceThreshold`] given as JSON. Crossings are broadcast as
+/// [`crate::AlertEvent`]s and logged.
+fn register_alert(alerts: AlertRegistry) -> MethodRouter {
+    post(move |Json(alert): Json<PriceThreshold>| async move {
+        if !alert.threshold.is_finite() || alert.threshold <= 0.0 {
+            let reason = "Threshold must be a positive price";
+            return (StatusCode::BAD_REQUEST, reason).into_response();
+        }
+
+        let id = alerts.register(alert);
+        (StatusCode::CREATED, Json(AlertCreated { id })).into_response()
+    })
+}
+
+/// `DELETE /alerts/{id}`: stops checking an alert registered through `POST /alerts`.
+fn remove_alert(alerts: AlertRegistry) -> MethodRouter {
+    delete(move |Path(id): Path<String>| async move {
+        let Ok(id) = id.parse::<u64>() else {
+            return (StatusCode::BAD_REQUEST, "Invalid ID format").into_response();
+        };
+
+        if alerts.remove(id) {
+            StatusCode::NO_CONTENT.into_response()
+        } else {
+            (StatusCode::NOT_FOUND, "Alert Not Found").into_response()
+        }
+    })
+}
 
 pub async fn data_server(
     refresh_intervals: Arc<DashMap<u32, Option<DateTime<Utc>>>>,
     market: Arc<Mutex<Market>>,
+    items: Arc<Items>,
+    liquidity: LiquidityHistory,
+    metrics: FetcherMetrics,
+    broadcasts: UpdateBroadcasts,
+    mut shutdown: watch::Receiver<bool>,
 ) -> Result<(), std::io::Error> {
     let server = Router::new()
         .route(
             "/ping",
             get(|| async { format!("OK {}", Utc::now().to_rfc2822()) }),
         )
+        .route("/health", {
+            let refresh_intervals = refresh_intervals.clone();
+            get(move || async move {
+                let health = health_report(&refresh_intervals, Utc::now());
+                let status = if health.healthy {
+                    StatusCode::OK
+                } else {
+                    StatusCode::SERVICE_UNAVAILABLE
+                };
+                (status, Json(health))
+            })
+        })
+        // Prometheus text format, see FetcherMetrics::render
+        .route("/metrics", {
+            let market = market.clone();
+            get(move || async move {
+                let book_orders = {
+                    let market = market.lock().await;
+                    market.items.iter().map(|book| book.orders.len()).sum()
+                };
+                (
+                    [(CONTENT_TYPE, "text/plain; version=0.0.4")],
+                    metrics.render(book_orders),
+                )
+            })
+        })
         .route("/refresh_intervals", {
             let refresh_intervals = refresh_intervals.clone();
             get(move || async move { serde_json::to_string(&*refresh_intervals).unwrap() })
         })
         .route("/market/{id}", {
+            let market = market.clone();
+            get(
+                move |Path(id): Path<String>, Query(query): Query<MarketQuery>| async move {
+                    let id = id.parse::<u32>();
+                    if id.is_err() {
+                        return (StatusCode::BAD_REQUEST, "Invalid ID format").into_response();
+                    }
+                    let id = TypeID::from(id.unwrap());
+
+                    let market = market.lock().await;
+                    let page = match market.items.get(&id) {
+                        Some(orderbook) => market_page(&market, &orderbook, &query),
+                        None => {
+                            return (StatusCode::NOT_FOUND, "Item Type Not Found").into_response();
+                        }
+                    };
+                    // the page owns a copy of its orders, so the book isn't held while a slow
+                    // client reads. Only the serialized body is bounded by the chunking
+                    drop(market);
+
+                    let body = Body::from_stream(page.into_json_stream(STREAM_CHUNK_ORDERS));
+                    ([(CONTENT_TYPE, "application/json")], body).into_response()
+                },
+            )
+        })
+        .route("/market/{id}/depth", {
             let market = market.clone();
             get(move |Path(id): Path<String>| async move {
-                let id = id.parse::<u32>();
-                if id.is_err() {
-                    return (axum::http::StatusCode::BAD_REQUEST, "Invalid ID format")
-                        .into_response();
-                }
-                let id = id.unwrap();
-
-                match market.lock().await.items.get(&id) {
-                    Some(orderbook) => {
-                        let orders: Vec<Order> = orderbook.value().orders.clone().into_values().collect();
-                        Json(orders).into_response()
-                    },
-                    None => (axum::http::StatusCode::NOT_FOUND, "Item Type Not Found").into_response()
+                let Ok(id) = id.parse::<u32>() else {
+                    return (StatusCode::BAD_REQUEST, "Invalid ID format").into_response();
+                };
+                let id = TypeID::from(id);
+
+                let market = market.lock().await;
+                match market.items.get(&id) {
+                    Some(orderbook) => Json(MarketDepth {
+                        buy: orderbook.depth_curve(true),
+                        sell: orderbook.depth_curve(false),
+                    })
+                    .into_response(),
+                    None => (StatusCode::NOT_FOUND, "Item Type Not Found").into_response(),
                 }
             })
-        });
+        })
+        // book value of a region at each of its recent refreshes, oldest first
+        .route(
+            "/market/region/{id}/liquidity",
+            get(move |Path(id): Path<String>| async move {
+                let Some(id) = id
+                    .parse::<u32>()
+                    .ok()
+                    .and_then(|id| RegionID::try_from(id).ok())
+                else {
+                    return (StatusCode::BAD_REQUEST, "Invalid ID format").into_response();
+                };
+
+                match liquidity.samples(id) {
+                    Some(samples) => Json(samples).into_response(),
+                    None => (StatusCode::NOT_FOUND, "Region Not Found").into_response(),
+                }
+            }),
+        )
+        .route(
+            "/ws/market/{id}",
+            market_feed(broadcasts.regions, shutdown.clone()),
+        )
+        .route("/alerts", register_alert(broadcasts.alerts.clone()))
+        .route("/alerts/{id}", remove_alert(broadcasts.alerts))
+        // same orders as /market/{id} with the item attached, kept separate so the raw route
+        // never waits on a type lookup
+        .route("/market/{id}/named", named_orders(market.clone(), items));
 
     let tcp_listener = tokio::net::TcpListener::bind(format!(
         "0.0.0.0:{}",
@@ -46,5 +464,242 @@ pub async fn data_server(
     .await
     .unwrap();
 
-    axum::serve(tcp_listener, server).await
+    // stops accepting connections once shutdown fires, and returns after in-flight requests end
+    axum::serve(tcp_listener, server)
+        .with_graceful_shutdown(async move { shutdown_requested(&mut shutdown).await })
+        .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use esi::{
+        market::{MarketDiff, MarketOrderRange},
+        universe::{StationID, SystemID},
+    };
+    use futures::{StreamExt, TryStreamExt};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path},
+    };
+
+    fn mock_client(server: &MockServer) -> esi::ESIClient {
+        esi::ESIClient::in_memory("test", "test", 1).with_base_url(&server.uri())
+    }
+
+    fn page_with_orders(count: u64) -> MarketPage {
+        let issued = Utc::now();
+        let orders = (0..count)
+            .map(|id| Order {
+                id,
+                is_buy_order: id % 2 == 0,
+                price: 5.0 + id as f64,
+                issued,
+                expiry: issued + TimeDelta::days(90),
+                location_id: StationID::try_from(60_003_760).unwrap(),
+                system_id: SystemID::try_from(30_000_142).unwrap(),
+                min_volume: 1,
+                range: MarketOrderRange::Station,
+                volume_remain: 100,
+                volume_total: 100,
+            })
+            .collect();
+
+        MarketPage {
+            total: count as usize,
+            offset: 0,
+            orders,
+            last_modified: issued,
+            expires: issued,
+            last_updated: issued,
+            region_ids: vec![RegionID::try_from(10_000_002).unwrap()],
+        }
+    }
+
+    async fn streamed_json(page: MarketPage, chunk_orders: usize) -> serde_json::Value {
+        let chunks: Vec<Bytes> = page
+            .into_json_stream(chunk_orders)
+            .try_collect()
+            .await
+            .unwrap();
+        serde_json::from_slice(&chunks.concat()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_streamed_page_matches_serialized_page() {
+        for count in [0, 1, 7] {
+            let page = page_with_orders(count);
+            let expected = serde_json::to_value(&page).unwrap();
+
+            // chunks of 3 split 7 orders unevenly
+            assert_eq!(streamed_json(page, 3).await, expected);
+        }
+    }
+
+    fn refresh_event(diff: MarketDiff) -> RegionRefreshEvent {
+        RegionRefreshEvent {
+            id: RegionID::try_from(10_000_002).unwrap(),
+            expires: Utc::now(),
+            new_orders: 0,
+            modified_orders: 0,
+            removed_orders: 0,
+            total_orders: 0,
+            diff: Arc::new(diff),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_market_feed_only_sends_the_requested_item() {
+        let (events, _) = broadcast::channel(8);
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        let app = Router::new().route("/ws/market/{id}", market_feed(events.clone(), shutdown_rx));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let mut feed = Box::pin(crate::connect_feed(format!("ws://{addr}/ws/market/34")));
+        let update = tokio::spawn(async move { feed.next().await });
+        while events.receiver_count() == 0 {
+            tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+        }
+
+        let mut other_item = MarketDiff::new();
+        other_item
+            .new
+            .insert(TypeID::from(35), page_with_orders(1).orders);
+        events.send(refresh_event(other_item)).unwrap();
+        let mut tritanium = MarketDiff::new();
+        tritanium.removed.insert(TypeID::from(34), vec![7, 8]);
+        events.send(refresh_event(tritanium)).unwrap();
+
+        let update = update.await.unwrap().unwrap();
+        assert_eq!(update.type_id, TypeID::from(34));
+        assert_eq!(update.removed, vec![7, 8]);
+        assert!(update.new.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_alert_routes_register_and_remove() {
+        use reqwest::StatusCode;
+
+        let alerts = AlertRegistry::new(8);
+        let app = Router::new()
+            .route("/alerts", register_alert(alerts.clone()))
+            .route("/alerts/{id}", remove_alert(alerts.clone()));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        let client = reqwest::Client::new();
+
+        let response = client
+            .post(format!("http://{addr}/alerts"))
+            .json(&serde_json::json!({"type_id": 34, "is_buy": false, "threshold": 5.0}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::CREATED);
+        let created: AlertCreated = response.json().await.unwrap();
+
+        let response = client
+            .post(format!("http://{addr}/alerts"))
+            .json(&serde_json::json!({"type_id": 34, "is_buy": true, "threshold": -1.0}))
+            .send()
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::BAD_REQUEST);
+
+        for expected in [StatusCode::NO_CONTENT, StatusCode::NOT_FOUND] {
+            let response = client
+                .delete(format!("http://{addr}/alerts/{}", created.id))
+                .send()
+                .await
+                .unwrap();
+            assert_eq!(response.status(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_named_orders_release_the_market_during_lookup() {
+        use std::time::Duration;
+
+        let esi = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/types/34/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({
+                        "type_id": 34, "group_id": 18, "icon_id": 22, "market_group_id": 1857,
+                        "name": "Tritanium", "description": "",
+                    }))
+                    .set_delay(Duration::from_millis(500)),
+            )
+            .mount(&esi)
+            .await;
+        let items = Arc::new(Items::new(Arc::new(mock_client(&esi))));
+
+        let market = Arc::new(Mutex::new(Market::new()));
+        let mut book = OrderBook::new(TypeID::from(34));
+        for order in page_with_orders(2).orders {
+            book.orders.insert(order.id, order);
+        }
+        market.lock().await.items.insert(TypeID::from(34), book);
+
+        let app = Router::new().route("/market/{id}/named", named_orders(market.clone(), items));
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+
+        let request = tokio::spawn(reqwest::get(format!("http://{addr}/market/34/named")));
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // the type lookup is still in flight, and must not be holding the market
+        let locked = tokio::time::timeout(Duration::from_millis(100), market.lock()).await;
+        assert!(locked.is_ok());
+        drop(locked);
+
+        let named: serde_json::Value = request.await.unwrap().unwrap().json().await.unwrap();
+        assert_eq!(named["item"]["name"], "Tritanium");
+        assert_eq!(named["orders"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_health_waits_for_first_refresh() {
+        let now = Utc::now();
+        let intervals = DashMap::from_iter([(10000002, Some(now)), (10000043, None)]);
+
+        let health = health_report(&intervals, now);
+        assert!(!health.healthy);
+        assert_eq!(health.regions_total, 2);
+        assert_eq!(health.regions_refreshed, 1);
+
+        intervals.insert(10000043, Some(now));
+        assert!(health_report(&intervals, now).healthy);
+    }
+
+    #[test]
+    fn test_health_tolerates_a_few_unrefreshed_regions() {
+        let now = Utc::now();
+        let intervals: DashMap<u32, Option<DateTime<Utc>>> =
+            (10000001..=10000010).map(|id| (id, Some(now))).collect();
+        intervals.insert(10000010, None);
+
+        let health = health_report(&intervals, now);
+        assert!(health.healthy);
+        assert_eq!(health.regions_refreshed, 9);
+
+        intervals.insert(10000009, None);
+        assert!(!health_report(&intervals, now).healthy);
+    }
+
+    #[test]
+    fn test_health_reports_overdue_regions() {
+        let now = Utc::now();
+        let intervals = DashMap::from_iter([
+            (10000002, Some(now - TimeDelta::minutes(5))),
+            (10000043, Some(now - TimeDelta::hours(1))),
+        ]);
+
+        let health = health_report(&intervals, now);
+        assert!(!health.healthy);
+        assert_eq!(health.regions_overdue, vec![10000043]);
+    }
 }
@@ -0,0 +1,135 @@
+// Peak heap use of fetching a large region buffered vs streamed, against a local mock of ESI.
+// Run with `cargo bench -p esi --bench page_memory`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::{
+        Arc,
+        atomic::{AtomicUsize, Ordering},
+    },
+};
+
+use esi::{
+    ESIClient, ResponseCache,
+    market::Market,
+    universe::{Region, RegionID},
+};
+use wiremock::{
+    Mock, MockServer, ResponseTemplate,
+    matchers::{method, path, query_param},
+};
+
+const PAGES: usize = 20;
+const ORDERS_PER_PAGE: usize = 1000;
+
+/// Tracks the bytes currently allocated and the most that were at once.
+struct CountingAlloc;
+
+static CURRENT: AtomicUsize = AtomicUsize::new(0);
+static PEAK: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        let ptr = unsafe { System.alloc(layout) };
+        if !ptr.is_null() {
+            let current = CURRENT.fetch_add(layout.size(), Ordering::Relaxed) + layout.size();
+            PEAK.fetch_max(current, Ordering::Relaxed);
+        }
+        ptr
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) };
+        CURRENT.fetch_sub(layout.size(), Ordering::Relaxed);
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+fn page_body(page: usize) -> String {
+    let orders: Vec<serde_json::Value> = (0..ORDERS_PER_PAGE)
+        .map(|i| {
+            serde_json::json!({
+                "duration": 90,
+                "is_buy_order": i % 2 == 0,
+                "issued": "2025-01-01T00:00:00Z",
+                "location_id": 60_003_760,
+                "min_volume": 1,
+                "order_id": page * ORDERS_PER_PAGE + i,
+                "price": 5.25 + i as f64,
+                "range": "region",
+                "system_id": 30_000_142,
+                "type_id": 34 + (i % 500),
+                "volume_remain": 500,
+                "volume_total": 500,
+            })
+        })
+        .collect();
+    serde_json::to_string(&orders).unwrap()
+}
+
+async fn serve_pages() -> MockServer {
+    let server = MockServer::start().await;
+    // the first page is mounted last, so it only answers the request without a page
+    for page in (2..=PAGES).chain([1]) {
+        let response = ResponseTemplate::new(200)
+            .insert_header("last-modified", "Wed, 01 Jan 2025 11:00:00 GMT")
+            .insert_header("expires", "Wed, 01 Jan 2025 11:05:00 GMT")
+            .insert_header("x-pages", PAGES.to_string().as_str())
+            .set_body_raw(page_body(page), "application/json");
+        let mock = Mock::given(method("GET")).and(path("/markets/10000002/orders/"));
+        let mock = if page == 1 {
+            mock
+        } else {
+            mock.and(query_param("page", page.to_string().as_str()))
+        };
+        mock.respond_with(response).mount(&server).await;
+    }
+    server
+}
+
+/// Runs `fetch` and returns the peak heap growth during it, in bytes, and the orders fetched.
+async fn peak_during<F: Future<Output = Market>>(fetch: F) -> (usize, usize) {
+    let baseline = CURRENT.load(Ordering::Relaxed);
+    PEAK.store(baseline, Ordering::Relaxed);
+
+    let market = fetch.await;
+    let peak = PEAK.load(Ordering::Relaxed) - baseline;
+    let orders = market.items.iter().map(|book| book.orders.len()).sum();
+
+    (peak, orders)
+}
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() {
+    let server = serve_pages().await;
+    let client = Arc::new(
+        ESIClient::builder()
+            .component_name("page_memory_bench")
+            .cache_mode(ResponseCache::Disabled)
+            .base_url(&server.uri())
+            .build(),
+    );
+    let region = Region {
+        id: RegionID::try_from(10000002).unwrap(),
+        name: String::from("The Forge"),
+    };
+
+    // warm up the connection pool so neither run pays for it
+    Market::fetch_region(&region, client.clone()).await.unwrap();
+
+    let (buffered, orders) =
+        peak_during(async { Market::fetch_region(&region, client.clone()).await.unwrap() }).await;
+    let (streamed, _) = peak_during(async {
+        Market::fetch_region_streaming(&region, client.clone())
+            .await
+            .unwrap()
+    })
+    .await;
+
+    // the mock server shares the heap, so both include its copies of the pages
+    println!("{PAGES} pages, {orders} orders");
+    println!("fetch_region           peak {:>8} KiB", buffered / 1024);
+    println!("fetch_region_streaming peak {:>8} KiB", streamed / 1024);
+}
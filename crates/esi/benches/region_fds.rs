@@ -0,0 +1,141 @@
+// Peak open file descriptors and wall time of fetching many regions at once vs in waves, against
+// a local mock of ESI. Run with `cargo bench -p esi --bench region_fds`. Linux only, it counts
+// the entries of /proc/self/fd.
+
+use std::{
+    sync::{
+        Arc,
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+    },
+    time::{Duration, Instant},
+};
+
+use esi::{
+    ESIClient, ResponseCache,
+    market::{DEFAULT_MAX_CONCURRENT_REGIONS, Market},
+    universe::{Region, RegionID},
+};
+use wiremock::{
+    Mock, MockServer, Request, ResponseTemplate,
+    matchers::{method, path_regex},
+};
+
+const REGIONS: u32 = 64;
+const PAGES: usize = 5;
+const ORDERS_PER_PAGE: usize = 200;
+/// The connection limit data_fetcher runs with.
+const MAX_CONNECTIONS: usize = 32;
+
+fn page_body(region: u64, page: usize) -> String {
+    let orders: Vec<serde_json::Value> = (0..ORDERS_PER_PAGE)
+        .map(|i| {
+            serde_json::json!({
+                "duration": 90,
+                "is_buy_order": i % 2 == 0,
+                "issued": "2025-01-01T00:00:00Z",
+                "location_id": 60_003_760,
+                "min_volume": 1,
+                "order_id": (region * PAGES as u64 + page as u64) * ORDERS_PER_PAGE as u64
+                    + i as u64,
+                "price": 5.25 + i as f64,
+                "range": "region",
+                "system_id": 30_000_142,
+                "type_id": 34 + (i % 50),
+                "volume_remain": 500,
+                "volume_total": 500,
+            })
+        })
+        .collect();
+    serde_json::to_string(&orders).unwrap()
+}
+
+async fn serve_regions() -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("GET"))
+        .and(path_regex(r"^/markets/\d+/orders/$"))
+        .respond_with(|req: &Request| {
+            let region: u64 = req.url.path().split('/').nth(2).unwrap().parse().unwrap();
+            let page = req
+                .url
+                .query_pairs()
+                .find(|(key, _)| key == "page")
+                .map_or(1, |(_, page)| page.parse().unwrap());
+            ResponseTemplate::new(200)
+                .insert_header("last-modified", "Wed, 01 Jan 2025 11:00:00 GMT")
+                .insert_header("expires", "Wed, 01 Jan 2025 11:05:00 GMT")
+                .insert_header("x-pages", PAGES.to_string().as_str())
+                .set_body_raw(page_body(region, page), "application/json")
+        })
+        .mount(&server)
+        .await;
+    server
+}
+
+fn open_fds() -> usize {
+    std::fs::read_dir("/proc/self/fd").map_or(0, |fds| fds.count())
+}
+
+/// Fetches every region with at most `max_concurrent_regions` at once, through a fresh client so
+/// neither run starts with the other's pooled connections. Returns the peak fds and the time.
+async fn measure(
+    server: &MockServer,
+    regions: &[Region],
+    max_concurrent_regions: usize,
+) -> (usize, Duration) {
+    let client = Arc::new(
+        ESIClient::builder()
+            .component_name("region_fds_bench")
+            .max_connections(MAX_CONNECTIONS)
+            .cache_mode(ResponseCache::Disabled)
+            .base_url(&server.uri())
+            .build(),
+    );
+
+    let peak = Arc::new(AtomicUsize::new(open_fds()));
+    let done = Arc::new(AtomicBool::new(false));
+    let sampler = {
+        let (peak, done) = (peak.clone(), done.clone());
+        std::thread::spawn(move || {
+            while !done.load(Ordering::Relaxed) {
+                peak.fetch_max(open_fds(), Ordering::Relaxed);
+                std::thread::sleep(Duration::from_micros(200));
+            }
+        })
+    };
+
+    let started = Instant::now();
+    let market =
+        Market::fetch_all_regions_parallel(regions.to_vec(), client, max_concurrent_regions)
+            .await
+            .unwrap();
+    let elapsed = started.elapsed();
+    done.store(true, Ordering::Relaxed);
+    sampler.join().unwrap();
+
+    let orders: usize = market.items.iter().map(|book| book.orders.len()).sum();
+    assert_eq!(orders, REGIONS as usize * PAGES * ORDERS_PER_PAGE);
+    (peak.load(Ordering::Relaxed), elapsed)
+}
+
+#[tokio::main]
+async fn main() {
+    let server = serve_regions().await;
+    let regions: Vec<Region> = (10_000_001..=10_000_000 + REGIONS)
+        .map(|id| Region {
+            id: RegionID::try_from(id).unwrap(),
+            name: id.to_string(),
+        })
+        .collect();
+
+    let baseline = open_fds();
+    println!(
+        "{REGIONS} regions x {PAGES} pages, {MAX_CONNECTIONS} connections, {baseline} fds open before"
+    );
+    for max_concurrent_regions in [REGIONS as usize, DEFAULT_MAX_CONCURRENT_REGIONS, 1] {
+        let (peak, elapsed) = measure(&server, &regions, max_concurrent_regions).await;
+        println!(
+            "{max_concurrent_regions:>3} regions at once: peak {peak:>4} fds, {:>5} ms",
+            elapsed.as_millis()
+        );
+    }
+}
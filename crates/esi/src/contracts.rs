@@ -0,0 +1,288 @@
+use chrono::{DateTime, Utc};
+use reqwest::header::HeaderMap;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::sync::Arc;
+
+use crate::{
+    ESIClient,
+    market::{PageLimit, cache_times, page_orders},
+    page_count,
+    universe::{RegionID, StationID, TypeID},
+};
+
+/// What a contract asks of whoever accepts it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Deserialize, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ContractType {
+    /// Items for ISK, or for other items.
+    ItemExchange,
+    /// Items sold to the highest bidder, see [`PublicContracts::fetch_bids`].
+    Auction,
+    /// Hauling a package from `start_location_id` to `end_location_id`.
+    Courier,
+    /// Anything else ESI reports, such as loans, which the game no longer creates.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A public contract as ESI lists it. Which of the optional fields are set depends on the
+/// contract's [`ContractType`].
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Contract {
+    pub contract_id: u32,
+    #[serde(rename = "type")]
+    pub kind: ContractType,
+    pub issuer_id: u32,
+    pub issuer_corporation_id: u32,
+    pub for_corporation: Option<bool>,
+    pub title: Option<String>,
+    pub date_issued: DateTime<Utc>,
+    pub date_expired: DateTime<Utc>,
+    pub start_location_id: Option<StationID>,
+    pub end_location_id: Option<StationID>,
+    /// The asking price of an item exchange, or the starting bid of an auction.
+    pub price: Option<f64>,
+    /// What an auction sells for straight away.
+    pub buyout: Option<f64>,
+    /// Paid to the hauler of a courier contract.
+    pub reward: Option<f64>,
+    /// Put up by the hauler of a courier contract, lost if the package isn't delivered.
+    pub collateral: Option<f64>,
+    /// Days a courier contract has to be completed in, once accepted.
+    pub days_to_complete: Option<u32>,
+    /// Volume of the contract's items, in m³.
+    pub volume: Option<f64>,
+}
+
+/// An item in a public contract.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ContractItem {
+    pub record_id: u64,
+    pub type_id: TypeID,
+    pub quantity: i32,
+    /// Whether the issuer gives the item, rather than asks for it.
+    pub is_included: bool,
+    /// Set for items that aren't stackable, such as ships and blueprints.
+    pub item_id: Option<u64>,
+    pub is_blueprint_copy: Option<bool>,
+    pub material_efficiency: Option<i32>,
+    pub time_efficiency: Option<i32>,
+    pub runs: Option<i32>,
+}
+
+/// A bid on a public auction.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ContractBid {
+    pub bid_id: u32,
+    pub amount: f64,
+    pub date_bid: DateTime<Utc>,
+}
+
+/// The public contracts of one region.
+#[derive(Clone, Debug, Serialize)]
+pub struct PublicContracts {
+    pub region_id: RegionID,
+    pub contracts: Vec<Contract>,
+    pub last_modified: DateTime<Utc>,
+    pub expires: DateTime<Utc>,
+}
+
+impl PublicContracts {
+    /// Fetches every public contract in a region.
+    pub async fn fetch_region(region_id: RegionID, client: Arc<ESIClient>) -> anyhow::Result<Self> {
+        Self::fetch_region_with_limit(region_id, client, PageLimit::default()).await
+    }
+
+    /// Like [`PublicContracts::fetch_region`], but with `page_limit` instead of the default
+    /// page cap.
+    pub async fn fetch_region_with_limit(
+        region_id: RegionID,
+        client: Arc<ESIClient>,
+        page_limit: PageLimit,
+    ) -> anyhow::Result<Self> {
+        let url = format!("/contracts/public/{region_id}/");
+        let (headers, contracts) = fetch_pages(client, &url, page_limit).await?;
+        let (last_modified, expires) = cache_times(&headers)?;
+
+        Ok(PublicContracts {
+            region_id,
+            contracts,
+            last_modified,
+            expires,
+        })
+    }
+
+    /// Fetches the items of a public contract. ESI answers 404 once the contract has expired or
+    /// been accepted.
+    pub async fn fetch_items(
+        contract_id: u32,
+        client: Arc<ESIClient>,
+    ) -> anyhow::Result<Vec<ContractItem>> {
+        let url = format!("/contracts/public/items/{contract_id}/");
+        let (_, items) = fetch_pages(client, &url, PageLimit::default()).await?;
+        Ok(items)
+    }
+
+    /// Fetches the bids on a public auction, in the order ESI lists them.
+    pub async fn fetch_bids(
+        contract_id: u32,
+        client: Arc<ESIClient>,
+    ) -> anyhow::Result<Vec<ContractBid>> {
+        let url = format!("/contracts/public/bids/{contract_id}/");
+        let (_, bids) = fetch_pages(client, &url, PageLimit::default()).await?;
+        Ok(bids)
+    }
+
+    /// The contracts of one type.
+    pub fn of_type(&self, kind: ContractType) -> impl Iterator<Item = &Contract> {
+        self.contracts
+            .iter()
+            .filter(move |contract| contract.kind == kind)
+    }
+}
+
+/// Fetches every page of `url` the way [`crate::market::Market::fetch_region`] pages through
+/// orders, returning the first page's headers and the entries of all pages in page order.
+async fn fetch_pages<T: DeserializeOwned + Send + 'static>(
+    client: Arc<ESIClient>,
+    url: &str,
+    page_limit: PageLimit,
+) -> anyhow::Result<(HeaderMap, Vec<T>)> {
+    let first_page = client.esi_get(url).await?;
+    let headers = first_page.headers().clone();
+    let num_pages = page_limit.pages_to_fetch(page_count(&headers))?;
+    let mut entries: Vec<T> = page_orders(url, first_page).await?;
+
+    let mut handles = Vec::new();
+    for page in 2..=num_pages {
+        let client = client.clone();
+        let url = format!("{url}?page={page}");
+        handles.push(tokio::spawn(async move {
+            let response = client.esi_get(&url).await?;
+            page_orders::<T>(&url, response).await
+        }));
+    }
+
+    for page in futures::future::try_join_all(handles).await? {
+        entries.extend(page?);
+    }
+
+    Ok((headers, entries))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::TimeZone;
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{method, path, query_param},
+    };
+
+    fn mock_client(server: &MockServer) -> ESIClient {
+        let http = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        ESIClient::with_client(http, "test", "test", 4).with_base_url(&server.uri())
+    }
+
+    #[tokio::test]
+    async fn test_fetch_region_pages_contracts() {
+        let server = MockServer::start().await;
+        let headers = |response: ResponseTemplate| {
+            response
+                .insert_header("last-modified", "Wed, 01 Jan 2025 11:00:00 GMT")
+                .insert_header("expires", "Wed, 01 Jan 2025 11:30:00 GMT")
+                .insert_header("x-pages", "2")
+        };
+        Mock::given(method("GET"))
+            .and(path("/contracts/public/10000002/"))
+            .and(query_param("page", "2"))
+            .respond_with(
+                headers(ResponseTemplate::new(200)).set_body_json(serde_json::json!([{
+                    "contract_id": 3, "type": "courier", "issuer_id": 90000001,
+                    "issuer_corporation_id": 98000001, "date_issued": "2025-01-01T09:00:00Z",
+                    "date_expired": "2025-01-15T09:00:00Z", "start_location_id": 60003760,
+                    "end_location_id": 1035466617946u64, "reward": 25_000_000.0,
+                    "collateral": 500_000_000.0, "days_to_complete": 3, "volume": 12500.0,
+                }])),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/contracts/public/10000002/"))
+            .respond_with(
+                headers(ResponseTemplate::new(200)).set_body_json(serde_json::json!([
+                    {"contract_id": 1, "type": "item_exchange", "issuer_id": 90000001,
+                     "issuer_corporation_id": 98000001, "for_corporation": false,
+                     "title": "Rifter", "date_issued": "2025-01-01T08:00:00Z",
+                     "date_expired": "2025-01-29T08:00:00Z", "start_location_id": 60003760,
+                     "price": 450_000.0, "volume": 27289.0},
+                    {"contract_id": 2, "type": "auction", "issuer_id": 90000002,
+                     "issuer_corporation_id": 98000002, "date_issued": "2025-01-01T10:00:00Z",
+                     "date_expired": "2025-01-08T10:00:00Z", "price": 1_000_000.0,
+                     "buyout": 5_000_000.0, "volume": 0.01},
+                    {"contract_id": 4, "type": "loan", "issuer_id": 90000003,
+                     "issuer_corporation_id": 98000003, "date_issued": "2025-01-01T10:00:00Z",
+                     "date_expired": "2025-01-08T10:00:00Z"},
+                ])),
+            )
+            .mount(&server)
+            .await;
+
+        let region_id = RegionID::try_from(10000002).unwrap();
+        let contracts = PublicContracts::fetch_region(region_id, Arc::new(mock_client(&server)))
+            .await
+            .unwrap();
+
+        let ids: Vec<u32> = contracts.contracts.iter().map(|c| c.contract_id).collect();
+        assert_eq!(ids, [1, 2, 4, 3]);
+        assert_eq!(
+            contracts.expires,
+            Utc.with_ymd_and_hms(2025, 1, 1, 11, 30, 0).unwrap()
+        );
+
+        let courier: Vec<&Contract> = contracts.of_type(ContractType::Courier).collect();
+        assert_eq!(courier.len(), 1);
+        assert_eq!(courier[0].collateral, Some(500_000_000.0));
+        assert_eq!(
+            courier[0].end_location_id,
+            Some(StationID::try_from(1035466617946).unwrap())
+        );
+        let auction = contracts.of_type(ContractType::Auction).next().unwrap();
+        assert_eq!(auction.buyout, Some(5_000_000.0));
+        assert_eq!(contracts.contracts[2].kind, ContractType::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_items_and_bids() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/contracts/public/items/2/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"record_id": 11, "type_id": 587, "quantity": 1, "is_included": true,
+                 "item_id": 1_000_000_000_001u64},
+                {"record_id": 12, "type_id": 691, "quantity": 1, "is_included": true,
+                 "is_blueprint_copy": true, "material_efficiency": 10, "time_efficiency": 20,
+                 "runs": 5},
+            ])))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/contracts/public/bids/2/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"bid_id": 7, "amount": 1_250_000.0, "date_bid": "2025-01-02T12:00:00Z"},
+            ])))
+            .mount(&server)
+            .await;
+
+        let client = Arc::new(mock_client(&server));
+        let items = PublicContracts::fetch_items(2, client.clone())
+            .await
+            .unwrap();
+        assert_eq!(items.len(), 2);
+        assert_eq!(items[0].type_id, TypeID::from(587));
+        assert_eq!(items[1].runs, Some(5));
+
+        let bids = PublicContracts::fetch_bids(2, client).await.unwrap();
+        assert_eq!(bids[0].amount, 1_250_000.0);
+    }
+}
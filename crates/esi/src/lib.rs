@@ -1,159 +1,787 @@
 use base64::prelude::*;
+use dashmap::DashMap;
 use http_cache_reqwest::{
-    CACacheManager, Cache, CacheMode, CacheOptions, HttpCache, HttpCacheOptions,
+    CACacheManager, Cache, CacheManager, CacheMode, CacheOptions, HttpCache, HttpCacheOptions,
+    MokaManager,
 };
-use macros::ESI_URL;
-use reqwest::header::{AUTHORIZATION, CONTENT_TYPE};
-use reqwest::{Response, header::USER_AGENT};
+use macros::{ESI_URL, LOGIN_URL};
+use reqwest::header::{AUTHORIZATION, CONTENT_TYPE, ETAG, HeaderMap, HeaderValue, IF_NONE_MATCH};
+use reqwest::{Method, Response, StatusCode, header::USER_AGENT};
 use reqwest_middleware::{ClientBuilder, ClientWithMiddleware, Error as MiddlewareError};
-use std::clone;
+use serde::{Serialize, de::DeserializeOwned};
 use std::{
-    sync::Arc,
+    error::Error,
+    fmt,
+    path::PathBuf,
+    str::FromStr,
+    sync::{
+        Arc,
+        atomic::{AtomicU32, AtomicU64, Ordering},
+    },
     time::{Duration, SystemTime, UNIX_EPOCH},
 };
-use tokio::sync::{Mutex, Semaphore};
+use tokio::sync::Semaphore;
 use tokio::time::sleep;
 
+pub mod contracts;
 mod macros;
 pub mod market;
 pub mod universe;
 
 #[derive(Clone, Debug)]
 pub struct ESIClient {
-    errors: Arc<Mutex<u32>>,
-    error_timeout: Arc<Mutex<u32>>,
+    /// ESI's error budget as of the last response that reported it.
+    errors: Arc<AtomicU32>,
+    /// When ESI's error window resets, in milliseconds since the Unix epoch.
+    error_reset_at: Arc<AtomicU64>,
     client: ClientWithMiddleware,
     component_name: String,
     platform_name: String,
     connect_pool: Arc<Semaphore>, // we need this to not run out of open fd's
     auth_tok: Option<String>,
+    downtime_backoff: Duration,
+    retry_policy: RetryPolicy,
+    /// How long a single attempt at a request may wait for ESI to answer.
+    timeout: Option<Duration>,
+    base_url: String,
+    login_url: String,
+    datasource: Datasource,
+    /// The last ETag seen for each URL fetched through [`ESIClient::esi_get_if_modified`].
+    etags: Option<Arc<DashMap<String, String>>>,
+    /// Overrides [`default_cache_dir`] for the files this client's users cache.
+    cache_dir: Option<PathBuf>,
+}
+
+/// What [`ESIClient::esi_get_if_modified`] got back.
+#[derive(Debug)]
+pub enum EsiResponse {
+    Modified(Response),
+    /// ESI answered 304, the body last seen for the URL is still current. The response carries
+    /// no body, only the refreshed headers such as `expires`.
+    NotModified(Response),
+}
+
+/// Which EVE server ESI answers for, sent as the `datasource` query parameter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Datasource {
+    /// The live server, what ESI answers for when no datasource is given.
+    #[default]
+    Tranquility,
+    /// The test server.
+    Singularity,
+}
+
+impl Datasource {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Datasource::Tranquility => "tranquility",
+            Datasource::Singularity => "singularity",
+        }
+    }
+}
+
+impl fmt::Display for Datasource {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Datasource {
+    type Err = UnknownDatasourceError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "tranquility" => Ok(Datasource::Tranquility),
+            "singularity" => Ok(Datasource::Singularity),
+            _ => Err(UnknownDatasourceError(String::from(s))),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct UnknownDatasourceError(pub String);
+
+impl fmt::Display for UnknownDatasourceError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "unknown ESI datasource {:?}", self.0)
+    }
+}
+
+impl Error for UnknownDatasourceError {}
+
+/// How long each attempt at a request may take, body included, unless changed with
+/// [`ESIClient::set_timeout`].
+pub const DEFAULT_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How [`ESIClient`] retries a request that failed for a transient reason: the connection failing
+/// or timing out, or, with [`RetryPolicy::retry_server_errors`], ESI answering with a 500-504.
+/// Client errors are never retried, they count against ESI's error limit.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct RetryPolicy {
+    /// Attempts per request, counting the first one.
+    pub max_attempts: u32,
+    /// Delay before the first retry, doubling with each retry after it.
+    pub base_delay: Duration,
+    /// Upper bound of the delay between two attempts.
+    pub max_delay: Duration,
+    /// Waits a random time between half and all of each delay, so clients that failed together
+    /// don't all retry together.
+    pub jitter: bool,
+    /// Also retries when ESI answers with a 500-504, not only when the request itself fails.
+    pub retry_server_errors: bool,
+}
+
+impl Default for RetryPolicy {
+    /// A single immediate retry, for failed requests only.
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::ZERO,
+            max_delay: Duration::from_secs(30),
+            jitter: false,
+            retry_server_errors: false,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Delay before retry number `retry`, starting at 1.
+    pub fn delay(&self, retry: u32) -> Duration {
+        let delay = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(retry.saturating_sub(1)))
+            .min(self.max_delay);
+
+        if self.jitter && !delay.is_zero() {
+            delay.mul_f64(rand::random_range(0.5..=1.0))
+        } else {
+            delay
+        }
+    }
+
+    fn is_transient(&self, status: StatusCode) -> bool {
+        self.retry_server_errors && (500..=504).contains(&status.as_u16())
+    }
+}
+
+/// A failed ESI request.
+#[derive(Debug)]
+pub enum EsiError {
+    /// The request to `url`, a path relative to the ESI base URL, failed or got an error status.
+    Request {
+        url: String,
+        source: MiddlewareError,
+    },
+    /// ESI answered `url` with something other than the JSON that was expected.
+    /// `snippet` holds the start of the body.
+    Decode {
+        url: String,
+        reason: String,
+        snippet: String,
+    },
+    /// The request to `url` ran out of the client's [`ESIClient::set_timeout`], either on every
+    /// attempt the retry policy allowed, or while its body was being read.
+    Timeout { url: String },
+}
+
+impl EsiError {
+    /// The status ESI answered with, if it answered at all.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            EsiError::Request { source, .. } => source.status(),
+            EsiError::Decode { .. } | EsiError::Timeout { .. } => None,
+        }
+    }
+}
+
+impl fmt::Display for EsiError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            EsiError::Request { url, source } => write!(f, "Request to {url} failed: {source}"),
+            EsiError::Decode {
+                url,
+                reason,
+                snippet,
+            } => write!(
+                f,
+                "Unexpected response from {url}: {reason}, body starts with {snippet:?}"
+            ),
+            EsiError::Timeout { url } => write!(f, "Request to {url} timed out"),
+        }
+    }
+}
+
+impl Error for EsiError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            EsiError::Request { source, .. } => Some(source),
+            EsiError::Decode { .. } | EsiError::Timeout { .. } => None,
+        }
+    }
+}
+
+/// The plain HTTP client under every [`ESIClient`] built by [`ESIClientBuilder`].
+///
+/// Responses are decompressed as they arrive, before the cache middleware sees them, so cached
+/// bodies are always plain JSON. reqwest only sends `Accept-Encoding` and decodes the body when
+/// the header isn't set by hand, so requests must leave it alone.
+fn http_client() -> reqwest::Client {
+    reqwest::Client::builder()
+        .pool_max_idle_per_host(32)
+        .pool_idle_timeout(Duration::from_secs(15))
+        .gzip(true)
+        .brotli(true)
+        .deflate(true)
+        .build()
+        .unwrap()
+}
+
+/// The pooled client used by [`ESIClient::new`], caching responses in `manager`.
+fn cached_client<T: CacheManager>(manager: T) -> ClientWithMiddleware {
+    ClientBuilder::new(http_client())
+        .with(Cache(HttpCache {
+            mode: CacheMode::Default,
+            manager,
+            options: HttpCacheOptions {
+                cache_key: None,
+                cache_mode_fn: None,
+                cache_options: Some(CacheOptions {
+                    shared: true,
+                    cache_heuristic: 0.01,
+                    ignore_cargo_cult: false,
+                    immutable_min_time_to_live: Duration::from_secs(24 * 3600),
+                }),
+                cache_bust: None,
+                cache_status_headers: true,
+            },
+        }))
+        .build() // cursed
+}
+
+/// Where cached files go when a client doesn't say otherwise: `EVERTERM_CACHE_DIR` if it is
+/// set, else `everterm` in the user's cache directory.
+pub fn default_cache_dir() -> PathBuf {
+    match std::env::var_os("EVERTERM_CACHE_DIR") {
+        Some(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => dirs::cache_dir()
+            .unwrap_or_else(std::env::temp_dir)
+            .join("everterm"),
+    }
+}
+
+/// Wraps a failure of the request to `url`, telling timeouts apart from other failures.
+pub(crate) fn request_failed(url: &str, source: MiddlewareError) -> EsiError {
+    let timed_out = match &source {
+        MiddlewareError::Reqwest(err) => err.is_timeout(),
+        MiddlewareError::Middleware(err) => err.chain().any(|cause| {
+            cause
+                .downcast_ref::<reqwest::Error>()
+                .is_some_and(reqwest::Error::is_timeout)
+        }),
+    };
+
+    let url = String::from(url);
+    if timed_out {
+        EsiError::Timeout { url }
+    } else {
+        EsiError::Request { url, source }
+    }
+}
+
+/// How much of an unexpected body is kept in [`EsiError::Decode`].
+const DECODE_SNIPPET_CHARS: usize = 200;
+
+/// Reads the body of a response to `url`, failing if it isn't labelled as JSON.
+pub(crate) async fn read_json_body(url: &str, response: Response) -> Result<Vec<u8>, EsiError> {
+    let content_type = response
+        .headers()
+        .get(CONTENT_TYPE)
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+
+    let body = response
+        .bytes()
+        .await
+        .map_err(|err| request_failed(url, err.into()))?;
+
+    match content_type {
+        Some(content_type) if !content_type.contains("json") => Err(decode_error(
+            url,
+            format!("expected JSON, got {content_type}"),
+            &body,
+        )),
+        _ => Ok(body.into()),
+    }
+}
+
+/// Parses a body read by [`read_json_body`].
+pub(crate) fn parse_json<T: DeserializeOwned>(url: &str, body: &[u8]) -> Result<T, EsiError> {
+    serde_json::from_slice(body).map_err(|err| decode_error(url, err.to_string(), body))
+}
+
+/// Reads and parses the JSON body of a response to `url`.
+pub(crate) async fn decode_json<T: DeserializeOwned>(
+    url: &str,
+    response: Response,
+) -> Result<T, EsiError> {
+    let body = read_json_body(url, response).await?;
+    parse_json(url, &body)
+}
+
+fn decode_error(url: &str, reason: String, body: &[u8]) -> EsiError {
+    EsiError::Decode {
+        url: String::from(url),
+        reason,
+        snippet: String::from_utf8_lossy(body)
+            .chars()
+            .take(DECODE_SNIPPET_CHARS)
+            .collect(),
+    }
+}
+
+/// Reads the `x-pages` header of a paginated response. Single-page responses may leave it out,
+/// so a missing, unreadable or zero count is taken as one page.
+pub(crate) fn page_count(headers: &HeaderMap) -> usize {
+    headers
+        .get("x-pages")
+        .and_then(|pages| pages.to_str().ok()?.parse().ok())
+        .unwrap_or(1)
+        .max(1)
+}
+
+/// How long to wait after a 5xx before handing the error back. 503 and 504 usually mean ESI is
+/// down (e.g. daily downtime at 11:00 UTC), so those wait out `downtime_backoff`; other server
+/// errors are returned straight away.
+fn server_error_backoff(status: StatusCode, downtime_backoff: Duration) -> Option<Duration> {
+    match status {
+        StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT => Some(downtime_backoff),
+        _ => None,
+    }
+}
+
+/// Where an [`ESIClient`] keeps its HTTP cache.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ResponseCache {
+    /// In the user's cache directory, kept across restarts.
+    #[default]
+    Disk,
+    /// In memory only, so every client starts out with an empty cache.
+    Memory,
+    /// Every request goes out to ESI.
+    Disabled,
+}
+
+/// Builds an [`ESIClient`] one named setting at a time, starting from [`ESIClient::builder`].
+/// Settings left out keep the same defaults as [`ESIClient::new`].
+#[derive(Clone, Debug)]
+pub struct ESIClientBuilder {
+    component_name: String,
+    platform_name: String,
+    max_connections: usize,
+    auth_token: Option<String>,
+    cache_mode: ResponseCache,
+    base_url: String,
+    retry_policy: RetryPolicy,
+    timeout: Option<Duration>,
+    cache_dir: Option<PathBuf>,
+}
+
+impl Default for ESIClientBuilder {
+    fn default() -> Self {
+        ESIClientBuilder {
+            component_name: String::from("esi"),
+            platform_name: String::from(std::env::consts::OS),
+            max_connections: 32,
+            auth_token: None,
+            cache_mode: ResponseCache::default(),
+            base_url: String::from(ESI_URL),
+            retry_policy: RetryPolicy::default(),
+            timeout: Some(DEFAULT_TIMEOUT),
+            cache_dir: None,
+        }
+    }
+}
+
+impl ESIClientBuilder {
+    /// Names the program in the user agent. Defaults to `esi`.
+    pub fn component_name(mut self, component_name: &str) -> Self {
+        self.component_name = String::from(component_name);
+        self
+    }
+
+    /// The platform reported in the user agent. Defaults to the OS it was built for.
+    pub fn platform(mut self, platform_name: &str) -> Self {
+        self.platform_name = String::from(platform_name);
+        self
+    }
+
+    /// Most requests in flight at once. Defaults to 32.
+    pub fn max_connections(mut self, max_connections: usize) -> Self {
+        self.max_connections = max_connections;
+        self
+    }
+
+    /// An SSO access token to send with requests, instead of loading one through
+    /// [`ESIClient::load_auth_tok`].
+    pub fn auth_token(mut self, auth_token: &str) -> Self {
+        self.auth_token = Some(String::from(auth_token));
+        self
+    }
+
+    pub fn cache_mode(mut self, cache_mode: ResponseCache) -> Self {
+        self.cache_mode = cache_mode;
+        self
+    }
+
+    /// See [`ESIClient::set_base_url`].
+    pub fn base_url(mut self, base_url: &str) -> Self {
+        self.base_url = String::from(base_url);
+        self
+    }
+
+    /// See [`ESIClient::set_retry_policy`].
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// See [`ESIClient::set_timeout`].
+    pub fn timeout(mut self, timeout: Option<Duration>) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// See [`ESIClient::set_cache_dir`].
+    pub fn cache_dir(mut self, cache_dir: impl Into<PathBuf>) -> Self {
+        self.cache_dir = Some(cache_dir.into());
+        self
+    }
+
+    pub fn build(self) -> ESIClient {
+        let http = match self.cache_mode {
+            ResponseCache::Disk => cached_client(CACacheManager::default()),
+            ResponseCache::Memory => cached_client(MokaManager::default()),
+            ResponseCache::Disabled => ClientBuilder::new(http_client()).build(),
+        };
+
+        let mut client = ESIClient::with_client(
+            http,
+            &self.component_name,
+            &self.platform_name,
+            self.max_connections,
+        );
+        client.auth_tok = self.auth_token;
+        client.base_url = self.base_url;
+        client.retry_policy = self.retry_policy;
+        client.timeout = self.timeout;
+        client.cache_dir = self.cache_dir;
+        client
+    }
 }
 
 impl ESIClient {
     pub fn new(component_name: &str, platform_name: &str, max_sem: usize) -> Self {
+        ESIClient::builder()
+            .component_name(component_name)
+            .platform(platform_name)
+            .max_connections(max_sem)
+            .build()
+    }
+
+    /// Like [`ESIClient::new`], but the HTTP cache only lives in memory, so nothing is written to
+    /// disk and every client starts out with an empty cache.
+    pub fn in_memory(component_name: &str, platform_name: &str, max_sem: usize) -> Self {
+        ESIClient::builder()
+            .component_name(component_name)
+            .platform(platform_name)
+            .max_connections(max_sem)
+            .cache_mode(ResponseCache::Memory)
+            .build()
+    }
+
+    pub fn builder() -> ESIClientBuilder {
+        ESIClientBuilder::default()
+    }
+
+    /// Like [`ESIClient::new`], but sends requests through `client` instead of the default
+    /// pooled client with its on-disk HTTP cache.
+    pub fn with_client(
+        client: ClientWithMiddleware,
+        component_name: &str,
+        platform_name: &str,
+        max_sem: usize,
+    ) -> Self {
         ESIClient {
-            errors: Arc::new(Mutex::new(100)),
-            error_timeout: Arc::new(Mutex::new(0)),
-            client: ClientBuilder::new(
-                reqwest::Client::builder()
-                    .pool_max_idle_per_host(32)
-                    .pool_idle_timeout(Duration::from_secs(15))
-                    .build()
-                    .unwrap(),
-            )
-            .with(Cache(HttpCache {
-                mode: CacheMode::Default,
-                manager: CACacheManager::default(),
-                options: HttpCacheOptions {
-                    cache_key: None,
-                    cache_mode_fn: None,
-                    cache_options: Some(CacheOptions {
-                        shared: true,
-                        cache_heuristic: 0.01,
-                        ignore_cargo_cult: false,
-                        immutable_min_time_to_live: Duration::from_secs(24 * 3600),
-                    }),
-                    cache_bust: None,
-                    cache_status_headers: true,
-                },
-            }))
-            .build(), // cursed
+            errors: Arc::new(AtomicU32::new(100)),
+            error_reset_at: Arc::new(AtomicU64::new(0)),
+            client,
             component_name: String::from(component_name),
             platform_name: String::from(platform_name),
             connect_pool: Arc::new(Semaphore::new(max_sem)),
             auth_tok: None,
+            downtime_backoff: Duration::from_secs(30),
+            retry_policy: RetryPolicy::default(),
+            timeout: Some(DEFAULT_TIMEOUT),
+            base_url: String::from(ESI_URL),
+            login_url: String::from(LOGIN_URL),
+            datasource: Datasource::default(),
+            etags: None,
+            cache_dir: None,
         }
     }
 
-    pub async fn esi_get(&self, url: &str) -> Result<Response, MiddlewareError> {
-        let permit = self.connect_pool.acquire().await.unwrap();
+    /// Sets how long requests wait after a 503 or 504 before returning the error.
+    /// Defaults to 30 seconds.
+    pub fn set_downtime_backoff(&mut self, downtime_backoff: Duration) {
+        self.downtime_backoff = downtime_backoff;
+    }
+
+    /// Sets how failed requests are retried. Defaults to [`RetryPolicy::default`].
+    pub fn set_retry_policy(&mut self, retry_policy: RetryPolicy) {
+        self.retry_policy = retry_policy;
+    }
+
+    /// Sets how long each attempt at a request may take, from connecting until its body has been
+    /// read. An attempt that times out before ESI answers is retried like a server error, one
+    /// that times out while its body is read fails with [`EsiError::Timeout`]. `None` waits for
+    /// as long as the connection stays open. Defaults to [`DEFAULT_TIMEOUT`].
+    pub fn set_timeout(&mut self, timeout: Option<Duration>) {
+        self.timeout = timeout;
+    }
+
+    /// Makes [`ESIClient::esi_get_if_modified`] remember ETags and send conditional requests.
+    /// Off by default, since callers have to keep the bodies around to make use of a 304.
+    pub fn enable_etags(&mut self) {
+        self.etags.get_or_insert_with(Default::default);
+    }
+
+    /// Sets the URL that request paths are appended to. Defaults to the live ESI.
+    pub fn set_base_url(&mut self, base_url: &str) {
+        self.base_url = String::from(base_url);
+    }
+
+    /// Sets the URL of the SSO server [`ESIClient::load_auth_tok`] refreshes tokens through.
+    /// Defaults to EVE's login server.
+    pub fn set_login_url(&mut self, login_url: &str) {
+        self.login_url = String::from(login_url);
+    }
+
+    /// Like [`ESIClient::set_base_url`], for building a client in one expression.
+    pub fn with_base_url(mut self, base_url: &str) -> Self {
+        self.set_base_url(base_url);
+        self
+    }
+
+    /// Sets which server requests are answered for. Defaults to [`Datasource::Tranquility`],
+    /// which leaves the `datasource` parameter off since it is ESI's default too.
+    pub fn set_datasource(&mut self, datasource: Datasource) {
+        self.datasource = datasource;
+    }
+
+    /// Sets the directory the universe caches of this client are kept in, instead of
+    /// [`default_cache_dir`]. The HTTP cache of [`ResponseCache::Disk`] isn't affected.
+    pub fn set_cache_dir(&mut self, cache_dir: impl Into<PathBuf>) {
+        self.cache_dir = Some(cache_dir.into());
+    }
+
+    /// The directory the universe caches of this client are kept in.
+    pub fn cache_dir(&self) -> PathBuf {
+        self.cache_dir.clone().unwrap_or_else(default_cache_dir)
+    }
+
+    /// Errors ESI will still accept in its current window, as of the last response that said.
+    pub fn error_budget(&self) -> u32 {
+        self.errors.load(Ordering::Relaxed)
+    }
+
+    /// Sends a GET request for `url`, a path relative to the ESI base URL.
+    pub async fn esi_get(&self, url: &str) -> Result<Response, EsiError> {
+        self.send(Method::GET, url, HeaderMap::new(), None, self.timeout)
+            .await
+    }
+
+    /// Like [`ESIClient::esi_get`], but each attempt waits at most `timeout` instead of the
+    /// client's [`ESIClient::set_timeout`].
+    pub async fn esi_get_with_timeout(
+        &self,
+        url: &str,
+        timeout: Duration,
+    ) -> Result<Response, EsiError> {
+        self.send(Method::GET, url, HeaderMap::new(), None, Some(timeout))
+            .await
+    }
+
+    /// Like [`ESIClient::esi_get`], but also decodes the JSON body.
+    pub async fn esi_get_json<T: DeserializeOwned>(&self, url: &str) -> Result<T, EsiError> {
+        let response = self.esi_get(url).await?;
+        decode_json(url, response).await
+    }
 
+    /// Like [`ESIClient::esi_get`], but once ETags are enabled through
+    /// [`ESIClient::enable_etags`], sends the last ETag seen for `url` along and reports a 304
+    /// as [`EsiResponse::NotModified`]. Without ETags every response is
+    /// [`EsiResponse::Modified`].
+    pub async fn esi_get_if_modified(&self, url: &str) -> Result<EsiResponse, EsiError> {
+        let mut headers = HeaderMap::new();
+        if let Some(etag) = self.etags.as_ref().and_then(|etags| etags.get(url))
+            && let Ok(etag) = HeaderValue::from_str(&etag)
         {
-            // this blocks everything cuz it locks and doesnt unlock until it waits out the timer
-            let errors = self.errors.lock().await;
-            if *errors <= 10 {
-                self.await_esi_timeout().await;
-            }
+            headers.insert(IF_NONE_MATCH, etag);
         }
 
-        let mut req = self.client.get([ESI_URL, url].join(""))
-            .header(USER_AGENT, format!("{}; component of EvERTerm/0.0.1 (0@x4132.dev; +https://github.com/x4132/everterm; discord:msvcredist2022; eve:Charles Helugo) on {}", self.component_name, self.platform_name));
+        let response = self
+            .send(Method::GET, url, headers, None, self.timeout)
+            .await?;
 
-        if self.auth_tok_valid().await {
-            req = req.header(AUTHORIZATION, format!("Bearer {}", self.auth_tok.clone().unwrap_or(String::from("NOACL"))));
+        if response.status() == StatusCode::NOT_MODIFIED {
+            return Ok(EsiResponse::NotModified(response));
+        }
+        if let Some(etags) = &self.etags {
+            let etag = response.headers().get(ETAG);
+            match etag.and_then(|etag| etag.to_str().ok()) {
+                Some(etag) => {
+                    etags.insert(String::from(url), String::from(etag));
+                }
+                None => {
+                    etags.remove(url);
+                }
+            }
         }
 
-        // send first request via middleware and map all errors into MiddlewareError
-        let mut result: Result<Response, MiddlewareError> = {
-            let first = req.try_clone().unwrap().send().await;
-            first.map_err(|e| e.into())
+        Ok(EsiResponse::Modified(response))
+    }
+
+    /// Sends `body` as JSON in a POST request to `url`, a path relative to the ESI base URL.
+    pub async fn esi_post<B: Serialize + ?Sized>(
+        &self,
+        url: &str,
+        body: &B,
+    ) -> Result<Response, EsiError> {
+        let request_error = |source| EsiError::Request {
+            url: String::from(url),
+            source,
         };
 
-        // try again once if it's just a regular http error
-        // TODO: Evaluate if this is really necessary?
-        // NOTE: Do i need another permit?
-        if result.is_err() {
-            println!(
-                "ESI Client: Needed to resend request! {:?}",
-                result.as_ref().err().unwrap()
-            );
-            *self.errors.lock().await -= 1;
-            // retry and convert reqwest::Error into our MiddlewareError
-            result = req.send().await.map_err(|e| e.into());
+        let body = serde_json::to_vec(body)
+            .map_err(|err| request_error(MiddlewareError::Middleware(err.into())))?;
+        let timeout = self.timeout;
+        self.send(Method::POST, url, HeaderMap::new(), Some(body), timeout)
+            .await
+    }
+
+    /// Like [`ESIClient::esi_post`], but also decodes the JSON body of the response.
+    pub async fn esi_post_json<B: Serialize + ?Sized, T: DeserializeOwned>(
+        &self,
+        url: &str,
+        body: &B,
+    ) -> Result<T, EsiError> {
+        let response = self.esi_post(url, body).await?;
+        decode_json(url, response).await
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        url: &str,
+        headers: HeaderMap,
+        body: Option<Vec<u8>>,
+        timeout: Option<Duration>,
+    ) -> Result<Response, EsiError> {
+        // only this request waits, others check the budget for themselves
+        if self.errors.load(Ordering::Relaxed) <= 10 {
+            self.await_esi_timeout().await;
         }
 
-        if result.is_err() {
-            return Err(result.err().unwrap());
+        let mut req = self
+            .client
+            .request(method, [self.base_url.as_str(), url].join(""))
+            .header(USER_AGENT, self.user_agent());
+
+        if self.auth_tok_valid().await {
+            req = req.header(AUTHORIZATION, format!("Bearer {}", self.auth_tok.clone().unwrap_or(String::from("NOACL"))));
         }
 
-        let result = result.unwrap();
+        if self.datasource != Datasource::Tranquility {
+            req = req.query(&[("datasource", self.datasource.as_str())]);
+        }
+        req = req.headers(headers);
+        if let Some(body) = body {
+            req = req.header(CONTENT_TYPE, "application/json").body(body);
+        }
+        // runs until the body has been read, so a body that stalls halfway fails too
+        if let Some(timeout) = timeout {
+            req = req.timeout(timeout);
+        }
 
-        drop(permit);
+        let mut attempt = 1;
+        let result = loop {
+            let permit = self.connect_pool.acquire().await.unwrap();
+            let result: Result<Response, MiddlewareError> = req.try_clone().unwrap().send().await;
+            drop(permit);
+
+            let transient = match &result {
+                Ok(response) => self.retry_policy.is_transient(response.status()),
+                Err(_) => true,
+            };
+            if !transient || attempt >= self.retry_policy.max_attempts {
+                break result.map_err(|source| request_failed(url, source))?;
+            }
+
+            match &result {
+                Ok(response) => {
+                    tracing::warn!("ESI returned {} for {url}, retrying", response.status());
+                }
+                Err(err) => {
+                    tracing::warn!("Needed to resend request! {err:?}");
+                    // ESI never saw this one, so there's no header to read the budget from
+                    let decrement = |errors: u32| Some(errors.saturating_sub(1));
+                    let errors = &self.errors;
+                    let _ = errors.fetch_update(Ordering::Relaxed, Ordering::Relaxed, decrement);
+                }
+            }
+            sleep(self.retry_policy.delay(attempt)).await;
+            attempt += 1;
+        };
 
         // unify status errors into MiddlewareError via .into()
-        match result.status().as_u16() {
-            200 => Ok(result),
+        let checked: Result<Response, MiddlewareError> = match result.status().as_u16() {
+            // 304 only comes back for conditional requests
+            200 | 304 => Ok(result),
             420 => {
+                // the 4xx branch may never have run, so take the reset timer from this response
+                // rather than waiting on a stale or zero one
+                self.record_error_limit(result.headers());
                 self.await_esi_timeout().await;
 
                 Err(result.error_for_status().unwrap_err().into())
             }
             400..=499 => {
-                (*self.errors.lock().await) = result
-                    .headers()
-                    .get("x-esi-error-limit-remain")
-                    .expect("No ESI Error limit found???")
-                    .to_str()
-                    .unwrap()
-                    .parse()
-                    .unwrap();
-                (*self.error_timeout.lock().await) = result
-                    .headers()
-                    .get("x-esi-error-limit-reset")
-                    .expect("No ESI Reset Timer found???")
-                    .to_str()
-                    .unwrap()
-                    .parse()
-                    .unwrap();
+                self.record_error_limit(result.headers());
 
                 let err = result.error_for_status().unwrap_err();
 
                 Err(err.into())
             }
             500..=599 => {
+                let status = result.status();
+                if let Some(backoff) = server_error_backoff(status, self.downtime_backoff) {
+                    tracing::warn!(
+                        "ESI returned {status}, waiting {} secs before returning",
+                        backoff.as_secs()
+                    );
+                    sleep(backoff).await;
+                }
+
                 let err = result.error_for_status().unwrap_err();
 
                 Err(err.into())
             }
             _ => {
-                eprintln!(
-                    "ESI Client: Unknown error code detected! code: {}",
-                    result.status()
-                );
+                tracing::error!("Unknown error code detected! code: {}", result.status());
                 let err = result.error_for_status().unwrap_err();
                 Err(err.into())
             }
-        }
+        };
+        checked.map_err(|source| request_failed(url, source))
     }
 
     /// check if auth token is valid
@@ -209,9 +837,13 @@ impl ESIClient {
         let auth_str =
             BASE64_STANDARD.encode(format!("{}:{}", client_id, client_secret).as_bytes());
 
+        // the SSO server has no error limit of its own, so this skips send() but still takes a
+        // connection from the pool
+        let permit = self.connect_pool.acquire().await?;
         let response = self
             .client
-            .post("https://login.eveonline.com/v2/oauth/token")
+            .post(format!("{}/v2/oauth/token", self.login_url))
+            .header(USER_AGENT, self.user_agent())
             .header("Authorization", format!("Basic {}", auth_str))
             .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
             .form(&[
@@ -219,7 +851,9 @@ impl ESIClient {
                 ("refresh_token", &refresh_tok),
             ])
             .send()
-            .await?;
+            .await?
+            .error_for_status()?;
+        drop(permit);
 
         let token_response: serde_json::Value = response.json().await?;
         let access_token = token_response["access_token"]
@@ -232,9 +866,581 @@ impl ESIClient {
         Ok(())
     }
 
+    /// Identifies this client to CCP, as their developer guidelines ask for.
+    fn user_agent(&self) -> String {
+        format!(
+            "{}; component of EvERTerm/0.0.1 (0@x4132.dev; +https://github.com/x4132/everterm; discord:msvcredist2022; eve:Charles Helugo) on {}",
+            self.component_name, self.platform_name
+        )
+    }
+
+    /// Stores the error budget and reset timer a response reports, if it has them.
+    fn record_error_limit(&self, headers: &HeaderMap) {
+        if let Some(remain) = error_limit_header(headers, "x-esi-error-limit-remain") {
+            self.errors.store(remain, Ordering::Relaxed);
+        }
+        if let Some(reset) = error_limit_header(headers, "x-esi-error-limit-reset") {
+            let reset_at = unix_millis() + u64::from(reset) * 1000;
+            self.error_reset_at.store(reset_at, Ordering::Relaxed);
+        }
+    }
+
+    /// Time left until ESI's error window resets, zero if it already has.
+    fn error_reset_remaining(&self) -> Duration {
+        let reset_at = self.error_reset_at.load(Ordering::Relaxed);
+        Duration::from_millis(reset_at.saturating_sub(unix_millis()))
+    }
+
     async fn await_esi_timeout(&self) {
-        let timeout = self.error_timeout.lock().await;
+        let remaining = self.error_reset_remaining();
+        if !remaining.is_zero() {
+            tracing::warn!(
+                "ESI error budget is low, waiting {}ms for it to reset",
+                remaining.as_millis()
+            );
+            sleep(remaining).await;
+        }
+    }
+}
+
+fn unix_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |now| now.as_millis() as u64)
+}
+
+/// Reads one of ESI's numeric error limit headers, `None` if it is missing or unreadable.
+fn error_limit_header(headers: &HeaderMap, name: &str) -> Option<u32> {
+    headers.get(name)?.to_str().ok()?.parse().ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::{net::TcpListener, sync::Mutex};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{
+            body_string_contains, header_regex, method, path, query_param, query_param_is_missing,
+        },
+    };
+
+    fn mock_client(server: &MockServer) -> ESIClient {
+        // no HTTP cache, so nothing is written to disk
+        let http = ClientBuilder::new(reqwest::Client::new()).build();
+        ESIClient::with_client(http, "esi_tests", std::env::consts::OS, 4)
+            .with_base_url(&server.uri())
+    }
+
+    #[tokio::test]
+    async fn test_builder_applies_settings() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status/"))
+            .and(header_regex("user-agent", "^builder_tests; .* on testos$"))
+            .respond_with(ResponseTemplate::new(200))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = ESIClient::builder()
+            .component_name("builder_tests")
+            .platform("testos")
+            .max_connections(2)
+            .auth_token("token")
+            .cache_mode(ResponseCache::Disabled)
+            .base_url(&server.uri())
+            .build();
+
+        assert_eq!(client.connect_pool.available_permits(), 2);
+        assert_eq!(client.auth_tok.as_deref(), Some("token"));
+        client.esi_get("/status/").await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_compressed_responses_are_decoded() {
+        // gzip of `[34,35,36]`
+        const GZIPPED: [u8; 30] = [
+            31, 139, 8, 0, 0, 0, 0, 0, 2, 3, 139, 54, 54, 209, 49, 54, 213, 49, 54, 139, 5, 0, 55,
+            82, 120, 7, 10, 0, 0, 0,
+        ];
+
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/types/"))
+            .and(header_regex("accept-encoding", "gzip"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("content-encoding", "gzip")
+                    .set_body_raw(GZIPPED.to_vec(), "application/json"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client =
+            ESIClient::in_memory("esi_tests", std::env::consts::OS, 4).with_base_url(&server.uri());
+        let ids: Vec<u32> = client.esi_get_json("/universe/types/").await.unwrap();
+        assert_eq!(ids, vec![34, 35, 36]);
+    }
+
+    fn error_limit_response(status: u16, remain: u32, reset: u32) -> ResponseTemplate {
+        ResponseTemplate::new(status)
+            .insert_header("x-esi-error-limit-remain", remain.to_string().as_str())
+            .insert_header("x-esi-error-limit-reset", reset.to_string().as_str())
+    }
+
+    #[tokio::test]
+    async fn test_ok_response() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+        let response = client.esi_get("/status/").await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_singularity_datasource_is_sent() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/orders/"))
+            .and(query_param("page", "2"))
+            .and(query_param("datasource", "singularity"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("[]"))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/status/"))
+            .and(query_param_is_missing("datasource"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let tranquility = mock_client(&server);
+        tranquility.esi_get("/status/").await.unwrap();
 
-        sleep(Duration::from_secs((*timeout).into())).await
+        let mut singularity = mock_client(&server);
+        singularity.set_datasource("Singularity".parse().unwrap());
+        let response = singularity
+            .esi_get("/markets/10000002/orders/?page=2")
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!("duality".parse::<Datasource>().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_load_auth_tok_posts_to_login_server() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/token"))
+            .and(header_regex("user-agent", "^esi_tests; component of"))
+            .and(body_string_contains("refresh_token=good"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "access_token": "access",
+                "expires_in": 1199,
+                "token_type": "Bearer",
+            })))
+            .mount(&server)
+            .await;
+        // the SSO server sends no error limit headers
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/token"))
+            .respond_with(ResponseTemplate::new(400))
+            .mount(&server)
+            .await;
+
+        let mut client = mock_client(&server);
+        client.set_login_url(&server.uri());
+        client
+            .load_auth_tok("good".into(), "id".into(), "secret".into())
+            .await
+            .unwrap();
+        assert_eq!(client.auth_tok.as_deref(), Some("access"));
+
+        let result = client
+            .load_auth_tok("revoked".into(), "id".into(), "secret".into())
+            .await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_unexpected_body_is_decode_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/html/"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_raw("<html>Bad Gateway</html>", "text/html"),
+            )
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/truncated/"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("[1, 2", "application/json"))
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+        for (url, expected_snippet) in [
+            ("/html/", "<html>Bad Gateway</html>"),
+            ("/truncated/", "[1, 2"),
+        ] {
+            let err = client.esi_get_json::<Vec<u32>>(url).await.unwrap_err();
+            let EsiError::Decode {
+                url: err_url,
+                snippet,
+                ..
+            } = err
+            else {
+                panic!("expected a decode error, got {err}");
+            };
+            assert_eq!(err_url, url);
+            assert_eq!(snippet, expected_snippet);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_in_memory_caches_are_separate() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("cache-control", "public, max-age=600")
+                    .set_body_raw("{}", "application/json"),
+            )
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let mut first = ESIClient::in_memory("esi_tests", std::env::consts::OS, 4);
+        first.set_base_url(&server.uri());
+        let mut second = ESIClient::in_memory("esi_tests", std::env::consts::OS, 4);
+        second.set_base_url(&server.uri());
+
+        // the repeat is answered from the first client's cache, the second client has its own
+        for client in [&first, &first, &second] {
+            client.esi_get("/status/").await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_not_found_maps_to_status_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/types/1/"))
+            .respond_with(error_limit_response(404, 99, 60))
+            .mount(&server)
+            .await;
+
+        let err = mock_client(&server)
+            .esi_get("/universe/types/1/")
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+        let message = err.to_string();
+        assert!(message.starts_with("Request to /universe/types/1/ failed"));
+    }
+
+    #[tokio::test]
+    async fn test_client_error_updates_error_budget() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/markets/0/orders/"))
+            .respond_with(error_limit_response(400, 42, 17))
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+        client.esi_get("/markets/0/orders/").await.unwrap_err();
+
+        assert_eq!(client.errors.load(Ordering::Relaxed), 42);
+        let remaining = client.error_reset_remaining();
+        assert!(remaining > Duration::from_secs(16) && remaining <= Duration::from_secs(17));
+    }
+
+    #[tokio::test]
+    async fn test_error_limited_waits_out_reset() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bad/"))
+            .respond_with(error_limit_response(400, 50, 1))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/limited/"))
+            .respond_with(ResponseTemplate::new(420))
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+        // sets the reset timer the 420 waits on
+        client.esi_get("/bad/").await.unwrap_err();
+
+        // the second counts from the 400's response, which is already a little behind us
+        let start = std::time::Instant::now();
+        let err = client.esi_get("/limited/").await.unwrap_err();
+        assert!(start.elapsed() >= Duration::from_millis(900));
+        assert_eq!(err.status(), Some(StatusCode::from_u16(420).unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_exhausted_budget_waits_per_request() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bad/"))
+            .respond_with(error_limit_response(400, 5, 1))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/status/"))
+            .respond_with(ResponseTemplate::new(200).set_body_string("{}"))
+            .expect(4)
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+        client.esi_get("/bad/").await.unwrap_err();
+
+        // each request sleeps out the same reset on its own, rather than one after another
+        let start = std::time::Instant::now();
+        let requests = (0..4).map(|_| client.esi_get("/status/"));
+        for result in futures::future::join_all(requests).await {
+            assert!(result.is_ok());
+        }
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(900), "{elapsed:?}");
+        assert!(elapsed < Duration::from_secs(2), "{elapsed:?}");
+    }
+
+    #[tokio::test]
+    async fn test_error_limited_reads_its_own_reset() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/limited/"))
+            .respond_with(error_limit_response(420, 0, 1))
+            .mount(&server)
+            .await;
+
+        // no earlier 4xx, so the only reset timer is the one on the 420
+        let client = mock_client(&server);
+        let start = std::time::Instant::now();
+        let err = client.esi_get("/limited/").await.unwrap_err();
+        assert!(start.elapsed() >= Duration::from_secs(1));
+        assert_eq!(err.status(), Some(StatusCode::from_u16(420).unwrap()));
+        // the wait used up the reset timer, so the empty budget doesn't hold up the next request
+        assert_eq!(client.errors.load(Ordering::Relaxed), 0);
+        assert_eq!(client.error_reset_remaining(), Duration::ZERO);
+    }
+
+    #[tokio::test]
+    async fn test_transport_failure_retries_once() {
+        // hangs up on every connection without answering
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let connections = Arc::new(Mutex::new(0));
+        tokio::spawn({
+            let connections = connections.clone();
+            async move {
+                while let Ok((socket, _)) = listener.accept().await {
+                    *connections.lock().await += 1;
+                    drop(socket);
+                }
+            }
+        });
+
+        let client = ESIClient::builder()
+            .component_name("esi_tests")
+            .cache_mode(ResponseCache::Disabled)
+            .base_url(&format!("http://{addr}"))
+            .build();
+        assert!(client.esi_get("/status/").await.is_err());
+
+        assert_eq!(*connections.lock().await, 2);
+        assert_eq!(client.errors.load(Ordering::Relaxed), 99);
+    }
+
+    #[test]
+    fn test_retry_policy_delays() {
+        let policy = RetryPolicy {
+            max_attempts: 5,
+            base_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(300),
+            jitter: false,
+            retry_server_errors: false,
+        };
+        assert_eq!(policy.delay(1), Duration::from_millis(100));
+        assert_eq!(policy.delay(2), Duration::from_millis(200));
+        assert_eq!(policy.delay(3), Duration::from_millis(300));
+        assert_eq!(policy.delay(30), Duration::from_millis(300));
+
+        let jittered = RetryPolicy {
+            jitter: true,
+            ..policy
+        };
+        for _ in 0..20 {
+            let delay = jittered.delay(2);
+            assert!(delay >= Duration::from_millis(100) && delay <= Duration::from_millis(200));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_server_error_retries_with_backoff() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status/"))
+            .respond_with(ResponseTemplate::new(500))
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let mut client = mock_client(&server);
+        client.set_retry_policy(RetryPolicy {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(100),
+            retry_server_errors: true,
+            ..RetryPolicy::default()
+        });
+
+        let start = std::time::Instant::now();
+        let err = client.esi_get("/status/").await.unwrap_err();
+        // 100ms before the first retry, 200ms before the second
+        assert!(start.elapsed() >= Duration::from_millis(300));
+        assert_eq!(err.status(), Some(StatusCode::INTERNAL_SERVER_ERROR));
+    }
+
+    #[tokio::test]
+    async fn test_retry_recovers_from_transient_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status/"))
+            .respond_with(ResponseTemplate::new(502))
+            .up_to_n_times(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/status/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({})))
+            .mount(&server)
+            .await;
+
+        // the default policy only retries requests that failed outright
+        let mut client = mock_client(&server);
+        let err = client.esi_get("/status/").await.unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::BAD_GATEWAY));
+
+        client.set_retry_policy(RetryPolicy {
+            retry_server_errors: true,
+            ..RetryPolicy::default()
+        });
+        assert!(client.esi_get("/status/").await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_client_error_is_not_retried() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/bad/"))
+            .respond_with(error_limit_response(400, 90, 0))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut client = mock_client(&server);
+        client.set_retry_policy(RetryPolicy {
+            max_attempts: 5,
+            ..RetryPolicy::default()
+        });
+        client.esi_get("/bad/").await.unwrap_err();
+        assert_eq!(client.errors.load(Ordering::Relaxed), 90);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_is_retried_then_reported() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/status/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({}))
+                    .set_delay(Duration::from_millis(300)),
+            )
+            .expect(3)
+            .mount(&server)
+            .await;
+
+        let mut client = mock_client(&server);
+        client.set_timeout(Some(Duration::from_millis(50)));
+        client.set_retry_policy(RetryPolicy {
+            max_attempts: 2,
+            base_delay: Duration::from_millis(10),
+            ..RetryPolicy::default()
+        });
+
+        let err = client.esi_get("/status/").await.unwrap_err();
+        assert!(matches!(err, EsiError::Timeout { ref url } if url == "/status/"));
+        assert_eq!(err.status(), None);
+
+        // a longer timeout for one call waits the delay out
+        let response = client
+            .esi_get_with_timeout("/status/", Duration::from_secs(5))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_timeout_covers_a_stalled_body() {
+        // wiremock only delays whole responses, so this server sends the headers and half the
+        // body by hand, then stalls
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+            let (mut socket, _) = listener.accept().await.unwrap();
+            let mut request = [0; 1024];
+            let _ = socket.read(&mut request).await.unwrap();
+            let head = "HTTP/1.1 200 OK\r\ncontent-type: application/json\r\n\
+                        content-length: 64\r\n\r\n[{\"order_id\": 1,";
+            socket.write_all(head.as_bytes()).await.unwrap();
+            sleep(Duration::from_secs(30)).await;
+        });
+
+        let mut client = ESIClient::builder()
+            .component_name("esi_tests")
+            .cache_mode(ResponseCache::Disabled)
+            .base_url(&format!("http://{addr}"))
+            .build();
+        client.set_timeout(Some(Duration::from_millis(200)));
+
+        let read = client.esi_get_json::<serde_json::Value>("/markets/10000002/orders/");
+        let err = tokio::time::timeout(Duration::from_secs(5), read)
+            .await
+            .expect("the body read should time out")
+            .unwrap_err();
+        assert!(matches!(err, EsiError::Timeout { .. }), "{err}");
+    }
+
+    #[test]
+    fn test_server_error_backoff() {
+        let downtime = Duration::from_secs(30);
+
+        for code in [500, 502] {
+            let status = StatusCode::from_u16(code).unwrap();
+            assert_eq!(server_error_backoff(status, downtime), None);
+        }
+        for code in [503, 504] {
+            let status = StatusCode::from_u16(code).unwrap();
+            assert_eq!(server_error_backoff(status, downtime), Some(downtime));
+        }
     }
 }
@@ -1,10 +1,11 @@
-pub const ESI_URL: &'static str = "https://esi.evetech.net/latest";
+pub const ESI_URL: &str = "https://esi.evetech.net/latest";
+pub const LOGIN_URL: &str = "https://login.eveonline.com";
 
 /// Prepend the ESI base URL to a `format!`‐style string.
 ///
 /// # Examples
 ///
-/// ```rust
+/// ```rust,ignore
 /// # #![allow(unused_must_use)]
 /// let character_id = 90000001;
 /// let url = esi!("/characters/{}/", character_id);
@@ -1,30 +1,75 @@
-use chrono::{DateTime, ParseError, Utc};
+use anyhow::Context;
+use chrono::{DateTime, NaiveDate, ParseError, Utc};
 use dashmap::DashMap;
-use reqwest::header::{EXPIRES, HeaderValue, LAST_MODIFIED};
+use futures::{StreamExt, TryStreamExt};
+use reqwest::{
+    Response,
+    header::{EXPIRES, HeaderMap, LAST_MODIFIED},
+};
 use serde::{
     Deserialize, Serialize,
-    de::{self, Visitor},
+    de::{self, DeserializeOwned, MapAccess, Visitor},
 };
 use std::{
+    borrow::Cow,
     cmp::Ordering,
-    collections::HashMap,
+    collections::{HashMap, HashSet},
     fmt::{self},
+    path::Path,
     sync::Arc,
 };
-use tokio::sync::Mutex;
 
 use crate::{
-    ESIClient,
-    universe::{InvalidIDError, Region, StationID, SystemID},
+    ESIClient, EsiError, EsiResponse, page_count, parse_json, read_json_body, request_failed,
+    universe::{
+        Constellations, Graph, InvalidIDError, Item, Items, LocationId, Region, RegionID,
+        StationID, Stations, StructureAPIResponse, SystemID, Systems, TypeID, UniverseCacheStore,
+        hubs::TradeHub,
+    },
 };
 
 #[derive(Clone, PartialEq, Debug, Serialize)]
 pub enum MarketOrderRange {
-    System(u32),
+    /// Only sellers at the order's station.
     Station,
+    /// Any seller in the order's system.
+    SolarSystem,
+    /// Any seller at most this many jumps from the order's system.
+    Jumps(u32),
+    /// Any seller in the order's region.
     Region,
 }
 
+impl MarketOrderRange {
+    /// Whether a buy order placed in `order_system` can be filled by a seller in
+    /// `seller_system`.
+    ///
+    /// The graph only knows about systems, so for [`MarketOrderRange::Station`] this checks the
+    /// systems match; callers still need to compare the stations themselves. No range reaches
+    /// past the order's region, however few jumps away the seller is.
+    pub fn reaches(&self, order_system: SystemID, seller_system: SystemID, graph: &Graph) -> bool {
+        let same_region = || {
+            matches!(
+                (graph.region_of(order_system), graph.region_of(seller_system)),
+                (Some(a), Some(b)) if a == b
+            )
+        };
+
+        match self {
+            MarketOrderRange::Station | MarketOrderRange::SolarSystem => {
+                order_system == seller_system
+            }
+            MarketOrderRange::Jumps(jumps) => {
+                same_region()
+                    && graph
+                        .jumps_within(order_system, seller_system, *jumps)
+                        .is_some()
+            }
+            MarketOrderRange::Region => same_region(),
+        }
+    }
+}
+
 impl<'de> Deserialize<'de> for MarketOrderRange {
     fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
     where
@@ -36,7 +81,18 @@ impl<'de> Deserialize<'de> for MarketOrderRange {
             type Value = MarketOrderRange;
 
             fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str(r#""station", "region", or a number"#)
+                formatter.write_str(r#""station", "solarsystem", "region", or a number"#)
+            }
+
+            // the derived Serialize output, as read back from a snapshot: `{"Jumps": 5}`
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: MapAccess<'de>,
+            {
+                match map.next_entry::<String, u32>()? {
+                    Some((key, jumps)) if key == "Jumps" => Ok(MarketOrderRange::Jumps(jumps)),
+                    _ => Err(de::Error::custom("expected a Jumps range")),
+                }
             }
 
             fn visit_str<E>(self, value: &str) -> Result<Self::Value, E>
@@ -44,13 +100,13 @@ impl<'de> Deserialize<'de> for MarketOrderRange {
                 E: de::Error,
             {
                 match value {
-                    "station" => Ok(MarketOrderRange::Station),
-                    "region" => Ok(MarketOrderRange::Region),
-                    "solarsystem" => Ok(MarketOrderRange::System(1)),
+                    "station" | "Station" => Ok(MarketOrderRange::Station),
+                    "region" | "Region" => Ok(MarketOrderRange::Region),
+                    "solarsystem" | "SolarSystem" => Ok(MarketOrderRange::SolarSystem),
                     _ => {
                         let num_range: Result<u32, _> = value.parse();
                         match num_range {
-                            Ok(val) => Ok(MarketOrderRange::System(val)),
+                            Ok(val) => Ok(MarketOrderRange::Jumps(val)),
                             Err(_) => Err(E::custom(format!("unexpected string: {}", value))),
                         }
                     }
@@ -62,11 +118,17 @@ impl<'de> Deserialize<'de> for MarketOrderRange {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize)]
 pub struct MarketDiff {
-    pub new: HashMap<u32, Vec<Order>>,
-    pub modified: HashMap<u32, Vec<Order>>,
-    pub removed: HashMap<u32, Vec<u64>>,
+    pub new: HashMap<TypeID, Vec<Order>>,
+    pub modified: HashMap<TypeID, Vec<Order>>,
+    pub removed: HashMap<TypeID, Vec<u64>>,
+}
+
+impl Default for MarketDiff {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl MarketDiff {
@@ -77,6 +139,46 @@ impl MarketDiff {
             removed: HashMap::new(),
         }
     }
+
+    /// Totals the orders, volume and ISK value of this diff. `removed` only holds order IDs, so
+    /// the removed orders' volume and value are looked up in `previous`, the market the diff was
+    /// computed from.
+    pub fn summary(&self, previous: &Market) -> DiffSummary {
+        let mut summary = DiffSummary::default();
+
+        for order in self.new.values().flatten() {
+            summary.new_orders += 1;
+            summary.volume_added += order.volume_remain as u64;
+            summary.isk_added += order.price * order.volume_remain as f64;
+        }
+        summary.modified_orders = self.modified.values().map(Vec::len).sum();
+
+        for (type_id, order_ids) in &self.removed {
+            summary.removed_orders += order_ids.len();
+            let Some(book) = previous.items.get(type_id) else {
+                continue;
+            };
+            for order in order_ids.iter().filter_map(|id| book.orders.get(id)) {
+                summary.volume_removed += order.volume_remain as u64;
+                summary.isk_removed += order.price * order.volume_remain as f64;
+            }
+        }
+
+        summary
+    }
+}
+
+/// Totals over a [`MarketDiff`], see [`MarketDiff::summary`]. ISK values are price times
+/// remaining volume.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize)]
+pub struct DiffSummary {
+    pub new_orders: usize,
+    pub modified_orders: usize,
+    pub removed_orders: usize,
+    pub volume_added: u64,
+    pub volume_removed: u64,
+    pub isk_added: f64,
+    pub isk_removed: f64,
 }
 
 #[derive(Deserialize, Debug, Clone)]
@@ -90,7 +192,7 @@ struct MarketAPIResponseOrder {
     price: f64,
     range: MarketOrderRange,
     system_id: SystemID,
-    type_id: u32,
+    type_id: TypeID,
     volume_remain: u32,
     volume_total: u32,
 }
@@ -110,6 +212,13 @@ pub struct Order {
     pub volume_total: u32,
 }
 
+impl Order {
+    /// Whether the order sits in an NPC station or a player structure.
+    pub fn location(&self) -> LocationId {
+        LocationId::from(self.location_id)
+    }
+}
+
 impl Eq for Order {}
 
 impl PartialOrd for Order {
@@ -131,6 +240,17 @@ pub enum OrderConversionError {
     InvalidIDError(InvalidIDError),
 }
 
+impl fmt::Display for OrderConversionError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderConversionError::ParseError(err) => write!(f, "invalid issue date: {err}"),
+            OrderConversionError::InvalidIDError(err) => write!(f, "invalid location: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for OrderConversionError {}
+
 impl TryFrom<MarketAPIResponseOrder> for Order {
     type Error = OrderConversionError;
 
@@ -157,41 +277,827 @@ impl TryFrom<MarketAPIResponseOrder> for Order {
     }
 }
 
+/// An order as returned by `/markets/structures/{id}/`, which omits `system_id`.
+#[derive(Deserialize, Debug, Clone)]
+struct StructureAPIResponseOrder {
+    duration: u32,
+    is_buy_order: bool,
+    issued: String,
+    location_id: u64,
+    min_volume: u32,
+    order_id: u64,
+    price: f64,
+    range: MarketOrderRange,
+    type_id: TypeID,
+    volume_remain: u32,
+    volume_total: u32,
+}
+
+impl StructureAPIResponseOrder {
+    fn with_system(self, system_id: SystemID) -> MarketAPIResponseOrder {
+        MarketAPIResponseOrder {
+            duration: self.duration,
+            is_buy_order: self.is_buy_order,
+            issued: self.issued,
+            location_id: self.location_id,
+            min_volume: self.min_volume,
+            order_id: self.order_id,
+            price: self.price,
+            range: self.range,
+            system_id,
+            type_id: self.type_id,
+            volume_remain: self.volume_remain,
+            volume_total: self.volume_total,
+        }
+    }
+}
+
+/// An order as returned by `/characters/{id}/orders/`.
+/// Unlike public orders, these omit `system_id` and sell orders omit `is_buy_order`.
+#[derive(Deserialize, Debug, Clone)]
+struct CharacterAPIResponseOrder {
+    duration: u32,
+    escrow: Option<f64>,
+    #[serde(default)]
+    is_buy_order: bool,
+    is_corporation: bool,
+    issued: String,
+    location_id: u64,
+    #[serde(default = "default_min_volume")]
+    min_volume: u32,
+    order_id: u64,
+    price: f64,
+    range: MarketOrderRange,
+    region_id: RegionID,
+    type_id: TypeID,
+    volume_remain: u32,
+    volume_total: u32,
+}
+
+fn default_min_volume() -> u32 {
+    1
+}
+
+/// One of a character's own open orders.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CharacterOrder {
+    pub type_id: TypeID,
+    pub region_id: RegionID,
+    pub is_corporation: bool,
+    /// ISK held in escrow for buy orders.
+    pub escrow: Option<f64>,
+    pub order: Order,
+}
+
+/// How many regions [`Market::fetch_regions`] fetches at once.
+pub const DEFAULT_MAX_CONCURRENT_REGIONS: usize = 8;
+
+/// Caps how many pages of orders are fetched for a region, no matter what `x-pages` claims.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PageLimit {
+    pub max_pages: usize,
+    /// Fail the fetch instead of truncating it when `x-pages` is over the cap.
+    pub strict: bool,
+}
+
+impl Default for PageLimit {
+    fn default() -> Self {
+        PageLimit {
+            max_pages: 500,
+            strict: false,
+        }
+    }
+}
+
+impl PageLimit {
+    /// Returns how many pages to fetch for a response claiming `x_pages` pages.
+    pub(crate) fn pages_to_fetch(&self, x_pages: usize) -> anyhow::Result<usize> {
+        if x_pages <= self.max_pages {
+            return Ok(x_pages);
+        }
+
+        if self.strict {
+            anyhow::bail!(
+                "x-pages of {x_pages} is over the limit of {} pages",
+                self.max_pages
+            );
+        }
+
+        tracing::warn!(
+            "x-pages of {x_pages} is over the limit, only fetching {} pages",
+            self.max_pages
+        );
+        Ok(self.max_pages)
+    }
+}
+
+/// Reads a page of orders from `url`. Dead regions answer with `[]`, or sometimes with no body
+/// at all, both of which are just an empty page.
+pub(crate) async fn page_orders<T: DeserializeOwned>(
+    url: &str,
+    response: Response,
+) -> Result<Vec<T>, EsiError> {
+    let body = read_json_body(url, response).await?;
+    if body.trim_ascii().is_empty() {
+        return Ok(Vec::new());
+    }
+
+    parse_json(url, &body)
+}
+
+/// Splits a JSON array arriving in chunks into its elements, so each one can be parsed as soon
+/// as it is complete instead of after the whole body has been read. Only the element being
+/// read is kept, not the elements before it.
+#[derive(Debug, Default)]
+struct JsonArraySplitter {
+    buf: Vec<u8>,
+    /// How much of `buf` has been scanned.
+    scanned: usize,
+    /// Where the element being read starts in `buf`.
+    start: Option<usize>,
+    /// How deeply nested the scan is, the outer array being 1.
+    depth: u32,
+    in_string: bool,
+    escaped: bool,
+    closed: bool,
+}
+
+impl JsonArraySplitter {
+    /// Appends `chunk` and calls `on_element` with each element it completes.
+    fn push<E>(
+        &mut self,
+        chunk: &[u8],
+        mut on_element: impl FnMut(&[u8]) -> Result<(), E>,
+    ) -> Result<(), SplitError<E>> {
+        self.buf.extend_from_slice(chunk);
+
+        while self.scanned < self.buf.len() {
+            let pos = self.scanned;
+            let byte = self.buf[pos];
+            self.scanned += 1;
+
+            if self.in_string {
+                match byte {
+                    _ if self.escaped => self.escaped = false,
+                    b'\\' => self.escaped = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            if byte.is_ascii_whitespace() {
+                continue;
+            }
+            if self.closed {
+                return Err(SplitError::Malformed("data after the closing bracket"));
+            }
+            if self.depth == 0 {
+                if byte != b'[' {
+                    return Err(SplitError::Malformed("expected an array"));
+                }
+                self.depth = 1;
+                continue;
+            }
+
+            let ends_element = self.depth == 1 && matches!(byte, b',' | b']');
+            if ends_element {
+                if let Some(start) = self.start.take() {
+                    let element = self.buf[start..pos].trim_ascii_end();
+                    on_element(element).map_err(SplitError::Element)?;
+                } else if byte == b',' {
+                    return Err(SplitError::Malformed("empty array element"));
+                }
+                if byte == b']' {
+                    self.depth = 0;
+                    self.closed = true;
+                }
+                continue;
+            }
+
+            if self.depth == 1 && self.start.is_none() {
+                self.start = Some(pos);
+            }
+            match byte {
+                b'"' => self.in_string = true,
+                b'{' | b'[' => self.depth += 1,
+                b'}' | b']' => self.depth -= 1,
+                _ => {}
+            }
+        }
+
+        // drop everything before the element being read
+        let keep_from = self.start.unwrap_or(self.scanned);
+        self.buf.drain(..keep_from);
+        self.scanned -= keep_from;
+        self.start = self.start.map(|_| 0);
+
+        Ok(())
+    }
+
+    /// Checks the array was closed, once the body has ended. An empty body counts as an empty
+    /// array, like [`page_orders`] treats it.
+    fn finish<E>(&self) -> Result<(), SplitError<E>> {
+        let empty = self.depth == 0 && self.buf.iter().all(u8::is_ascii_whitespace);
+        if self.closed || empty {
+            Ok(())
+        } else {
+            Err(SplitError::Malformed("body ended inside the array"))
+        }
+    }
+}
+
+#[derive(Debug)]
+enum SplitError<E> {
+    Malformed(&'static str),
+    Element(E),
+}
+
+/// Like [`page_orders`], but parses the body while it arrives and inserts each order into
+/// `market` straight away, so neither the body nor the page's orders are held at once.
+/// Returns the orders that failed to convert.
+async fn stream_page_orders(
+    url: &str,
+    mut response: Response,
+    market: &Market,
+    region_id: RegionID,
+) -> Result<Vec<OrderConversionError>, EsiError> {
+    if let Some(content_type) = response.headers().get(reqwest::header::CONTENT_TYPE) {
+        let content_type = String::from_utf8_lossy(content_type.as_bytes()).into_owned();
+        if !content_type.contains("json") {
+            let reason = format!("expected JSON, got {content_type}");
+            return Err(EsiError::Decode {
+                url: String::from(url),
+                reason,
+                snippet: String::new(),
+            });
+        }
+    }
+
+    let mut splitter = JsonArraySplitter::default();
+    let mut errors = Vec::new();
+    let mut parse = |element: &[u8]| -> Result<(), EsiError> {
+        let order: MarketAPIResponseOrder = parse_json(url, element)?;
+        if let Err(err) = market.insert_order(region_id, order) {
+            errors.push(err);
+        }
+        Ok(())
+    };
+    let malformed = |reason: &str| EsiError::Decode {
+        url: String::from(url),
+        reason: String::from(reason),
+        snippet: String::new(),
+    };
+
+    loop {
+        let chunk = response
+            .chunk()
+            .await
+            .map_err(|err| request_failed(url, err.into()))?;
+        let Some(chunk) = chunk else {
+            break;
+        };
+
+        splitter.push(&chunk, &mut parse).map_err(|err| match err {
+            SplitError::Malformed(reason) => malformed(reason),
+            SplitError::Element(err) => err,
+        })?;
+    }
+    splitter
+        .finish::<EsiError>()
+        .map_err(|_| malformed("body ended inside the array"))?;
+
+    Ok(errors)
+}
+
+/// Reads the `Last-Modified` and `Expires` headers of a market response.
+pub(crate) fn cache_times(headers: &HeaderMap) -> anyhow::Result<(DateTime<Utc>, DateTime<Utc>)> {
+    let last_modified: DateTime<Utc> = DateTime::parse_from_rfc2822(
+        headers
+            .get(LAST_MODIFIED)
+            .context("No Last-Modified header in response")?
+            .to_str()?,
+    )?
+    .to_utc();
+    let expires: DateTime<Utc> = DateTime::parse_from_rfc2822(
+        headers
+            .get(EXPIRES)
+            .context("No Expires header in response")?
+            .to_str()?,
+    )?
+    .to_utc();
+
+    Ok((last_modified, expires))
+}
+
 /// Carries the current orders at a single snapshot.
-#[derive(Clone, Debug, Serialize)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct OrderBook {
-    pub item: u32,
+    pub item: TypeID,
     pub orders: HashMap<u64, Order>,
+    /// The regions these orders were fetched from.
+    pub regions: HashSet<RegionID>,
+    /// When the newest snapshot that touched these orders was taken by ESI. Regions refresh at
+    /// different times, so this can differ from the whole market's `last_modified`.
+    pub last_updated: DateTime<Utc>,
 }
 impl OrderBook {
-    pub fn new(item: u32) -> Self {
+    pub fn new(item: TypeID) -> Self {
         OrderBook {
             item,
             orders: HashMap::new(),
+            regions: HashSet::new(),
+            last_updated: DateTime::UNIX_EPOCH,
         }
     }
 
     pub fn merge(&mut self, other: Self) -> Result<(), InvalidIDError> {
         if self.item != other.item {
             return Err(InvalidIDError {
-                value: other.item.into(),
-                acceptable: self.item.into()..self.item.into(),
+                value: other.item.get().into(),
+                acceptable: self.item.get().into()..self.item.get().into(),
+                acceptable_from: None,
             });
         }
 
         self.orders.extend(other.orders);
+        self.regions.extend(other.regions);
+        self.last_updated = self.last_updated.max(other.last_updated);
 
         Ok(())
     }
+
+    /// Iterates over the buy orders in no particular order.
+    pub fn buy_orders(&self) -> impl Iterator<Item = &Order> {
+        self.orders.values().filter(|order| order.is_buy_order)
+    }
+
+    /// Iterates over the sell orders in no particular order.
+    pub fn sell_orders(&self) -> impl Iterator<Item = &Order> {
+        self.orders.values().filter(|order| !order.is_buy_order)
+    }
+
+    /// Splits the book into buy orders, highest price first, and sell orders, lowest price first.
+    pub fn split(&self) -> (Vec<&Order>, Vec<&Order>) {
+        let mut buys: Vec<&Order> = self.buy_orders().collect();
+        let mut sells: Vec<&Order> = self.sell_orders().collect();
+
+        // Order sorts by descending price
+        buys.sort();
+        sells.sort_by(|a, b| b.cmp(a));
+
+        (buys, sells)
+    }
+
+    /// The orders placed at `station`, sorted like [`OrderBook::split`]: buy orders first,
+    /// highest price first, then sell orders, lowest price first.
+    pub fn orders_at_station(&self, station: StationID) -> Vec<&Order> {
+        self.sorted_orders_where(|order| order.location_id == station)
+    }
+
+    /// The orders placed anywhere in `system`, sorted like [`OrderBook::orders_at_station`].
+    pub fn orders_in_system(&self, system: SystemID) -> Vec<&Order> {
+        self.sorted_orders_where(|order| order.system_id == system)
+    }
+
+    fn sorted_orders_where(&self, keep: impl Fn(&Order) -> bool) -> Vec<&Order> {
+        let (buys, sells) = self.split();
+        buys.into_iter()
+            .chain(sells)
+            .filter(|order| keep(order))
+            .collect()
+    }
+
+    /// The highest priced buy order.
+    pub fn best_buy(&self) -> Option<&Order> {
+        // Order sorts by descending price, so the highest price is the minimum
+        self.buy_orders().min()
+    }
+
+    /// The lowest priced sell order.
+    pub fn best_sell(&self) -> Option<&Order> {
+        self.sell_orders().max()
+    }
+
+    /// Lowest sell price minus highest buy price. Negative if the book is crossed, e.g. when it
+    /// holds orders from several regions.
+    pub fn spread(&self) -> Option<f64> {
+        Some(self.best_sell()?.price - self.best_buy()?.price)
+    }
+
+    /// A copy of the book without the orders priced more than `z` standard deviations away from
+    /// the median of their side, e.g. a fat-fingered 0.01 ISK sell. The deviation is estimated
+    /// from the median absolute deviation, so a single outlier can't inflate it and hide itself
+    /// on a thin side. The book itself is untouched.
+    pub fn without_outliers(&self, z: f64) -> OrderBook {
+        let buy_bounds = price_bounds(self.buy_orders(), z);
+        let sell_bounds = price_bounds(self.sell_orders(), z);
+
+        let orders = self
+            .orders
+            .iter()
+            .filter(|(_, order)| {
+                let bounds = if order.is_buy_order {
+                    buy_bounds
+                } else {
+                    sell_bounds
+                };
+                bounds.is_none_or(|(median, max_deviation)| {
+                    (order.price - median).abs() <= max_deviation
+                })
+            })
+            .map(|(id, order)| (*id, order.clone()))
+            .collect();
+
+        OrderBook {
+            item: self.item,
+            orders,
+            regions: self.regions.clone(),
+            last_updated: self.last_updated,
+        }
+    }
+
+    /// Highest buy and lowest sell price. With `outlier_z` set, these are taken from
+    /// [`OrderBook::without_outliers`] instead of the raw book.
+    pub fn best_prices(&self, outlier_z: Option<f64>) -> (Option<f64>, Option<f64>) {
+        let book = outlier_z.map_or(Cow::Borrowed(self), |z| {
+            Cow::Owned(self.without_outliers(z))
+        });
+
+        (
+            book.best_buy().map(|order| order.price),
+            book.best_sell().map(|order| order.price),
+        )
+    }
+
+    /// Cumulative remaining volume along one side of the book, as `(price, cumulative_volume)`.
+    /// Buys run down from the best bid and sells up from the best ask, one point per order.
+    pub fn depth_curve(&self, is_buy_order: bool) -> Vec<(f64, u64)> {
+        let (buys, sells) = self.split();
+        let side = if is_buy_order { buys } else { sells };
+
+        let mut cumulative_volume = 0;
+        side.into_iter()
+            .map(|order| {
+                cumulative_volume += u64::from(order.volume_remain);
+                (order.price, cumulative_volume)
+            })
+            .collect()
+    }
+
+    /// Volume for sale at or below each sell price, as `(price, cumulative_volume)` from the
+    /// best ask up. Orders at the same price share one point.
+    pub fn sell_depth(&self) -> Vec<(f64, u64)> {
+        collapse_depth(self.depth_curve(false))
+    }
+
+    /// Volume wanted at or above each buy price, as `(price, cumulative_volume)` from the best
+    /// bid down. Orders at the same price share one point.
+    pub fn buy_depth(&self) -> Vec<(f64, u64)> {
+        collapse_depth(self.depth_curve(true))
+    }
+
+    /// Average price per unit of selling `quantity` units into the buy orders, filling the best
+    /// bids first. `None` if the buy orders don't want that many, or `quantity` is zero.
+    pub fn buy_vwap(&self, quantity: u64) -> Option<f64> {
+        let (buys, _) = self.split();
+        fill_vwap(buys, quantity)
+    }
+
+    /// Average price per unit of buying `quantity` units from the sell orders, filling the best
+    /// asks first. `None` if there aren't that many for sale, or `quantity` is zero.
+    pub fn sell_vwap(&self, quantity: u64) -> Option<f64> {
+        let (_, sells) = self.split();
+        fill_vwap(sells, quantity)
+    }
+}
+
+/// Fills `quantity` units from `side`, in order, and returns the volume-weighted price paid.
+fn fill_vwap(side: Vec<&Order>, quantity: u64) -> Option<f64> {
+    if quantity == 0 {
+        return None;
+    }
+
+    let mut remaining = quantity;
+    let mut total_price = 0.0;
+    for order in side {
+        let filled = remaining.min(u64::from(order.volume_remain));
+        total_price += filled as f64 * order.price;
+        remaining -= filled;
+        if remaining == 0 {
+            return Some(total_price / quantity as f64);
+        }
+    }
+
+    None
+}
+
+/// Merges neighbouring points of a depth curve at the same price, keeping the last cumulative
+/// volume, which already includes the merged orders.
+fn collapse_depth(curve: Vec<(f64, u64)>) -> Vec<(f64, u64)> {
+    let mut collapsed: Vec<(f64, u64)> = Vec::with_capacity(curve.len());
+    for (price, cumulative_volume) in curve {
+        match collapsed.last_mut() {
+            Some(last) if last.0 == price => last.1 = cumulative_volume,
+            _ => collapsed.push((price, cumulative_volume)),
+        }
+    }
+    collapsed
+}
+
+/// Scales a median absolute deviation to a standard deviation, for normally distributed prices.
+const MAD_TO_STD_DEV: f64 = 1.4826;
+/// Scales a mean absolute deviation to a standard deviation, for normally distributed prices.
+const MEAN_AD_TO_STD_DEV: f64 = 1.2533;
+
+/// The median of `values`, which must be sorted and not empty.
+fn sorted_median(values: &[f64]) -> f64 {
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+/// The median price of `orders` and how far from it a price may be, `z` standard deviations.
+/// When more than half the prices equal the median, the median absolute deviation is zero and
+/// the mean absolute deviation is used instead.
+fn price_bounds<'a>(orders: impl Iterator<Item = &'a Order>, z: f64) -> Option<(f64, f64)> {
+    let mut prices: Vec<f64> = orders.map(|order| order.price).collect();
+    if prices.is_empty() {
+        return None;
+    }
+    prices.sort_by(f64::total_cmp);
+    let median = sorted_median(&prices);
+
+    let mut deviations: Vec<f64> = prices.iter().map(|price| (price - median).abs()).collect();
+    deviations.sort_by(f64::total_cmp);
+    let mad = sorted_median(&deviations);
+    let std_dev = if mad > 0.0 {
+        mad * MAD_TO_STD_DEV
+    } else {
+        deviations.iter().sum::<f64>() / deviations.len() as f64 * MEAN_AD_TO_STD_DEV
+    };
+
+    Some((median, z * std_dev))
+}
+
+/// An item's orders together with the item itself, so clients don't need to resolve names.
+#[derive(Clone, Debug, Serialize)]
+pub struct ItemOrderBook {
+    pub item: Item,
+    /// Buy orders, highest price first, followed by sell orders, lowest price first.
+    pub orders: Vec<Order>,
+}
+
+impl ItemOrderBook {
+    pub fn new(item: Item, book: &OrderBook) -> Self {
+        let (buys, sells) = book.split();
+        ItemOrderBook {
+            item,
+            orders: buys.into_iter().chain(sells).cloned().collect(),
+        }
+    }
+}
+
+/// The orders for one item at a single station, see [`Market::by_station`].
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct StationOrders {
+    pub orders: Vec<Order>,
+    /// Highest buy price at this station.
+    pub best_buy: Option<f64>,
+    /// Lowest sell price at this station.
+    pub best_sell: Option<f64>,
+}
+
+/// One day of a type's trading history in a region.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct MarketHistoryPoint {
+    pub date: NaiveDate,
+    pub average: f64,
+    pub highest: f64,
+    pub lowest: f64,
+    pub volume: u64,
+    pub order_count: u64,
+}
+
+/// CCP's reference prices for one type, from `/markets/prices/`. Either may be missing for
+/// types that have never traded.
+#[derive(Clone, Deserialize, Serialize, Debug, PartialEq)]
+pub struct MarketPrice {
+    pub type_id: TypeID,
+    /// The price industry job costs and reprocessing taxes are based on.
+    pub adjusted_price: Option<f64>,
+    pub average_price: Option<f64>,
+}
+
+/// How many calendar days [`Market::estimated_daily_turnover`] averages over.
+pub const TURNOVER_DAYS: usize = 7;
+
+/// A daily history series, oldest day first.
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MarketHistory {
+    pub points: Vec<MarketHistoryPoint>,
+}
+
+impl From<Vec<MarketHistoryPoint>> for MarketHistory {
+    fn from(points: Vec<MarketHistoryPoint>) -> Self {
+        MarketHistory { points }
+    }
+}
+
+impl MarketHistory {
+    /// Simple moving average of the daily `average` price.
+    /// Returns one value per full window, so it is empty if `window` is 0 or longer than the series.
+    pub fn sma(&self, window: usize) -> Vec<f64> {
+        if window == 0 {
+            return Vec::new();
+        }
+
+        self.points
+            .windows(window)
+            .map(|days| days.iter().map(|day| day.average).sum::<f64>() / window as f64)
+            .collect()
+    }
+
+    /// The `p`th percentile (0 to 100) of the daily `average` prices, interpolating linearly
+    /// between the closest ranks.
+    pub fn percentile(&self, p: f64) -> Option<f64> {
+        if self.points.is_empty() || !(0.0..=100.0).contains(&p) {
+            return None;
+        }
+
+        let mut averages: Vec<f64> = self.points.iter().map(|day| day.average).collect();
+        averages.sort_by(f64::total_cmp);
+
+        let rank = p / 100.0 * (averages.len() - 1) as f64;
+        let lower = rank.floor() as usize;
+        let upper = rank.ceil() as usize;
+        let weight = rank - lower as f64;
+
+        Some(averages[lower] + (averages[upper] - averages[lower]) * weight)
+    }
+
+    /// Donchian channel: the rolling `(highest, lowest)` prices over each full window.
+    pub fn donchian(&self, window: usize) -> Vec<(f64, f64)> {
+        if window == 0 {
+            return Vec::new();
+        }
+
+        self.points
+            .windows(window)
+            .map(|days| {
+                let high = days.iter().map(|day| day.highest).fold(f64::MIN, f64::max);
+                let low = days.iter().map(|day| day.lowest).fold(f64::MAX, f64::min);
+                (high, low)
+            })
+            .collect()
+    }
+}
+
+/// A fetched region book along with the orders that had to be dropped while converting it.
+#[derive(Debug)]
+pub struct FetchOutcome {
+    pub market: Market,
+    pub skipped: usize,
+    pub errors: Vec<OrderConversionError>,
+}
+
+/// The orders and headers of each market page fetched with an ETag, so pages ESI answers
+/// `304 Not Modified` for are neither downloaded nor parsed again. Needs a client with
+/// [`ESIClient::enable_etags`] called, otherwise every page is fetched in full.
+///
+/// Cheap to clone, clones share the same pages.
+#[derive(Clone, Debug, Default)]
+pub struct PageCache {
+    pages: Arc<DashMap<String, CachedPage>>,
+}
+
+#[derive(Clone, Debug)]
+struct CachedPage {
+    headers: HeaderMap,
+    orders: Arc<Vec<MarketAPIResponseOrder>>,
+}
+
+impl PageCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// How many pages are cached.
+    pub fn len(&self) -> usize {
+        self.pages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.pages.is_empty()
+    }
+
+    /// Drops the cached pages of `region` past `num_pages`, e.g. after its book shrank.
+    fn truncate(&self, region: RegionID, num_pages: usize) {
+        let prefix = format!("/markets/{region}/orders/?page=");
+        self.pages.retain(|url, _| {
+            url.strip_prefix(&prefix)
+                .and_then(|page| page.parse::<usize>().ok())
+                .is_none_or(|page| page <= num_pages)
+        });
+    }
+
+    /// Fetches the page of orders at `url`, taking it from the cache if ESI reports it unchanged.
+    async fn fetch(
+        &self,
+        client: &ESIClient,
+        url: &str,
+    ) -> Result<(HeaderMap, Arc<Vec<MarketAPIResponseOrder>>), EsiError> {
+        let response = match client.esi_get_if_modified(url).await? {
+            EsiResponse::Modified(response) => response,
+            EsiResponse::NotModified(response) => match self.pages.get(url) {
+                Some(page) => {
+                    // a 304 refreshes the cache headers, everything else stays as it was
+                    let mut headers = page.headers.clone();
+                    for name in [EXPIRES, LAST_MODIFIED] {
+                        if let Some(value) = response.headers().get(&name) {
+                            headers.insert(name, value.clone());
+                        }
+                    }
+                    return Ok((headers, page.orders.clone()));
+                }
+                // the client saw this page through another cache
+                None => client.esi_get(url).await?,
+            },
+        };
+
+        let headers = response.headers().clone();
+        let orders = Arc::new(page_orders(url, response).await?);
+        self.pages.insert(
+            String::from(url),
+            CachedPage {
+                headers: headers.clone(),
+                orders: orders.clone(),
+            },
+        );
+
+        Ok((headers, orders))
+    }
+}
+
+/// One page of orders, through `cache` if there is one.
+async fn fetch_order_page(
+    client: &ESIClient,
+    url: &str,
+    cache: Option<&PageCache>,
+) -> Result<(HeaderMap, Arc<Vec<MarketAPIResponseOrder>>), EsiError> {
+    match cache {
+        Some(cache) => cache.fetch(client, url).await,
+        None => {
+            let response = client.esi_get(url).await?;
+            let headers = response.headers().clone();
+            Ok((headers, Arc::new(page_orders(url, response).await?)))
+        }
+    }
+}
+
+/// What [`Market::fetch_region_or_stale`] got back.
+#[derive(Debug)]
+pub enum FetchResult {
+    Fresh(Market),
+    /// ESI failed with a server error and the previous snapshot should keep being served.
+    /// Holds the error that caused it.
+    Stale(String),
+}
+
+/// Whether `err` came from ESI answering with a 5xx.
+fn is_server_error(err: &anyhow::Error) -> bool {
+    err.chain().any(|cause| {
+        cause
+            .downcast_ref::<EsiError>()
+            .and_then(EsiError::status)
+            .is_some_and(|status| status.is_server_error())
+    })
+}
+
+/// The on-disk form of a [`Market`]. Books are listed rather than keyed by item, since each one
+/// already carries its item.
+#[derive(Serialize, Deserialize)]
+struct MarketSnapshot<B> {
+    last_modified: DateTime<Utc>,
+    expires: DateTime<Utc>,
+    items: Vec<B>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct Market {
-    pub items: DashMap<u32, OrderBook>,
+    pub items: DashMap<TypeID, OrderBook>,
     pub last_modified: DateTime<Utc>,
     pub expires: DateTime<Utc>,
 }
 
+impl Default for Market {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Market {
     pub fn new() -> Self {
         Market {
@@ -201,39 +1107,102 @@ impl Market {
         }
     }
 
+    /// Writes every order book and the market's timestamps to `path` as JSON. The file is written
+    /// next to `path` first and then renamed over it, so a crash never leaves a partial snapshot.
+    pub async fn save_snapshot(&self, path: &Path) -> anyhow::Result<()> {
+        let blob = {
+            let entries: Vec<_> = self.items.iter().collect();
+            serde_json::to_vec(&MarketSnapshot {
+                last_modified: self.last_modified,
+                expires: self.expires,
+                items: entries.iter().map(|entry| entry.value()).collect(),
+            })?
+        };
+
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent)
+                .await
+                .with_context(|| format!("Failed to create {}", parent.display()))?;
+        }
+        let partial = path.with_extension("partial");
+        tokio::fs::write(&partial, blob)
+            .await
+            .with_context(|| format!("Failed to write {}", partial.display()))?;
+        tokio::fs::rename(&partial, path)
+            .await
+            .with_context(|| format!("Failed to replace {}", path.display()))?;
+
+        Ok(())
+    }
+
+    /// Reads back a market written by [`Market::save_snapshot`].
+    pub async fn load_snapshot(path: &Path) -> anyhow::Result<Self> {
+        let blob = tokio::fs::read(path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))?;
+        let snapshot: MarketSnapshot<OrderBook> = serde_json::from_slice(&blob)
+            .with_context(|| format!("Unreadable market snapshot {}", path.display()))?;
+
+        Ok(Market {
+            items: snapshot
+                .items
+                .into_iter()
+                .map(|book| (book.item, book))
+                .collect(),
+            last_modified: snapshot.last_modified,
+            expires: snapshot.expires,
+        })
+    }
+
+    /// Fetches each of `regions` into its own snapshot, keeping its own `last_modified` and
+    /// `expires`, with at most `concurrency` regions being fetched at once.
+    pub async fn fetch_regions_separate(
+        regions: Vec<Region>,
+        client: Arc<ESIClient>,
+        concurrency: usize,
+    ) -> anyhow::Result<HashMap<RegionID, Market>> {
+        futures::stream::iter(regions)
+            .map(|region| {
+                let client = client.clone();
+                async move {
+                    let market = Self::fetch_region(&region, client).await?;
+                    anyhow::Ok((region.id, market))
+                }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .try_collect()
+            .await
+    }
+
     /// loads the market orders of a region.
     pub async fn fetch_regions(
         regions: Vec<Region>,
         client: Arc<ESIClient>,
     ) -> anyhow::Result<Self> {
-        println!(
-            "Markets: Starting region orderbook fetching at {}",
-            chrono::Utc::now()
-        );
-        let mut market = Market::new();
-
-        let markets = Arc::new(Mutex::new(Vec::new()));
-        let mut handles = Vec::new();
-        for region in regions {
-            let markets = markets.clone();
-            let client = client.clone();
-            let handle = tokio::spawn(async move {
-                let market = Self::fetch_region(&region, client).await.unwrap();
-                markets.lock().await.push(market);
-            });
-
-            handles.push(handle);
-        }
+        Self::fetch_all_regions_parallel(regions, client, DEFAULT_MAX_CONCURRENT_REGIONS).await
+    }
 
-        futures::future::try_join_all(handles).await?;
+    /// Fetches `regions` into one merged market like [`Market::fetch_regions`], with at most
+    /// `max_concurrent_regions` regions being fetched at once. The pages of the regions in
+    /// flight are still fetched together, so this bounds how many page requests queue up on
+    /// the client at a time.
+    pub async fn fetch_all_regions_parallel(
+        regions: Vec<Region>,
+        client: Arc<ESIClient>,
+        max_concurrent_regions: usize,
+    ) -> anyhow::Result<Self> {
+        tracing::info!("Starting region orderbook fetching");
+        let mut market = Market::new();
 
-        let markets = Arc::try_unwrap(markets)
-            .expect("Arc still has multiple strong counts")
-            .into_inner();
-        println!(
-            "Markets: Finished fetching orderbooks at {}",
-            chrono::Utc::now()
-        );
+        let markets: Vec<Market> = futures::stream::iter(regions)
+            .map(|region| {
+                let client = client.clone();
+                async move { Self::fetch_region(&region, client).await }
+            })
+            .buffer_unordered(max_concurrent_regions.max(1))
+            .try_collect()
+            .await?;
+        tracing::info!("Finished fetching orderbooks");
 
         for region in markets {
             if market.last_modified == DateTime::UNIX_EPOCH {
@@ -255,102 +1224,517 @@ impl Market {
             }
         }
 
-        return Ok(market);
+        Ok(market)
     }
 
+    /// Like [`Market::fetch_region`], but when ESI answers with a 5xx and there is a `prev`
+    /// snapshot to fall back on, the failure is reported as [`FetchResult::Stale`] instead of an
+    /// error. Other errors, or a 5xx without a previous snapshot, are still returned as errors.
+    ///
+    /// data_fetcher doesn't use this: its refresh loop already leaves a region's last applied
+    /// orders in the book whenever a fetch fails, and it fetches through a [`PageCache`].
+    pub async fn fetch_region_or_stale(
+        region: &Region,
+        prev: Option<&Market>,
+        client: Arc<ESIClient>,
+    ) -> anyhow::Result<FetchResult> {
+        match Self::fetch_region(region, client).await {
+            Ok(market) => Ok(FetchResult::Fresh(market)),
+            Err(err) if prev.is_some() && is_server_error(&err) => {
+                tracing::warn!("Serving stale orders: {err:#}");
+                Ok(FetchResult::Stale(format!("{err:#}")))
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Fetches the order book of a region, logging any orders that couldn't be converted.
+    /// Use [`Market::fetch_region_outcome`] to inspect those instead.
     pub async fn fetch_region(region: &Region, client: Arc<ESIClient>) -> anyhow::Result<Self> {
-        println!("Markets: Fetching Orderbook for {}", region.name);
-        let first_page = client
-            .esi_get(&format!("/markets/{}/orders/", region.id.get()))
-            .await?;
-        let first_page_headers = first_page.headers();
-        let num_pages: usize = first_page_headers
-            .get("x-pages")
-            .unwrap_or(&HeaderValue::from_static("1"))
-            .to_str()
-            .unwrap()
-            .parse()
-            .unwrap();
-        let last_modified: DateTime<Utc> = DateTime::parse_from_rfc2822(
-            first_page_headers
-                .get(LAST_MODIFIED)
-                .expect("No LAST_MODIFIED header in market data response?")
-                .to_str()?,
-        )?
-        .to_utc();
-        let expires: DateTime<Utc> = DateTime::parse_from_rfc2822(
-            first_page_headers
-                .get(EXPIRES)
-                .expect("No EXPIRES header in market data response?")
-                .to_str()?,
-        )?
-        .to_utc();
-
-        let pages: Vec<MarketAPIResponseOrder> =
-            first_page.json::<Vec<MarketAPIResponseOrder>>().await?;
-        let orders: Arc<Mutex<Vec<MarketAPIResponseOrder>>> = Arc::new(Mutex::new(pages));
-        let client = Arc::new(client);
+        Self::fetch_region_with_limit(region, client, PageLimit::default()).await
+    }
+
+    /// Fetches the region of a trade hub. The result holds the whole region's orders, narrow
+    /// them down with [`OrderBook::orders_at_station`] and [`TradeHub::station_id`].
+    pub async fn fetch_hub(hub: TradeHub, client: Arc<ESIClient>) -> anyhow::Result<Self> {
+        Self::fetch_region(&hub.region(), client).await
+    }
+
+    /// Like [`Market::fetch_region`], but with `page_limit` instead of the default page cap.
+    pub async fn fetch_region_with_limit(
+        region: &Region,
+        client: Arc<ESIClient>,
+        page_limit: PageLimit,
+    ) -> anyhow::Result<Self> {
+        Self::fetch_region_with_cache(region, client, page_limit, None).await
+    }
+
+    /// Like [`Market::fetch_region_with_limit`], but pages ESI reports unchanged since the last
+    /// fetch through `cache` are reused from it.
+    #[tracing::instrument(skip_all, fields(region = %region.name))]
+    pub async fn fetch_region_with_cache(
+        region: &Region,
+        client: Arc<ESIClient>,
+        page_limit: PageLimit,
+        cache: Option<&PageCache>,
+    ) -> anyhow::Result<Self> {
+        let started = std::time::Instant::now();
+        let outcome = Self::fetch_outcome(region, client, page_limit, cache)
+            .await
+            .with_context(|| format!("Failed to fetch orders for region {}", region.name))?;
+        tracing::debug!(
+            "Fetched {} items in {}ms",
+            outcome.market.items.len(),
+            started.elapsed().as_millis()
+        );
+
+        if let Some(err) = outcome.errors.first() {
+            tracing::warn!(
+                "Skipped {} orders in region {}, first error: {err}",
+                outcome.skipped,
+                region.name
+            );
+        }
+
+        Ok(outcome.market)
+    }
+
+    /// Fetches the order book of a region, collecting the orders that failed to convert.
+    /// At most `page_limit` pages are fetched.
+    pub async fn fetch_region_outcome(
+        region: &Region,
+        client: Arc<ESIClient>,
+        page_limit: PageLimit,
+    ) -> anyhow::Result<FetchOutcome> {
+        Self::fetch_outcome(region, client, page_limit, None).await
+    }
+
+    async fn fetch_outcome(
+        region: &Region,
+        client: Arc<ESIClient>,
+        page_limit: PageLimit,
+        cache: Option<&PageCache>,
+    ) -> anyhow::Result<FetchOutcome> {
+        tracing::debug!("Fetching orderbook for {}", region.name);
+        let url = format!("/markets/{}/orders/", region.id);
+        let (headers, first_page) = fetch_order_page(&client, &url, cache).await?;
+        let num_pages = page_limit.pages_to_fetch(page_count(&headers))?;
+        let (last_modified, expires) = cache_times(&headers)?;
+        if let Some(cache) = cache {
+            cache.truncate(region.id, num_pages);
+        }
+
+        let mut orders: Vec<MarketAPIResponseOrder> = first_page.as_ref().clone();
         let mut handles = Vec::new();
         for page in 2..=num_pages {
-            let pages = orders.clone();
             let client = client.clone();
+            let cache = cache.cloned();
             let region_id = region.id.get();
             let handle = tokio::spawn(async move {
-                let page = client
-                    .esi_get(&format!("/markets/{}/orders/?page={}", region_id, page))
-                    .await
-                    .expect("Failed to load page")
-                    .json::<Vec<MarketAPIResponseOrder>>()
+                let url = format!("/markets/{region_id}/orders/?page={page}");
+                fetch_order_page(&client, &url, cache.as_ref())
                     .await
-                    .expect("Failed to deserialize JSON");
-                let mut pages = pages.lock().await;
-                pages.extend(page.into_iter());
+                    .map(|(_, orders)| orders)
+            });
+
+            handles.push(handle);
+        }
+
+        let market = Market {
+            items: DashMap::new(),
+            last_modified,
+            expires,
+        };
+
+        for page in futures::future::try_join_all(handles).await? {
+            orders.extend(page?.iter().cloned());
+        }
+
+        let errors = market.insert_orders(region.id, orders);
+
+        tracing::debug!("Finished fetching orderbook for region {}", region.name);
+        Ok(FetchOutcome {
+            market,
+            skipped: errors.len(),
+            errors,
+        })
+    }
+
+    /// Like [`Market::fetch_region`], but each page is parsed while it downloads and its orders
+    /// go straight into the book. The whole region's raw orders are never held at once, which
+    /// keeps peak memory down for the largest regions. Pages held by the HTTP cache are still
+    /// buffered by it, so this saves the most with [`crate::ResponseCache::Disabled`].
+    #[tracing::instrument(skip_all, fields(region = %region.name))]
+    pub async fn fetch_region_streaming(
+        region: &Region,
+        client: Arc<ESIClient>,
+    ) -> anyhow::Result<Self> {
+        let url = format!("/markets/{}/orders/", region.id);
+        let first_page = client.esi_get(&url).await?;
+        let num_pages = PageLimit::default().pages_to_fetch(page_count(first_page.headers()))?;
+        let (last_modified, expires) = cache_times(first_page.headers())?;
+
+        let market = Market {
+            items: DashMap::new(),
+            last_modified,
+            expires,
+        };
+
+        let mut errors = stream_page_orders(&url, first_page, &market, region.id).await?;
+        let pages: Vec<Vec<OrderConversionError>> = futures::stream::iter(2..=num_pages)
+            .map(|page| {
+                let client = &client;
+                let market = &market;
+                async move {
+                    let url = format!("/markets/{}/orders/?page={page}", region.id);
+                    let response = client.esi_get(&url).await?;
+                    stream_page_orders(&url, response, market, region.id).await
+                }
+            })
+            .buffer_unordered(num_pages.max(1))
+            .try_collect()
+            .await
+            .with_context(|| format!("Failed to fetch orders for region {}", region.name))?;
+        errors.extend(pages.into_iter().flatten());
+
+        if let Some(err) = errors.first() {
+            tracing::warn!(
+                "Skipped {} orders in region {}, first error: {err}",
+                errors.len(),
+                region.name
+            );
+        }
+
+        Ok(market)
+    }
+
+    /// Fetches only the orders for `type_ids` in a region, one request per type.
+    ///
+    /// Much cheaper than [`Market::fetch_region`] for a short watchlist. The result's
+    /// `last_modified` is the newest and `expires` the soonest across the requested types.
+    pub async fn fetch_region_types(
+        region: &Region,
+        type_ids: &[TypeID],
+        client: Arc<ESIClient>,
+    ) -> anyhow::Result<Self> {
+        let mut handles = Vec::new();
+        for &type_id in type_ids {
+            let client = client.clone();
+            let region_id = region.id;
+            handles.push(tokio::spawn(async move {
+                Self::request_type_orders(&client, region_id, type_id).await
+            }));
+        }
+
+        let mut market = Market::new();
+        let mut orders = Vec::new();
+        for result in futures::future::try_join_all(handles).await? {
+            let (type_orders, last_modified, expires) = result?;
+
+            market.last_modified = market.last_modified.max(last_modified);
+            market.expires = if market.expires == DateTime::UNIX_EPOCH {
+                expires
+            } else {
+                market.expires.min(expires)
+            };
+            orders.extend(type_orders);
+        }
+
+        let errors = market.insert_orders(region.id, orders);
+        if let Some(err) = errors.first() {
+            tracing::warn!(
+                "Skipped {} orders in region {}, first error: {err}",
+                errors.len(),
+                region.name
+            );
+        }
+
+        Ok(market)
+    }
+
+    /// Fetches every page of a single type's orders in a region.
+    async fn request_type_orders(
+        client: &ESIClient,
+        region_id: RegionID,
+        type_id: TypeID,
+    ) -> anyhow::Result<(Vec<MarketAPIResponseOrder>, DateTime<Utc>, DateTime<Utc>)> {
+        let url = format!("/markets/{region_id}/orders/?type_id={type_id}");
+        let first_page = client.esi_get(&url).await?;
+        let (last_modified, expires) = cache_times(first_page.headers())?;
+        let num_pages = PageLimit::default().pages_to_fetch(page_count(first_page.headers()))?;
+
+        let mut orders: Vec<MarketAPIResponseOrder> = page_orders(&url, first_page).await?;
+        for page in 2..=num_pages {
+            let page: Vec<MarketAPIResponseOrder> =
+                client.esi_get_json(&format!("{url}&page={page}")).await?;
+            orders.extend(page);
+        }
+
+        Ok((orders, last_modified, expires))
+    }
+
+    /// Fetches the daily trading history of `type_id` in a region, oldest day first.
+    pub async fn fetch_history(
+        region_id: RegionID,
+        type_id: TypeID,
+        client: Arc<ESIClient>,
+    ) -> anyhow::Result<Vec<MarketHistoryPoint>> {
+        let url = format!("/markets/{region_id}/history/?type_id={type_id}");
+        let mut points: Vec<MarketHistoryPoint> = client.esi_get_json(&url).await?;
+        points.sort_by_key(|point| point.date);
+
+        Ok(points)
+    }
+
+    /// Fetches the universe-wide adjusted and average price of every type, keyed by type.
+    pub async fn fetch_prices(
+        client: Arc<ESIClient>,
+    ) -> anyhow::Result<DashMap<TypeID, MarketPrice>> {
+        let prices: Vec<MarketPrice> = client.esi_get_json("/markets/prices/").await?;
+
+        Ok(prices
+            .into_iter()
+            .map(|price| (price.type_id, price))
+            .collect())
+    }
+
+    /// Adds raw orders from `region_id` to the book, returning the ones that failed to convert.
+    fn insert_orders(
+        &self,
+        region_id: RegionID,
+        orders: Vec<MarketAPIResponseOrder>,
+    ) -> Vec<OrderConversionError> {
+        orders
+            .into_iter()
+            .filter_map(|order| self.insert_order(region_id, order).err())
+            .collect()
+    }
+
+    /// Adds one raw order from `region_id` to the book.
+    fn insert_order(
+        &self,
+        region_id: RegionID,
+        order_response: MarketAPIResponseOrder,
+    ) -> Result<(), OrderConversionError> {
+        let type_id = order_response.type_id;
+        // the book is created even if the order fails to convert, like a page of orders does
+        let mut book = self.items.entry(type_id).or_insert_with(|| {
+            let mut book = OrderBook::new(type_id);
+            book.regions.insert(region_id);
+            book.last_updated = self.last_modified;
+            book
+        });
+
+        let order = Order::try_from(order_response)?;
+        book.orders.insert(order.id, order);
+        Ok(())
+    }
+
+    /// Fetches the orders inside a player structure.
+    ///
+    /// Requires `client` to hold a token with the `esi-markets.structure_markets.v1` and
+    /// `esi-universe.read_structures.v1` scopes, from a character with docking access.
+    pub async fn fetch_structure(
+        structure_id: StationID,
+        client: Arc<ESIClient>,
+    ) -> anyhow::Result<Self> {
+        if !client.auth_tok_valid().await {
+            anyhow::bail!("Fetching structure markets requires a valid auth token");
+        }
+        let LocationId::Structure(structure) = LocationId::from(structure_id) else {
+            anyhow::bail!("{structure_id} is an NPC station, not a structure");
+        };
+
+        // structure orders don't carry their system, and books track the region they came from
+        let structure_info: StructureAPIResponse = client
+            .esi_get_json(&format!("/universe/structures/{structure}/"))
+            .await?;
+        let system_id = structure_info.system_id;
+        let system = Systems::new(client.clone()).get_system(system_id).await?;
+        let region_id = Constellations::new(client.clone())
+            .get_constellation(system.constellation_id)
+            .await?
+            .region_id;
+
+        let url = format!("/markets/structures/{structure}/");
+        let first_page = client.esi_get(&url).await?;
+        let (last_modified, expires) = cache_times(first_page.headers())?;
+        let num_pages = PageLimit::default().pages_to_fetch(page_count(first_page.headers()))?;
+
+        let mut responses: Vec<StructureAPIResponseOrder> = page_orders(&url, first_page).await?;
+        for page in 2..=num_pages {
+            let page: Vec<StructureAPIResponseOrder> =
+                client.esi_get_json(&format!("{url}?page={page}")).await?;
+            responses.extend(page);
+        }
+
+        let market = Market {
+            items: DashMap::new(),
+            last_modified,
+            expires,
+        };
+        let orders = responses
+            .into_iter()
+            .map(|response| response.with_system(system_id))
+            .collect();
+        let errors = market.insert_orders(region_id, orders);
+        if let Some(err) = errors.first() {
+            tracing::warn!(
+                "Skipped {} orders in structure {structure}, first error: {err}",
+                errors.len()
+            );
+        }
+
+        Ok(market)
+    }
+
+    /// Fetches the open orders of a character.
+    ///
+    /// Requires `client` to hold a token with the `esi-markets.read_character_orders.v1` scope,
+    /// and `esi-universe.read_structures.v1` if any order sits in a player structure. Stations
+    /// are resolved through `stations`, so its cache is shared with the caller.
+    pub async fn fetch_character_orders<S: UniverseCacheStore + 'static>(
+        char_id: u64,
+        client: Arc<ESIClient>,
+        stations: &Stations<S>,
+    ) -> anyhow::Result<Vec<CharacterOrder>> {
+        if !client.auth_tok_valid().await {
+            anyhow::bail!("Fetching character orders requires a valid auth token");
+        }
+
+        let responses: Vec<CharacterAPIResponseOrder> = client
+            .esi_get_json(&format!("/characters/{char_id}/orders/"))
+            .await?;
+
+        // character orders don't carry their system, so resolve it once per location
+        let mut systems: HashMap<u64, SystemID> = HashMap::new();
+        let mut orders = Vec::with_capacity(responses.len());
+        for response in responses {
+            let system_id = match systems.get(&response.location_id) {
+                Some(system_id) => *system_id,
+                None => {
+                    let system_id = match LocationId::try_from(response.location_id)? {
+                        LocationId::Station(id) => stations.get_station(id).await?.system_id,
+                        LocationId::Structure(id) => {
+                            let structure: StructureAPIResponse = client
+                                .esi_get_json(&format!("/universe/structures/{id}/"))
+                                .await?;
+                            structure.system_id
+                        }
+                    };
+                    systems.insert(response.location_id, system_id);
+                    system_id
+                }
+            };
+
+            let issued = DateTime::parse_from_rfc3339(&response.issued)?.to_utc();
+            orders.push(CharacterOrder {
+                type_id: response.type_id,
+                region_id: response.region_id,
+                is_corporation: response.is_corporation,
+                escrow: response.escrow,
+                order: Order {
+                    id: response.order_id,
+                    is_buy_order: response.is_buy_order,
+                    price: response.price,
+                    issued,
+                    expiry: issued + chrono::TimeDelta::days(response.duration.into()),
+                    location_id: StationID::try_from(response.location_id)?,
+                    system_id,
+                    min_volume: response.min_volume,
+                    range: response.range,
+                    volume_remain: response.volume_remain,
+                    volume_total: response.volume_total,
+                },
             });
+        }
+
+        Ok(orders)
+    }
 
-            handles.push(handle);
+    /// Returns the orders for `type_id` along with its [`Item`] details.
+    pub async fn orders_with_names(
+        &self,
+        type_id: TypeID,
+        items: &Items,
+    ) -> anyhow::Result<ItemOrderBook> {
+        let item = items
+            .get_item(type_id)
+            .await
+            .with_context(|| format!("failed to look up type {type_id}"))?;
+
+        let book = self
+            .items
+            .get(&type_id)
+            .ok_or_else(|| anyhow::anyhow!("no orders for type {type_id}"))?;
+
+        Ok(ItemOrderBook::new(item, &book))
+    }
+
+    /// Lists the types in the book whose name starts with `prefix`, ignoring case, sorted by name.
+    ///
+    /// Names come from `items`, which caches them, so only the first search fetches every type.
+    /// Types that can't be resolved are left out, see [`Items::resolve_missing`].
+    pub async fn search_items(&self, prefix: &str, items: &Items) -> Vec<(TypeID, String)> {
+        let type_ids: Vec<TypeID> = self.items.iter().map(|book| *book.key()).collect();
+        items.resolve_missing(type_ids).await;
+
+        items
+            .search(prefix)
+            .into_iter()
+            .filter(|(id, _)| self.items.contains_key(id))
+            .collect()
+    }
+
+    /// Average daily ISK traded in `type_id` over the [`TURNOVER_DAYS`] calendar days ending on
+    /// the latest day in `history`, each day counted as `volume * average`. ESI leaves out days
+    /// nothing traded, so missing days count as zero. `None` if the item isn't on this market or
+    /// there's no history for it.
+    pub fn estimated_daily_turnover(
+        &self,
+        type_id: TypeID,
+        history: &[MarketHistoryPoint],
+    ) -> Option<f64> {
+        if !self.items.contains_key(&type_id) {
+            return None;
         }
 
-        let market = Market {
-            items: DashMap::new(),
-            last_modified,
-            expires,
+        let latest = history.iter().map(|day| day.date).max()?;
+        let traded: f64 = history
+            .iter()
+            .filter(|day| (latest - day.date).num_days() < TURNOVER_DAYS as i64)
+            .map(|day| day.volume as f64 * day.average)
+            .sum();
+        Some(traded / TURNOVER_DAYS as f64)
+    }
+
+    /// Groups the orders for `type_id` by the station or structure they were placed at.
+    pub fn by_station(&self, type_id: TypeID) -> HashMap<StationID, StationOrders> {
+        let mut stations: HashMap<StationID, StationOrders> = HashMap::new();
+
+        let Some(book) = self.items.get(&type_id) else {
+            return stations;
         };
 
-        futures::future::try_join_all(handles).await?;
+        for order in book.orders.values() {
+            let station = stations.entry(order.location_id).or_default();
 
-        let orders = Arc::try_unwrap(orders)
-            .expect("Arc still has multiple strong counts")
-            .into_inner();
-        for order_response in orders {
-            if !(&market).items.contains_key(&order_response.type_id) {
-                market.items.insert(
-                    order_response.type_id,
-                    OrderBook::new(order_response.type_id),
-                );
+            if order.is_buy_order {
+                let best = station.best_buy.get_or_insert(order.price);
+                *best = best.max(order.price);
+            } else {
+                let best = station.best_sell.get_or_insert(order.price);
+                *best = best.min(order.price);
             }
 
-            let type_id = order_response.type_id;
-            match Order::try_from(order_response) {
-                Ok(order) => {
-                    let order_id = order.id;
-                    market
-                        .items
-                        .get_mut(&type_id)
-                        .unwrap()
-                        .orders
-                        .insert(order_id, order);
-                }
-                Err(err) => {
-                    eprintln!("{:?}", err);
-                }
-            }
+            station.orders.push(order.clone());
         }
 
-        println!(
-            "Markets: Finished fetching orderbook for region {}",
-            region.name
-        );
-        Ok(market)
+        for station in stations.values_mut() {
+            station.orders.sort_by_key(|order| order.id);
+        }
+
+        stations
     }
 
     /// This function compares two markets and returns the diff between the two.
@@ -425,15 +1809,37 @@ impl Market {
             }
         }
 
-        return diff;
+        diff
     }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::universe::{StationID, SystemID};
+    use crate::universe::{FileStore, StationID, SystemID};
     use chrono::{Duration, TimeZone, Utc};
+    use wiremock::{
+        Mock, MockServer, ResponseTemplate,
+        matchers::{header, method, path, path_regex, query_param},
+    };
+
+    /// A client for `server`, without an HTTP cache so nothing is written to disk.
+    fn mock_client(server: &MockServer) -> ESIClient {
+        let http = reqwest_middleware::ClientBuilder::new(reqwest::Client::new()).build();
+        let mut client = ESIClient::with_client(http, "test", "test", 4);
+        client.set_base_url(&server.uri());
+        client
+    }
+
+    /// A region order as ESI sends it: a sell of 100 at 5 ISK in Jita 4-4.
+    fn order_json(id: u64, type_id: u32) -> serde_json::Value {
+        serde_json::json!({
+            "duration": 90, "is_buy_order": false, "issued": "2025-01-01T00:00:00Z",
+            "location_id": 60_003_760, "min_volume": 1, "order_id": id, "price": 5.0,
+            "range": "region", "system_id": 30_000_142, "type_id": type_id,
+            "volume_remain": 100, "volume_total": 100,
+        })
+    }
 
     fn make_order(id: u64, price: f64) -> Order {
         let issued = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
@@ -455,6 +1861,1187 @@ mod tests {
         }
     }
 
+    fn make_side_order(id: u64, price: f64, is_buy_order: bool) -> Order {
+        Order {
+            is_buy_order,
+            ..make_order(id, price)
+        }
+    }
+
+    fn make_mixed_book() -> OrderBook {
+        let mut book = OrderBook::new(TypeID::from(34));
+        for order in [
+            make_side_order(1, 5.0, true),
+            make_side_order(2, 7.0, false),
+            make_side_order(3, 6.0, true),
+            make_side_order(4, 9.0, false),
+            make_side_order(5, 4.0, true),
+            make_side_order(6, 8.0, false),
+        ] {
+            book.orders.insert(order.id, order);
+        }
+        book
+    }
+
+    #[test]
+    fn test_buy_sell_partition() {
+        let book = make_mixed_book();
+        assert!(book.buy_orders().all(|order| order.is_buy_order));
+        assert!(book.sell_orders().all(|order| !order.is_buy_order));
+        assert_eq!(book.buy_orders().count(), 3);
+        assert_eq!(book.sell_orders().count(), 3);
+    }
+
+    #[test]
+    fn test_split_sort_order() {
+        let book = make_mixed_book();
+        let (buys, sells) = book.split();
+        let buy_prices: Vec<f64> = buys.iter().map(|order| order.price).collect();
+        let sell_prices: Vec<f64> = sells.iter().map(|order| order.price).collect();
+        assert_eq!(buy_prices, vec![6.0, 5.0, 4.0]);
+        assert_eq!(sell_prices, vec![7.0, 8.0, 9.0]);
+    }
+
+    #[test]
+    fn test_orders_by_location() {
+        let mut book = make_mixed_book();
+        let jita_4_4 = StationID::try_from(60_003_760).unwrap();
+        let jita = SystemID::try_from(30_000_142).unwrap();
+        for order in book.orders.values_mut() {
+            if order.id % 2 == 0 {
+                order.system_id = jita;
+            }
+        }
+        for id in [2, 4] {
+            book.orders.get_mut(&id).unwrap().location_id = jita_4_4;
+        }
+
+        let at_station: Vec<u64> = book
+            .orders_at_station(jita_4_4)
+            .iter()
+            .map(|order| order.id)
+            .collect();
+        assert_eq!(at_station, vec![2, 4]);
+
+        // buys highest first, then sells lowest first
+        let in_system: Vec<f64> = book
+            .orders_in_system(jita)
+            .iter()
+            .map(|order| order.price)
+            .collect();
+        assert_eq!(in_system, vec![7.0, 8.0, 9.0]);
+        let elsewhere: Vec<f64> = book
+            .orders_in_system(SystemID::try_from(30_000_001).unwrap())
+            .iter()
+            .map(|order| order.price)
+            .collect();
+        assert_eq!(elsewhere, vec![6.0, 5.0, 4.0]);
+
+        let empty_station = StationID::try_from(60_000_002).unwrap();
+        assert!(book.orders_at_station(empty_station).is_empty());
+    }
+
+    #[test]
+    fn test_best_buy_and_sell() {
+        let book = make_mixed_book();
+        assert_eq!(book.best_buy().unwrap().id, 3);
+        assert_eq!(book.best_sell().unwrap().id, 2);
+        assert_eq!(book.spread(), Some(1.0));
+
+        // a buy priced above every sell must not be taken as the best sell
+        let mut crossed = make_mixed_book();
+        let high_buy = make_side_order(7, 10.0, true);
+        crossed.orders.insert(high_buy.id, high_buy);
+        assert_eq!(crossed.best_buy().unwrap().id, 7);
+        assert_eq!(crossed.best_sell().unwrap().id, 2);
+        assert_eq!(crossed.spread(), Some(-3.0));
+
+        let mut sells_only = OrderBook::new(TypeID::from(34));
+        let sell = make_side_order(1, 5.0, false);
+        sells_only.orders.insert(sell.id, sell);
+        assert!(sells_only.best_buy().is_none());
+        assert_eq!(sells_only.best_sell().unwrap().price, 5.0);
+        assert_eq!(sells_only.spread(), None);
+    }
+
+    #[test]
+    fn test_without_outliers_drops_fat_fingers() {
+        let mut book = OrderBook::new(TypeID::from(34));
+        for i in 0..10 {
+            let sell = make_side_order(i, 5.0 + i as f64 * 0.01, false);
+            let buy = make_side_order(100 + i, 4.5 - i as f64 * 0.01, true);
+            book.orders.insert(sell.id, sell);
+            book.orders.insert(buy.id, buy);
+        }
+        for order in [
+            make_side_order(50, 0.01, false),
+            make_side_order(150, 9e15, true),
+        ] {
+            book.orders.insert(order.id, order);
+        }
+
+        let filtered = book.without_outliers(3.0);
+        assert_eq!(filtered.orders.len(), 20);
+        assert!(!filtered.orders.contains_key(&50));
+        assert!(!filtered.orders.contains_key(&150));
+
+        // the raw book keeps them
+        assert_eq!(book.orders.len(), 22);
+        assert_eq!(book.best_prices(None), (Some(9e15), Some(0.01)));
+        assert_eq!(book.best_prices(Some(3.0)), (Some(4.5), Some(5.0)));
+    }
+
+    #[test]
+    fn test_without_outliers_on_thin_sides() {
+        // five orders a side: a standard deviation around the mean is dragged out by the
+        // outlier itself and can never flag it at z = 3
+        let mut book = OrderBook::new(TypeID::from(34));
+        for i in 0..4 {
+            let sell = make_side_order(i, 5.0, false);
+            let buy = make_side_order(100 + i, 4.5 - i as f64 * 0.01, true);
+            book.orders.insert(sell.id, sell);
+            book.orders.insert(buy.id, buy);
+        }
+        for order in [
+            make_side_order(50, 0.01, false),
+            make_side_order(150, 9e15, true),
+        ] {
+            book.orders.insert(order.id, order);
+        }
+
+        let filtered = book.without_outliers(3.0);
+        assert_eq!(filtered.orders.len(), 8);
+        assert!(!filtered.orders.contains_key(&50));
+        assert!(!filtered.orders.contains_key(&150));
+        assert_eq!(book.best_prices(Some(3.0)), (Some(4.5), Some(5.0)));
+
+        // a single order is its own median
+        let mut single = OrderBook::new(TypeID::from(34));
+        let order = make_side_order(1, 5.0, false);
+        single.orders.insert(order.id, order);
+        assert_eq!(single.without_outliers(3.0).orders.len(), 1);
+    }
+
+    #[test]
+    fn test_depth_curve() {
+        let mut book = make_mixed_book();
+        for order in book.orders.values_mut() {
+            order.volume_remain = order.id as u32 * 10;
+        }
+
+        assert_eq!(
+            book.depth_curve(true),
+            vec![(6.0, 30), (5.0, 40), (4.0, 90)]
+        );
+        assert_eq!(
+            book.depth_curve(false),
+            vec![(7.0, 20), (8.0, 80), (9.0, 120)]
+        );
+        assert!(OrderBook::new(34.into()).depth_curve(true).is_empty());
+    }
+
+    #[test]
+    fn test_depth_collapses_equal_prices() {
+        let mut book = OrderBook::new(TypeID::from(34));
+        for (id, price, is_buy_order, volume) in [
+            (1, 5.0, false, 100),
+            (2, 5.0, false, 50),
+            (3, 5.5, false, 10),
+            (4, 6.0, false, 1),
+            (5, 6.0, false, 2),
+            (6, 4.0, true, 200),
+            (7, 4.5, true, 20),
+            (8, 4.0, true, 300),
+        ] {
+            let order = Order {
+                volume_remain: volume,
+                ..make_side_order(id, price, is_buy_order)
+            };
+            book.orders.insert(order.id, order);
+        }
+
+        assert_eq!(book.sell_depth(), vec![(5.0, 150), (5.5, 160), (6.0, 163)]);
+        assert_eq!(book.buy_depth(), vec![(4.5, 20), (4.0, 520)]);
+        // the raw curve still has a point per order
+        assert_eq!(book.depth_curve(false).len(), 5);
+        assert!(OrderBook::new(34.into()).sell_depth().is_empty());
+    }
+
+    #[test]
+    fn test_vwap_spans_orders() {
+        let mut book = OrderBook::new(TypeID::from(34));
+        for (id, price, volume, is_buy_order) in [
+            (1, 5.0, 100, false),
+            (2, 6.0, 50, false),
+            (3, 9.0, 1000, false),
+            (4, 4.0, 10, true),
+            (5, 3.0, 30, true),
+        ] {
+            let order = Order {
+                volume_remain: volume,
+                ..make_side_order(id, price, is_buy_order)
+            };
+            book.orders.insert(order.id, order);
+        }
+
+        assert_eq!(book.sell_vwap(100), Some(5.0));
+        // 100 at 5.0, 50 at 6.0 and 50 at 9.0
+        assert_eq!(book.sell_vwap(200), Some(6.25));
+        // 10 at 4.0 then 10 at 3.0
+        assert_eq!(book.buy_vwap(20), Some(3.5));
+        assert_eq!(book.sell_vwap(0), None);
+    }
+
+    #[test]
+    fn test_vwap_needs_enough_volume() {
+        let book = make_mixed_book();
+        let sell_volume = book.sell_orders().count() as u64;
+        assert!(book.sell_vwap(sell_volume).is_some());
+        assert_eq!(book.sell_vwap(sell_volume + 1), None);
+        assert_eq!(OrderBook::new(TypeID::from(34)).buy_vwap(1), None);
+    }
+
+    #[test]
+    fn test_split_empty_book() {
+        let book = OrderBook::new(TypeID::from(34));
+        let (buys, sells) = book.split();
+        assert!(buys.is_empty());
+        assert!(sells.is_empty());
+    }
+
+    /// Region 1: 1 - 2 - 3 - 4, region 2: 5, connected to 4. System 6 is in region 1 but
+    /// has no stargates.
+    fn make_graph() -> Graph {
+        let system = |id: u32| SystemID::try_from(30_000_000 + id).unwrap();
+        let region = |id: u32| RegionID::try_from(10_000_000 + id).unwrap();
+
+        let mut graph = Graph::new();
+        for id in [1, 2, 3, 4, 6] {
+            graph.add_system(system(id), region(1));
+        }
+        graph
+            .add_system(system(5), region(2))
+            .add_stargate(system(1), system(2))
+            .add_stargate(system(2), system(3))
+            .add_stargate(system(3), system(4))
+            .add_stargate(system(4), system(5));
+        graph
+    }
+
+    #[test]
+    fn test_range_reaches() {
+        let graph = make_graph();
+        let system = |id: u32| SystemID::try_from(30_000_000 + id).unwrap();
+
+        for range in [MarketOrderRange::Station, MarketOrderRange::SolarSystem] {
+            assert!(range.reaches(system(1), system(1), &graph));
+            assert!(!range.reaches(system(1), system(2), &graph));
+        }
+
+        let jumps = MarketOrderRange::Jumps(2);
+        assert!(jumps.reaches(system(1), system(1), &graph));
+        assert!(jumps.reaches(system(1), system(3), &graph));
+        assert!(!jumps.reaches(system(1), system(4), &graph));
+        // 5 is two jumps from 3, but across the region border
+        assert!(!jumps.reaches(system(3), system(5), &graph));
+        assert!(MarketOrderRange::Jumps(1).reaches(system(3), system(4), &graph));
+        assert!(!jumps.reaches(system(1), system(6), &graph));
+
+        let region = MarketOrderRange::Region;
+        assert!(region.reaches(system(1), system(4), &graph));
+        assert!(region.reaches(system(1), system(6), &graph));
+        assert!(!region.reaches(system(4), system(5), &graph));
+    }
+
+    #[test]
+    fn test_structure_orders_use_structure_location() {
+        let mut json = order_json(6789, 34);
+        json["location_id"] = 1035466617946u64.into();
+        let response: StructureAPIResponseOrder = serde_json::from_value(json).unwrap();
+
+        let system_id = SystemID::try_from(30_000_142).unwrap();
+        let order = Order::try_from(response.with_system(system_id)).unwrap();
+        assert_eq!(order.system_id, system_id);
+        assert!(matches!(order.location(), LocationId::Structure(_)));
+        let station_order = make_order(1, 1.0);
+        assert!(matches!(station_order.location(), LocationId::Station(_)));
+    }
+
+    async fn serve_region_orders(body: &str, x_pages: Option<&str>) -> (MockServer, Region) {
+        let server = MockServer::start().await;
+        let mut response = ResponseTemplate::new(200)
+            .insert_header("last-modified", "Wed, 01 Jan 2025 11:00:00 GMT")
+            .insert_header("expires", "Wed, 01 Jan 2025 11:05:00 GMT")
+            .set_body_raw(body, "application/json");
+        if let Some(x_pages) = x_pages {
+            response = response.insert_header("x-pages", x_pages);
+        }
+        Mock::given(method("GET"))
+            .and(path("/markets/10000004/orders/"))
+            .respond_with(response)
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let region = Region {
+            id: RegionID::try_from(10000004).unwrap(),
+            name: String::from("UUA-F4"),
+        };
+        (server, region)
+    }
+
+    #[tokio::test]
+    async fn test_fetch_character_orders_resolves_systems() {
+        use base64::prelude::*;
+        // a JWT that expires in 2100, only its payload is looked at
+        let payload = BASE64_STANDARD.encode(r#"{"exp":4102444800}"#);
+        let token = format!("header.{payload}.signature");
+
+        let server = MockServer::start().await;
+        let order = |order_id: u64, location_id: u64| {
+            let mut order = order_json(order_id, 34);
+            order["location_id"] = location_id.into();
+            order["region_id"] = 10000002.into();
+            order["is_corporation"] = false.into();
+            order
+        };
+        Mock::given(method("GET"))
+            .and(path("/characters/90000001/orders/"))
+            .and(header("authorization", format!("Bearer {token}")))
+            .respond_with(ResponseTemplate::new(200).set_body_json([
+                order(1, 60003760),
+                order(2, 60003760),
+                order(3, 1035466617946),
+            ]))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // each location is looked up once
+        Mock::given(method("GET"))
+            .and(path("/universe/stations/60003760/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "station_id": 60003760, "system_id": 30000142, "name": "Jita IV - Moon 4",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/universe/structures/1035466617946/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "solar_system_id": 30000144, "type_id": 35834, "name": "Perimeter - Keepstar",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut client = mock_client(&server);
+        client.auth_tok = Some(token);
+        let client = Arc::new(client);
+        let dir = std::env::temp_dir().join(format!("character-orders-{}", std::process::id()));
+        let stations = Stations::with_store(client.clone(), FileStore::new(&dir));
+
+        let orders = Market::fetch_character_orders(90000001, client, &stations)
+            .await
+            .unwrap();
+        let systems: Vec<u32> = orders
+            .iter()
+            .map(|order| order.order.system_id.get())
+            .collect();
+        assert_eq!(systems, [30000142, 30000142, 30000144]);
+        let station = StationID::try_from(60003760).unwrap();
+        assert!(stations.map.contains_key(&station));
+
+        // without a token nothing is requested
+        let anonymous = Arc::new(mock_client(&server));
+        assert!(
+            Market::fetch_character_orders(90000001, anonymous, &stations)
+                .await
+                .is_err()
+        );
+
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_or_stale_falls_back_on_server_error() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/orders/"))
+            .respond_with(ResponseTemplate::new(503))
+            .mount(&server)
+            .await;
+        let mut client = mock_client(&server);
+        client.set_downtime_backoff(std::time::Duration::ZERO);
+        let client = Arc::new(client);
+
+        let region = Region {
+            id: RegionID::try_from(10000002).unwrap(),
+            name: String::from("The Forge"),
+        };
+        let prev = Market::new();
+
+        let result = Market::fetch_region_or_stale(&region, Some(&prev), client.clone()).await;
+        assert!(matches!(result, Ok(FetchResult::Stale(_))));
+        // nothing to fall back on
+        let result = Market::fetch_region_or_stale(&region, None, client).await;
+        assert!(result.is_err());
+
+        let (server, region) = serve_region_orders("[]", None).await;
+        let client = Arc::new(mock_client(&server));
+        let result = Market::fetch_region_or_stale(&region, Some(&prev), client).await;
+        assert!(matches!(result, Ok(FetchResult::Fresh(_))));
+    }
+
+    #[tokio::test]
+    async fn test_fetch_region_without_cache_headers_errors() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/orders/"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("[]", "application/json"))
+            .mount(&server)
+            .await;
+        let region = Region {
+            id: RegionID::try_from(10000002).unwrap(),
+            name: String::from("The Forge"),
+        };
+
+        let result = Market::fetch_region(&region, Arc::new(mock_client(&server))).await;
+        assert!(result.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_regions_separate_keeps_timestamps() {
+        let server = MockServer::start().await;
+        let mut regions = Vec::new();
+        for (id, name, hour) in [(10000002, "The Forge", 10), (10000043, "Domain", 11)] {
+            Mock::given(method("GET"))
+                .and(path(format!("/markets/{id}/orders/")))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .insert_header(
+                            "last-modified",
+                            format!("Wed, 01 Jan 2025 {hour}:00:00 GMT").as_str(),
+                        )
+                        .insert_header(
+                            "expires",
+                            format!("Wed, 01 Jan 2025 {hour}:05:00 GMT").as_str(),
+                        )
+                        .set_body_raw("[]", "application/json"),
+                )
+                .mount(&server)
+                .await;
+            regions.push(Region {
+                id: RegionID::try_from(id).unwrap(),
+                name: String::from(name),
+            });
+        }
+
+        let markets = Market::fetch_regions_separate(regions, Arc::new(mock_client(&server)), 1)
+            .await
+            .unwrap();
+
+        assert_eq!(markets.len(), 2);
+        let domain = &markets[&RegionID::try_from(10000043).unwrap()];
+        assert_eq!(
+            domain.last_modified,
+            Utc.with_ymd_and_hms(2025, 1, 1, 11, 0, 0).unwrap()
+        );
+        let forge = &markets[&RegionID::try_from(10000002).unwrap()];
+        assert_eq!(
+            forge.expires,
+            Utc.with_ymd_and_hms(2025, 1, 1, 10, 5, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_fetch_all_regions_parallel_merges_books() {
+        use std::sync::Mutex;
+        use std::time::{Duration, Instant};
+        // every response takes DELAY, so a request that arrived less than DELAY ago is still
+        // in flight
+        const DELAY: Duration = Duration::from_millis(200);
+        let arrivals: Arc<Mutex<Vec<Instant>>> = Arc::default();
+        let max_in_flight = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+
+        let server = MockServer::start().await;
+        let (seen, max) = (arrivals.clone(), max_in_flight.clone());
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/markets/\d+/orders/$"))
+            .respond_with(move |req: &wiremock::Request| {
+                let mut seen = seen.lock().unwrap();
+                seen.push(Instant::now());
+                let in_flight = seen.iter().filter(|at| at.elapsed() < DELAY).count();
+                max.fetch_max(in_flight, std::sync::atomic::Ordering::SeqCst);
+
+                let region: u64 = req.url.path().split('/').nth(2).unwrap().parse().unwrap();
+                ResponseTemplate::new(200)
+                    .insert_header("last-modified", "Wed, 01 Jan 2025 11:00:00 GMT")
+                    .insert_header("expires", "Wed, 01 Jan 2025 11:05:00 GMT")
+                    .set_body_json([order_json(region, 34)])
+                    .set_delay(DELAY)
+            })
+            .expect(5)
+            .mount(&server)
+            .await;
+        let regions = [10000002, 10000043, 10000030, 10000032, 10000042]
+            .into_iter()
+            .map(|id| Region {
+                id: RegionID::try_from(id).unwrap(),
+                name: id.to_string(),
+            })
+            .collect();
+
+        let market = Market::fetch_all_regions_parallel(regions, Arc::new(mock_client(&server)), 2)
+            .await
+            .unwrap();
+
+        let book = market.items.get(&TypeID::from(34)).unwrap();
+        assert_eq!(book.orders.len(), 5);
+        assert_eq!(book.regions.len(), 5);
+        assert_eq!(max_in_flight.load(std::sync::atomic::Ordering::SeqCst), 2);
+    }
+
+    /// Runs `body` through a [`JsonArraySplitter`] `chunk_len` bytes at a time.
+    fn split_elements(body: &str, chunk_len: usize) -> Result<Vec<String>, SplitError<()>> {
+        let mut splitter = JsonArraySplitter::default();
+        let mut elements = Vec::new();
+        for chunk in body.as_bytes().chunks(chunk_len) {
+            splitter.push(chunk, |element| {
+                elements.push(String::from_utf8(element.to_vec()).unwrap());
+                Ok::<_, ()>(())
+            })?;
+        }
+        splitter.finish()?;
+        Ok(elements)
+    }
+
+    #[test]
+    fn test_json_array_splitter() {
+        let body = r#" [{"a": "x,]}"}, {"b": [1, {"c": "\"}"}]}, 5 ,"s"] "#;
+        for chunk_len in [1, 2, 7, body.len()] {
+            assert_eq!(
+                split_elements(body, chunk_len).unwrap(),
+                vec![
+                    r#"{"a": "x,]}"}"#,
+                    r#"{"b": [1, {"c": "\"}"}]}"#,
+                    "5",
+                    r#""s""#
+                ]
+            );
+        }
+
+        assert!(split_elements("[]", 1).unwrap().is_empty());
+        assert!(split_elements("", 1).unwrap().is_empty());
+        for malformed in [r#"{"a": 1}"#, "[1, 2", "[1,,2]", "[1] 2"] {
+            assert!(split_elements(malformed, 3).is_err(), "{malformed}");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_region_streaming_matches_buffered() {
+        let page = |body: serde_json::Value| {
+            ResponseTemplate::new(200)
+                .insert_header("last-modified", "Wed, 01 Jan 2025 11:00:00 GMT")
+                .insert_header("expires", "Wed, 01 Jan 2025 11:05:00 GMT")
+                .insert_header("x-pages", "2")
+                .set_body_raw(body.to_string(), "application/json")
+        };
+
+        let first_page = serde_json::json!([order_json(1, 34), order_json(2, 34)]);
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/orders/"))
+            .and(query_param("page", "2"))
+            .respond_with(page(serde_json::json!([order_json(3, 35)])))
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/orders/"))
+            .respond_with(page(first_page))
+            .mount(&server)
+            .await;
+        let region = Region {
+            id: RegionID::try_from(10000002).unwrap(),
+            name: String::from("The Forge"),
+        };
+
+        let buffered = Market::fetch_region(&region, Arc::new(mock_client(&server)))
+            .await
+            .unwrap();
+        let streamed = Market::fetch_region_streaming(&region, Arc::new(mock_client(&server)))
+            .await
+            .unwrap();
+
+        assert_eq!(streamed.items.len(), 2);
+        assert_eq!(streamed.expires, buffered.expires);
+        assert!(buffered.delta(&streamed).new.is_empty());
+        assert!(streamed.delta(&buffered).new.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_fetch_empty_region() {
+        for (body, x_pages) in [("[]", Some("1")), ("[]", None), ("", Some("0"))] {
+            let (server, region) = serve_region_orders(body, x_pages).await;
+
+            let market = Market::fetch_region(&region, Arc::new(mock_client(&server)))
+                .await
+                .unwrap();
+            assert!(market.items.is_empty());
+            assert_eq!(
+                market.last_modified,
+                Utc.with_ymd_and_hms(2025, 1, 1, 11, 0, 0).unwrap()
+            );
+            assert_eq!(
+                market.expires,
+                Utc.with_ymd_and_hms(2025, 1, 1, 11, 5, 0).unwrap()
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn test_fetch_history() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/history/"))
+            .and(query_param("type_id", "34"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"average": 5.2, "date": "2025-01-02", "highest": 5.5, "lowest": 5.0,
+                 "order_count": 1200, "volume": 3_000_000_000u64},
+                {"average": 5.0, "date": "2025-01-01", "highest": 5.1, "lowest": 4.9,
+                 "order_count": 1100, "volume": 2_500_000_000u64},
+            ])))
+            .mount(&server)
+            .await;
+
+        let region_id = RegionID::try_from(10_000_002).unwrap();
+        let client = Arc::new(mock_client(&server));
+        let history = Market::fetch_history(region_id, TypeID::from(34), client)
+            .await
+            .unwrap();
+
+        assert_eq!(history.len(), 2);
+        let first_day = NaiveDate::from_ymd_opt(2025, 1, 1).unwrap();
+        assert_eq!(history[0].date, first_day);
+        assert_eq!(history[1].volume, 3_000_000_000);
+        assert_eq!(history[1].order_count, 1200);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_prices() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/markets/prices/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                {"type_id": 34, "adjusted_price": 4.9, "average_price": 5.1},
+                {"type_id": 32772, "average_price": 0.0},
+            ])))
+            .mount(&server)
+            .await;
+
+        let client = Arc::new(mock_client(&server));
+        let prices = Market::fetch_prices(client).await.unwrap();
+        assert_eq!(prices.len(), 2);
+        let tritanium = prices.get(&TypeID::from(34)).unwrap();
+        assert_eq!(tritanium.adjusted_price, Some(4.9));
+        assert_eq!(tritanium.average_price, Some(5.1));
+        let never_adjusted = prices.get(&TypeID::from(32772)).unwrap();
+        assert_eq!(never_adjusted.adjusted_price, None);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_single_page_without_x_pages() {
+        let body = serde_json::json!([order_json(6789, 34)]);
+        let (server, region) = serve_region_orders(&body.to_string(), None).await;
+
+        let market = Market::fetch_region(&region, Arc::new(mock_client(&server)))
+            .await
+            .unwrap();
+        let book = market.items.get(&TypeID::from(34)).unwrap();
+        assert_eq!(book.orders.len(), 1);
+        assert!(book.regions.contains(&region.id));
+        assert_eq!(book.last_updated, market.last_modified);
+    }
+
+    #[tokio::test]
+    async fn test_unchanged_pages_are_reused() {
+        let body = serde_json::json!([order_json(4321, 34)]);
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/orders/"))
+            .and(header("if-none-match", "\"v1\""))
+            .respond_with(
+                ResponseTemplate::new(304)
+                    .insert_header("etag", "\"v1\"")
+                    .insert_header("expires", "Wed, 01 Jan 2025 11:10:00 GMT"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/orders/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("etag", "\"v1\"")
+                    .insert_header("last-modified", "Wed, 01 Jan 2025 11:00:00 GMT")
+                    .insert_header("expires", "Wed, 01 Jan 2025 11:05:00 GMT")
+                    .set_body_raw(body.to_string(), "application/json"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut client = mock_client(&server);
+        client.enable_etags();
+        let client = Arc::new(client);
+        let region = Region {
+            id: RegionID::try_from(10000002).unwrap(),
+            name: String::from("The Forge"),
+        };
+        let cache = PageCache::new();
+
+        let limit = PageLimit::default();
+        let first = Market::fetch_region_with_cache(&region, client.clone(), limit, Some(&cache))
+            .await
+            .unwrap();
+        assert_eq!(cache.len(), 1);
+        let second = Market::fetch_region_with_cache(&region, client, limit, Some(&cache))
+            .await
+            .unwrap();
+
+        let book = second.items.get(&TypeID::from(34)).unwrap();
+        assert_eq!(book.orders.len(), 1);
+        assert_eq!(second.last_modified, first.last_modified);
+        assert_eq!(
+            second.expires,
+            Utc.with_ymd_and_hms(2025, 1, 1, 11, 10, 0).unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_page_cache_drops_pages_past_the_last() {
+        let page = |pages: usize, etag: &str, order_id: u64| {
+            ResponseTemplate::new(200)
+                .insert_header("x-pages", pages.to_string())
+                .insert_header("etag", etag)
+                .insert_header("last-modified", "Wed, 01 Jan 2025 11:00:00 GMT")
+                .insert_header("expires", "Wed, 01 Jan 2025 11:05:00 GMT")
+                .set_body_json([order_json(order_id, 34)])
+        };
+
+        let server = MockServer::start().await;
+        // three pages at first, then the book shrinks to one
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/orders/"))
+            .respond_with(page(3, "\"v1\"", 1))
+            .up_to_n_times(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/markets/10000002/orders/"))
+            .respond_with(page(1, "\"v2\"", 4))
+            .mount(&server)
+            .await;
+        for n in [2, 3] {
+            Mock::given(method("GET"))
+                .and(path("/markets/10000002/orders/"))
+                .and(query_param("page", n.to_string()))
+                .respond_with(page(3, "\"v1\"", n))
+                .expect(1)
+                .with_priority(1)
+                .mount(&server)
+                .await;
+        }
+
+        // with the in-memory HTTP cache in front, as ESIClient::in_memory sets up
+        let mut client = ESIClient::in_memory("test", "test", 4);
+        client.set_base_url(&server.uri());
+        client.enable_etags();
+        let client = Arc::new(client);
+        let region = Region {
+            id: RegionID::try_from(10000002).unwrap(),
+            name: String::from("The Forge"),
+        };
+        let cache = PageCache::new();
+
+        let limit = PageLimit::default();
+        let first = Market::fetch_region_with_cache(&region, client.clone(), limit, Some(&cache))
+            .await
+            .unwrap();
+        assert_eq!(first.items.get(&TypeID::from(34)).unwrap().orders.len(), 3);
+        assert_eq!(cache.len(), 3);
+
+        let second = Market::fetch_region_with_cache(&region, client, limit, Some(&cache))
+            .await
+            .unwrap();
+        let book = second.items.get(&TypeID::from(34)).unwrap();
+        assert_eq!(book.orders.keys().copied().collect::<Vec<_>>(), [4]);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_fetch_structure_orders() {
+        use base64::prelude::*;
+        let structure_id = StationID::try_from(1_042_508_032_148).unwrap();
+        let server = MockServer::start().await;
+        let exp = Utc::now().timestamp() + 3600;
+        let payload = BASE64_STANDARD.encode(serde_json::json!({ "exp": exp }).to_string());
+        let token = format!("e30.{payload}.sig");
+        Mock::given(method("POST"))
+            .and(path("/v2/oauth/token"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!({ "access_token": token })),
+            )
+            .mount(&server)
+            .await;
+        for (url, body) in [
+            (
+                format!("/universe/structures/{structure_id}/"),
+                serde_json::json!({"name": "Perimeter - Trade Hub", "solar_system_id": 30000144,
+                                   "type_id": 35834}),
+            ),
+            (
+                String::from("/universe/systems/30000144/"),
+                serde_json::json!({"system_id": 30000144, "constellation_id": 20000020,
+                                   "position": {"x": 0.0, "y": 0.0, "z": 0.0},
+                                   "security_status": 0.95, "name": "Perimeter"}),
+            ),
+            (
+                String::from("/universe/constellations/20000020/"),
+                serde_json::json!({"constellation_id": 20000020, "name": "Kimotoro",
+                                   "region_id": 10000002, "systems": [30000144]}),
+            ),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(url))
+                .respond_with(ResponseTemplate::new(200).set_body_json(body))
+                .mount(&server)
+                .await;
+        }
+        let mut order = order_json(99, 34);
+        order["location_id"] = structure_id.get().into();
+        Mock::given(method("GET"))
+            .and(path(format!("/markets/structures/{structure_id}/")))
+            .and(header("authorization", format!("Bearer {token}").as_str()))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("last-modified", "Wed, 01 Jan 2025 11:00:00 GMT")
+                    .insert_header("expires", "Wed, 01 Jan 2025 11:05:00 GMT")
+                    .set_body_json([order]),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let mut client = mock_client(&server);
+        client.set_login_url(&server.uri());
+        // without a token the structure isn't even looked up
+        let anonymous = Arc::new(client.clone());
+        let result = Market::fetch_structure(structure_id, anonymous).await;
+        assert!(result.is_err());
+
+        client
+            .load_auth_tok("refresh".into(), "id".into(), "secret".into())
+            .await
+            .unwrap();
+        let market = Market::fetch_structure(structure_id, Arc::new(client))
+            .await
+            .unwrap();
+
+        let book = market.items.get(&TypeID::from(34)).unwrap();
+        let order = &book.orders[&99];
+        assert_eq!(order.location_id, structure_id);
+        assert_eq!(order.system_id, SystemID::try_from(30000144).unwrap());
+        let forge = RegionID::try_from(10000002).unwrap();
+        assert!(book.regions.contains(&forge));
+    }
+
+    #[test]
+    fn test_range_deserialize() {
+        let ranges: Vec<MarketOrderRange> =
+            serde_json::from_str(r#"["station", "solarsystem", "1", "5", "40", "region"]"#)
+                .unwrap();
+        assert_eq!(
+            ranges,
+            vec![
+                MarketOrderRange::Station,
+                MarketOrderRange::SolarSystem,
+                MarketOrderRange::Jumps(1),
+                MarketOrderRange::Jumps(5),
+                MarketOrderRange::Jumps(40),
+                MarketOrderRange::Region,
+            ]
+        );
+        // "this system only" and "one jump" are different ranges
+        assert_ne!(ranges[1], ranges[2]);
+
+        let unknown = serde_json::from_str::<MarketOrderRange>(r#""constellation""#);
+        assert!(unknown.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_round_trip() {
+        let dir = std::env::temp_dir().join(format!("market-snapshot-{}", std::process::id()));
+        let path = dir.join("10000002.json");
+
+        let mut market = Market::new();
+        market.last_modified = Utc.with_ymd_and_hms(2024, 1, 1, 0, 0, 0).unwrap();
+        market.expires = market.last_modified + Duration::minutes(5);
+        let mut book = make_mixed_book();
+        book.orders.get_mut(&1).unwrap().range = MarketOrderRange::Jumps(5);
+        book.orders.get_mut(&2).unwrap().range = MarketOrderRange::SolarSystem;
+        book.regions.insert(RegionID::try_from(10000002).unwrap());
+        market.items.insert(book.item, book.clone());
+
+        market.save_snapshot(&path).await.unwrap();
+        let loaded = Market::load_snapshot(&path).await.unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(loaded.last_modified, market.last_modified);
+        assert_eq!(loaded.expires, market.expires);
+        assert_eq!(loaded.items.len(), 1);
+        let loaded_book = loaded.items.get(&book.item).unwrap();
+        assert_eq!(loaded_book.orders, book.orders);
+        assert_eq!(loaded_book.regions, book.regions);
+        assert!(market.delta(&loaded).new.is_empty());
+    }
+
+    #[test]
+    fn test_page_limit_caps_absurd_x_pages() {
+        let x_pages: usize = "100000".parse().unwrap();
+
+        let lenient = PageLimit::default();
+        assert_eq!(lenient.pages_to_fetch(x_pages).unwrap(), 500);
+        assert_eq!(lenient.pages_to_fetch(42).unwrap(), 42);
+
+        let strict = PageLimit {
+            max_pages: 10,
+            strict: true,
+        };
+        assert!(strict.pages_to_fetch(x_pages).is_err());
+        assert_eq!(strict.pages_to_fetch(10).unwrap(), 10);
+    }
+
+    fn make_item(id: u32, name: &str) -> Item {
+        serde_json::from_value(serde_json::json!({
+            "id": id,
+            "group_id": 18,
+            "icon_id": 22,
+            "market_group_id": 1857,
+            "name": name,
+            "description": "",
+        }))
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn test_search_items_by_prefix() {
+        let items = Items::new(Arc::new(ESIClient::in_memory("test", "test", 1)));
+        let market = Market::new();
+        for (id, name) in [
+            (34, "Tritanium"),
+            (35, "Pyerite"),
+            (40, "Megacyte"),
+            (41, "Trinity"),
+        ] {
+            let id = TypeID::from(id);
+            items.map.insert(id, make_item(id.get(), name));
+            market.items.insert(id, OrderBook::new(id));
+        }
+        // known type without orders in this market
+        items.map.insert(TypeID::from(36), make_item(36, "Tripod"));
+
+        let matches = market.search_items("tri", &items).await;
+        assert_eq!(
+            matches,
+            vec![
+                (TypeID::from(41), String::from("Trinity")),
+                (TypeID::from(34), String::from("Tritanium"))
+            ]
+        );
+        assert!(market.search_items("Zydrine", &items).await.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_search_items_skips_unresolvable_types() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/types/34/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "type_id": 34, "group_id": 18, "icon_id": 22, "market_group_id": 1857,
+                "name": "Tritanium", "description": "",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        // no market group
+        Mock::given(method("GET"))
+            .and(path("/universe/types/670/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "type_id": 670, "group_id": 29, "icon_id": 73, "name": "Capsule",
+                "description": "",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/universe/types/99999/"))
+            .respond_with(
+                ResponseTemplate::new(404)
+                    .insert_header("x-esi-error-limit-remain", "99")
+                    .insert_header("x-esi-error-limit-reset", "0"),
+            )
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let items = Items::new(Arc::new(mock_client(&server)));
+        let market = Market::new();
+        for id in [34, 670, 99999] {
+            market
+                .items
+                .insert(TypeID::from(id), OrderBook::new(TypeID::from(id)));
+        }
+
+        // every search after the first is answered without asking ESI
+        for _ in 0..3 {
+            let matches = market.search_items("", &items).await;
+            assert_eq!(matches, vec![(TypeID::from(34), String::from("Tritanium"))]);
+        }
+    }
+
+    #[test]
+    fn test_item_order_book_sorts_sides() {
+        let item = make_item(34, "Tritanium");
+
+        let named = ItemOrderBook::new(item.clone(), &make_mixed_book());
+        let ids: Vec<u64> = named.orders.iter().map(|order| order.id).collect();
+        assert_eq!(named.item, item);
+        assert_eq!(ids, vec![3, 1, 5, 2, 6, 4]);
+    }
+
+    #[test]
+    fn test_by_station_groups_orders() {
+        let jita = StationID::try_from(60_003_760).unwrap();
+        let amarr = StationID::try_from(60_008_494).unwrap();
+
+        let market = Market::new();
+        let mut book = OrderBook::new(TypeID::from(34));
+        for (order, location_id) in [
+            (make_side_order(1, 5.0, true), jita),
+            (make_side_order(2, 7.0, false), jita),
+            (make_side_order(3, 6.0, true), jita),
+            (make_side_order(4, 9.0, false), amarr),
+            (make_side_order(5, 8.0, false), amarr),
+        ] {
+            let order = Order {
+                location_id,
+                ..order
+            };
+            book.orders.insert(order.id, order);
+        }
+        market.items.insert(TypeID::from(34), book);
+
+        let stations = market.by_station(TypeID::from(34));
+        assert_eq!(stations.len(), 2);
+
+        let jita_orders = &stations[&jita];
+        let ids: Vec<u64> = jita_orders.orders.iter().map(|order| order.id).collect();
+        assert_eq!(ids, vec![1, 2, 3]);
+        assert_eq!(jita_orders.best_buy, Some(6.0));
+        assert_eq!(jita_orders.best_sell, Some(7.0));
+
+        let amarr_orders = &stations[&amarr];
+        assert_eq!(amarr_orders.orders.len(), 2);
+        assert_eq!(amarr_orders.best_buy, None);
+        assert_eq!(amarr_orders.best_sell, Some(8.0));
+
+        assert!(market.by_station(TypeID::from(35)).is_empty());
+    }
+
+    fn make_history(days: &[(f64, f64, f64)]) -> MarketHistory {
+        let start = NaiveDate::from_ymd_opt(2020, 1, 1).unwrap();
+        days.iter()
+            .enumerate()
+            .map(|(i, &(average, highest, lowest))| MarketHistoryPoint {
+                date: start + Duration::days(i as i64),
+                average,
+                highest,
+                lowest,
+                volume: 100,
+                order_count: 10,
+            })
+            .collect::<Vec<_>>()
+            .into()
+    }
+
+    #[test]
+    fn test_estimated_daily_turnover() {
+        let market = Market::new();
+        market
+            .items
+            .insert(TypeID::from(34), OrderBook::new(TypeID::from(34)));
+
+        // ten days, only the last seven count: 1000 ISK a day, except 2000 on the last one
+        let mut history = make_history(&[(10.0, 10.0, 10.0); 10]).points;
+        for day in &mut history[..3] {
+            day.volume = 1_000_000;
+        }
+        history[9].average = 20.0;
+
+        let turnover = market.estimated_daily_turnover(TypeID::from(34), &history);
+        assert_eq!(turnover, Some(8000.0 / 7.0));
+
+        // gaps are days nothing traded: of these, only the last two fall within the window
+        let gappy = [history[0].clone(), history[6].clone(), history[9].clone()];
+        let turnover = market.estimated_daily_turnover(TypeID::from(34), &gappy);
+        assert_eq!(turnover, Some(3000.0 / 7.0));
+        assert_eq!(market.estimated_daily_turnover(TypeID::from(34), &[]), None);
+        assert_eq!(
+            market.estimated_daily_turnover(TypeID::from(35), &history),
+            None
+        );
+    }
+
+    #[test]
+    fn test_history_sma() {
+        let history = make_history(&[
+            (1.0, 1.0, 1.0),
+            (2.0, 2.0, 2.0),
+            (3.0, 3.0, 3.0),
+            (4.0, 4.0, 4.0),
+            (5.0, 5.0, 5.0),
+        ]);
+        assert_eq!(history.sma(3), vec![2.0, 3.0, 4.0]);
+        assert_eq!(history.sma(5), vec![3.0]);
+        assert!(history.sma(6).is_empty());
+        assert!(history.sma(0).is_empty());
+    }
+
+    #[test]
+    fn test_history_percentile() {
+        let history = make_history(&[
+            (5.0, 5.0, 5.0),
+            (1.0, 1.0, 1.0),
+            (4.0, 4.0, 4.0),
+            (2.0, 2.0, 2.0),
+            (3.0, 3.0, 3.0),
+        ]);
+        assert_eq!(history.percentile(0.0), Some(1.0));
+        assert_eq!(history.percentile(25.0), Some(2.0));
+        assert_eq!(history.percentile(50.0), Some(3.0));
+        assert_eq!(history.percentile(100.0), Some(5.0));
+        assert!((history.percentile(90.0).unwrap() - 4.6).abs() < 1e-9);
+        assert_eq!(history.percentile(101.0), None);
+        assert_eq!(MarketHistory::default().percentile(50.0), None);
+    }
+
+    #[test]
+    fn test_history_donchian() {
+        let history = make_history(&[(2.0, 3.0, 1.0), (5.0, 6.0, 4.0), (3.0, 4.0, 0.5)]);
+        assert_eq!(history.donchian(2), vec![(6.0, 1.0), (6.0, 0.5)]);
+    }
+
     #[test]
     fn test_delta_empty() {
         let m1 = Market::new();
@@ -468,47 +3055,47 @@ mod tests {
     #[test]
     fn test_delta_removed() {
         let m1 = Market::new();
-        let mut book = OrderBook::new(100);
+        let mut book = OrderBook::new(TypeID::from(100));
         let o = make_order(1, 10.0);
         book.orders.insert(o.id, o.clone());
-        m1.items.insert(100, book);
+        m1.items.insert(TypeID::from(100), book);
         let m2 = Market::new();
         let diff = m1.delta(&m2);
-        assert_eq!(diff.removed.get(&100).unwrap(), &vec![1]);
-        assert!(diff.new.get(&100).is_none());
-        assert!(diff.modified.get(&100).is_none());
+        assert_eq!(diff.removed.get(&TypeID::from(100)).unwrap(), &vec![1]);
+        assert!(!diff.new.contains_key(&TypeID::from(100)));
+        assert!(!diff.modified.contains_key(&TypeID::from(100)));
     }
 
     #[test]
     fn test_delta_new() {
         let m1 = Market::new();
         let m2 = Market::new();
-        let mut book = OrderBook::new(200);
+        let mut book = OrderBook::new(TypeID::from(200));
         let o = make_order(2, 20.0);
         book.orders.insert(o.id, o.clone());
-        m2.items.insert(200, book);
+        m2.items.insert(TypeID::from(200), book);
         let diff = m1.delta(&m2);
-        assert_eq!(diff.new.get(&200).unwrap(), &vec![o]);
-        assert!(diff.modified.get(&200).is_none());
-        assert!(diff.removed.get(&200).is_none());
+        assert_eq!(diff.new.get(&TypeID::from(200)).unwrap(), &vec![o]);
+        assert!(!diff.modified.contains_key(&TypeID::from(200)));
+        assert!(!diff.removed.contains_key(&TypeID::from(200)));
     }
 
     #[test]
     fn test_delta_modified() {
         let m1 = Market::new();
         let m2 = Market::new();
-        let mut b1 = OrderBook::new(300);
+        let mut b1 = OrderBook::new(TypeID::from(300));
         let o1 = make_order(3, 30.0);
         b1.orders.insert(o1.id, o1.clone());
-        m1.items.insert(300, b1);
-        let mut b2 = OrderBook::new(300);
+        m1.items.insert(TypeID::from(300), b1);
+        let mut b2 = OrderBook::new(TypeID::from(300));
         let o2 = make_order(3, 35.0);
         b2.orders.insert(o2.id, o2.clone());
-        m2.items.insert(300, b2);
+        m2.items.insert(TypeID::from(300), b2);
         let diff = m1.delta(&m2);
-        assert!(diff.new.get(&300).is_none());
-        assert!(diff.removed.get(&300).unwrap().is_empty());
-        assert_eq!(diff.modified.get(&300).unwrap(), &vec![o2]);
+        assert!(!diff.new.contains_key(&TypeID::from(300)));
+        assert!(diff.removed.get(&TypeID::from(300)).unwrap().is_empty());
+        assert_eq!(diff.modified.get(&TypeID::from(300)).unwrap(), &vec![o2]);
     }
 
     #[test]
@@ -517,29 +3104,32 @@ mod tests {
         let m2 = Market::new();
 
         // Add an item with multiple orders to m1
-        let mut b1 = OrderBook::new(100);
+        let mut b1 = OrderBook::new(TypeID::from(100));
         let o1 = make_order(1, 10.0);
         let o2 = make_order(2, 20.0);
         b1.orders.insert(o1.id, o1.clone());
         b1.orders.insert(o2.id, o2.clone());
-        m1.items.insert(100, b1);
+        m1.items.insert(TypeID::from(100), b1);
 
         // Add same item to m2 with one modified order, one unchanged, and one new
-        let mut b2 = OrderBook::new(100);
+        let mut b2 = OrderBook::new(TypeID::from(100));
         let o1_unchanged = o1.clone(); // same order
         let o2_modified = make_order(2, 25.0); // modified price
         let o3_new = make_order(3, 30.0); // new order
         b2.orders.insert(o1_unchanged.id, o1_unchanged);
         b2.orders.insert(o2_modified.id, o2_modified.clone());
         b2.orders.insert(o3_new.id, o3_new.clone());
-        m2.items.insert(100, b2);
+        m2.items.insert(TypeID::from(100), b2);
 
         let diff = m1.delta(&m2);
 
         // Should have one modified order and one new order
-        assert_eq!(diff.modified.get(&100).unwrap(), &vec![o2_modified]);
-        assert_eq!(diff.new.get(&100).unwrap(), &vec![o3_new]);
-        assert!(diff.removed.get(&100).unwrap().is_empty());
+        assert_eq!(
+            diff.modified.get(&TypeID::from(100)).unwrap(),
+            &vec![o2_modified]
+        );
+        assert_eq!(diff.new.get(&TypeID::from(100)).unwrap(), &vec![o3_new]);
+        assert!(diff.removed.get(&TypeID::from(100)).unwrap().is_empty());
     }
 
     #[test]
@@ -547,21 +3137,64 @@ mod tests {
         let m1 = Market::new();
         let m2 = Market::new();
 
-        let mut b1 = OrderBook::new(100);
+        let mut b1 = OrderBook::new(TypeID::from(100));
         let o1 = make_order(1, 10.0);
         b1.orders.insert(o1.id, o1.clone());
-        m1.items.insert(100, b1);
+        m1.items.insert(TypeID::from(100), b1);
 
-        let mut b2 = OrderBook::new(100);
+        let mut b2 = OrderBook::new(TypeID::from(100));
         let o1_same = o1.clone(); // exactly the same order
         b2.orders.insert(o1_same.id, o1_same);
-        m2.items.insert(100, b2);
+        m2.items.insert(TypeID::from(100), b2);
 
         let diff = m1.delta(&m2);
 
         // No changes should be detected
-        assert!(diff.new.get(&100).is_none());
-        assert!(diff.modified.get(&100).unwrap().is_empty());
-        assert!(diff.removed.get(&100).unwrap().is_empty());
+        assert!(!diff.new.contains_key(&TypeID::from(100)));
+        assert!(diff.modified.get(&TypeID::from(100)).unwrap().is_empty());
+        assert!(diff.removed.get(&TypeID::from(100)).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_diff_summary() {
+        let m1 = Market::new();
+        let m2 = Market::new();
+
+        let mut b1 = OrderBook::new(TypeID::from(100));
+        for order in [make_order(1, 10.0), make_order(2, 20.0)] {
+            b1.orders.insert(order.id, order);
+        }
+        let removed = Order {
+            volume_remain: 4,
+            ..make_order(3, 2.5)
+        };
+        b1.orders.insert(removed.id, removed);
+        m1.items.insert(TypeID::from(100), b1);
+
+        let mut b2 = OrderBook::new(TypeID::from(100));
+        for order in [make_order(1, 10.0), make_order(2, 25.0)] {
+            b2.orders.insert(order.id, order);
+        }
+        let new = Order {
+            volume_remain: 3,
+            ..make_order(4, 100.0)
+        };
+        b2.orders.insert(new.id, new);
+        m2.items.insert(TypeID::from(100), b2);
+
+        let summary = m1.delta(&m2).summary(&m1);
+        assert_eq!(
+            summary,
+            DiffSummary {
+                new_orders: 1,
+                modified_orders: 1,
+                removed_orders: 1,
+                volume_added: 3,
+                volume_removed: 4,
+                isk_added: 300.0,
+                isk_removed: 10.0,
+            }
+        );
+        assert_eq!(MarketDiff::new().summary(&m1), DiffSummary::default());
     }
 }
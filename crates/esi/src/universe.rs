@@ -1,15 +1,24 @@
-use dashmap::DashMap;
+use dashmap::{DashMap, DashSet};
+use futures::{StreamExt, TryStreamExt};
+use reqwest::StatusCode;
 use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, HashSet, VecDeque};
 use std::error::Error;
 use std::fmt::{self};
+use std::future::Future;
 use std::ops::Range;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
-use crate::ESIClient;
+use crate::{ESIClient, EsiError, decode_json, page_count};
+
+pub mod hubs;
 
 /// This struct represents a geospatial point in the EvE universe.
 /// i have no idea what that means
-#[derive(Clone, Debug, Copy, Deserialize, PartialEq)]
+#[derive(Clone, Debug, Copy, Deserialize, Serialize, PartialEq)]
 pub struct Point {
     x: f64,
     y: f64,
@@ -19,12 +28,50 @@ impl Point {
     pub fn new(x: f64, y: f64, z: f64) -> Self {
         Point { x, y, z }
     }
+
+    pub fn x(&self) -> f64 {
+        self.x
+    }
+
+    pub fn y(&self) -> f64 {
+        self.y
+    }
+
+    pub fn z(&self) -> f64 {
+        self.z
+    }
+
+    /// Straight-line distance to `other`, in metres.
+    pub fn distance(&self, other: &Point) -> f64 {
+        let (dx, dy, dz) = (self.x - other.x, self.y - other.y, self.z - other.z);
+        (dx * dx + dy * dy + dz * dz).sqrt()
+    }
+
+    /// Straight-line distance to `other` in light years, the unit jump drive ranges are given in.
+    pub fn distance_ly(&self, other: &Point) -> f64 {
+        self.distance(other) / METRES_PER_LIGHT_YEAR
+    }
 }
 
+/// The light year EVE uses for jump ranges.
+pub const METRES_PER_LIGHT_YEAR: f64 = 9_460_730_472_580_800.0;
+
 #[derive(Debug)]
 pub struct InvalidIDError {
     pub value: u64,
     pub acceptable: Range<u64>,
+    /// IDs at or above this are valid too, e.g. structure IDs next to the NPC station range.
+    pub acceptable_from: Option<u64>,
+}
+
+impl InvalidIDError {
+    fn new(value: u32, acceptable: Range<u32>) -> Self {
+        InvalidIDError {
+            value: value.into(),
+            acceptable: acceptable.start.into()..acceptable.end.into(),
+            acceptable_from: None,
+        }
+    }
 }
 
 impl fmt::Display for InvalidIDError {
@@ -33,19 +80,215 @@ impl fmt::Display for InvalidIDError {
             f,
             "Value {} is outside the valid range [{}, {}]",
             self.value, self.acceptable.start, self.acceptable.end
-        )
+        )?;
+        if let Some(from) = self.acceptable_from {
+            write!(f, " and below {from}")?;
+        }
+        Ok(())
     }
 }
 
 impl Error for InvalidIDError {}
 
+/// Why a universe lookup failed.
+#[derive(Debug)]
+pub enum UniverseError {
+    /// The request failed, or ESI answered with an error status other than 404.
+    Http(EsiError),
+    /// ESI answered with a body that isn't the expected JSON.
+    Deserialize(EsiError),
+    /// ESI doesn't know the ID requested through `url`.
+    NotFound {
+        url: String,
+    },
+    InvalidId(InvalidIDError),
+    /// The type exists, but can't be traded on the market.
+    NonMarketable(NonMarketableTypeError),
+}
+
+impl UniverseError {
+    /// The status ESI answered with, if it answered at all. A 420 means the error limit was hit.
+    pub fn status(&self) -> Option<StatusCode> {
+        match self {
+            UniverseError::Http(err) | UniverseError::Deserialize(err) => err.status(),
+            UniverseError::NotFound { .. } => Some(StatusCode::NOT_FOUND),
+            UniverseError::InvalidId(_) | UniverseError::NonMarketable(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for UniverseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UniverseError::Http(err) | UniverseError::Deserialize(err) => write!(f, "{err}"),
+            UniverseError::NotFound { url } => write!(f, "Nothing found at {url}"),
+            UniverseError::InvalidId(err) => write!(f, "{err}"),
+            UniverseError::NonMarketable(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl Error for UniverseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UniverseError::Http(err) | UniverseError::Deserialize(err) => Some(err),
+            UniverseError::NotFound { .. } => None,
+            UniverseError::InvalidId(err) => Some(err),
+            UniverseError::NonMarketable(err) => Some(err),
+        }
+    }
+}
+
+impl From<EsiError> for UniverseError {
+    fn from(err: EsiError) -> Self {
+        match err {
+            EsiError::Decode { .. } => UniverseError::Deserialize(err),
+            EsiError::Request { url, .. } if err.status() == Some(StatusCode::NOT_FOUND) => {
+                UniverseError::NotFound { url }
+            }
+            err => UniverseError::Http(err),
+        }
+    }
+}
+
+impl From<InvalidIDError> for UniverseError {
+    fn from(err: InvalidIDError) -> Self {
+        UniverseError::InvalidId(err)
+    }
+}
+
+impl From<NonMarketableTypeError> for UniverseError {
+    fn from(err: NonMarketableTypeError) -> Self {
+        UniverseError::NonMarketable(err)
+    }
+}
+
+// ========================================
+// CACHE STORE
+// ========================================
+
+/// Persistent backing storage for the universe caches.
+///
+/// Values are opaque serialized blobs, one per cache (`"regions"`, `"systems"`, ...).
+/// Implement this to share caches between instances, e.g. through Redis.
+pub trait UniverseCacheStore: Send + Sync {
+    fn load(&self, key: &str) -> impl Future<Output = Option<String>> + Send;
+    fn store(&self, key: &str, val: &str) -> impl Future<Output = ()> + Send;
+}
+
+/// The default store: one JSON file per key in the user's cache directory.
+#[derive(Clone, Debug)]
+pub struct FileStore {
+    root: PathBuf,
+}
+
+impl FileStore {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        FileStore { root: root.into() }
+    }
+
+    /// The store in `client`'s cache directory, see [`ESIClient::cache_dir`].
+    pub fn for_client(client: &ESIClient) -> Self {
+        FileStore::new(client.cache_dir().join("esi"))
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.root.join(format!("{key}.json"))
+    }
+}
+
+impl Default for FileStore {
+    fn default() -> Self {
+        FileStore::new(crate::default_cache_dir().join("esi"))
+    }
+}
+
+impl UniverseCacheStore for FileStore {
+    async fn load(&self, key: &str) -> Option<String> {
+        tokio::fs::read_to_string(self.path(key)).await.ok()
+    }
+
+    async fn store(&self, key: &str, val: &str) {
+        if let Err(err) = tokio::fs::create_dir_all(&self.root).await {
+            tracing::warn!("Failed to create {}: {err}", self.root.display());
+            return;
+        }
+
+        // written whole under a name of its own, then swapped in, so neither a crash nor a
+        // concurrent store of the same key leaves a torn file behind
+        static PARTIALS: AtomicU64 = AtomicU64::new(0);
+        let partial = self.root.join(format!(
+            "{key}.json.{}-{}.partial",
+            std::process::id(),
+            PARTIALS.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        if let Err(err) = tokio::fs::write(&partial, val).await {
+            tracing::warn!("Failed to save {key}: {err}");
+            return;
+        }
+        if let Err(err) = tokio::fs::rename(&partial, self.path(key)).await {
+            tracing::warn!("Failed to replace {key}: {err}");
+            let _ = tokio::fs::remove_file(&partial).await;
+        }
+    }
+}
+
+/// Loads a cache blob from `store` into `map`, returning how many entries were loaded.
+async fn load_map<S, K, V>(store: &S, key: &str, map: &DashMap<K, V>, id: fn(&V) -> K) -> usize
+where
+    S: UniverseCacheStore,
+    K: Eq + std::hash::Hash,
+    V: for<'de> Deserialize<'de>,
+{
+    let Some(blob) = store.load(key).await else {
+        return 0;
+    };
+
+    match serde_json::from_str::<Vec<V>>(&blob) {
+        Ok(values) => {
+            let count = values.len();
+            for value in values {
+                map.insert(id(&value), value);
+            }
+            count
+        }
+        Err(err) => {
+            tracing::warn!("Discarding unreadable {key} cache: {err}");
+            0
+        }
+    }
+}
+
+/// Serializes every value of `map` into `store` under `key`.
+async fn save_map<S, K, V>(store: &S, key: &str, map: &DashMap<K, V>)
+where
+    S: UniverseCacheStore,
+    K: Eq + std::hash::Hash,
+    V: Serialize,
+{
+    let blob = {
+        let entries: Vec<_> = map.iter().collect();
+        let values: Vec<&V> = entries.iter().map(|entry| entry.value()).collect();
+        serde_json::to_string(&values)
+    };
+
+    match blob {
+        Ok(blob) => store.store(key, &blob).await,
+        Err(err) => tracing::error!("Failed to serialize {key} cache: {err}"),
+    }
+}
+
+/// How many requests `get_all` keeps in flight by default.
+pub const DEFAULT_FETCH_CONCURRENCY: usize = 32;
+
 /**
 ========================================
 REGION API
 ========================================
 */
 
-#[derive(Clone, PartialEq, Debug, Eq, Hash, Copy, Serialize)]
+#[derive(Clone, PartialEq, Debug, Eq, Hash, Copy)]
 pub struct RegionID {
     value: u32,
 }
@@ -68,20 +311,51 @@ impl<'de> Deserialize<'de> for RegionID {
     }
 }
 
+impl Serialize for RegionID {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u32(self.value)
+    }
+}
+
+/// Every ID CCP allocates to regions. ESI only hands out IDs from the bands below, but the whole
+/// allocation is accepted so a newly added band doesn't break deserialization.
+pub const REGION_IDS: Range<u32> = 10_000_000..20_000_000;
+/// Known space regions, including Pochven (10000070).
+pub const KSPACE_REGION_IDS: Range<u32> = 10_000_000..11_000_000;
+/// Wormhole space regions, A-R00001 (11000001) onwards.
+pub const WORMHOLE_REGION_IDS: Range<u32> = 11_000_000..12_000_000;
+/// Abyssal deadspace regions.
+pub const ABYSSAL_REGION_IDS: Range<u32> = 12_000_000..13_000_000;
+/// The unreachable void regions (VR-01 to VR-05).
+pub const VOID_REGION_IDS: Range<u32> = 14_000_000..15_000_000;
+
 impl TryFrom<u32> for RegionID {
     type Error = InvalidIDError;
 
     fn try_from(value: u32) -> Result<Self, Self::Error> {
-        match value {
-            10_000_000..20_000_000 => Ok(RegionID { value }),
-            _ => Err(InvalidIDError {
-                value: value.into(),
-                acceptable: 10_000_000..20_000_000,
-            }),
+        if REGION_IDS.contains(&value) {
+            Ok(RegionID { value })
+        } else {
+            Err(InvalidIDError::new(value, REGION_IDS))
         }
     }
 }
 
+impl From<RegionID> for u32 {
+    fn from(id: RegionID) -> Self {
+        id.value
+    }
+}
+
+impl fmt::Display for RegionID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
 /// This struct represents a region in the EvE universe.
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug, Hash)]
 pub struct Region {
@@ -91,57 +365,83 @@ pub struct Region {
 }
 impl Eq for Region {}
 
-type RegionResult = Result<Region, Box<dyn Error>>;
+pub type RegionResult = Result<Region, UniverseError>;
 
 #[derive(Clone, Debug)]
-pub struct Regions {
+pub struct Regions<S = FileStore> {
     pub region_map: Arc<DashMap<RegionID, Region>>,
     client: Arc<ESIClient>,
+    store: Arc<S>,
 }
 
 impl Regions {
     pub fn new(client: Arc<ESIClient>) -> Self {
+        let store = FileStore::for_client(&client);
+        Regions::with_store(client, store)
+    }
+
+    /// Fetches all regions in the universe and returns a Regions object with all regions
+    pub async fn get_all(client: Arc<ESIClient>) -> Result<Self, UniverseError> {
+        Regions::get_all_with_concurrency(client, DEFAULT_FETCH_CONCURRENCY).await
+    }
+
+    /// Like [`Regions::get_all`], but with at most `concurrency` region requests in flight.
+    pub async fn get_all_with_concurrency(
+        client: Arc<ESIClient>,
+        concurrency: usize,
+    ) -> Result<Self, UniverseError> {
+        let store = FileStore::for_client(&client);
+        Regions::get_all_with_store(client, store, concurrency).await
+    }
+
+    pub fn from_map(map: DashMap<RegionID, Region>, client: Arc<ESIClient>) -> Self {
+        Regions {
+            region_map: Arc::new(map),
+            store: Arc::new(FileStore::for_client(&client)),
+            client,
+        }
+    }
+}
+
+impl<S: UniverseCacheStore + 'static> Regions<S> {
+    pub fn with_store(client: Arc<ESIClient>, store: S) -> Self {
         Regions {
             region_map: Arc::new(DashMap::new()),
             client,
+            store: Arc::new(store),
         }
     }
 
-    /// Fetches all regions in the universe and returns a Regions object with all regions
-    pub async fn get_all(client: Arc<ESIClient>) -> Result<Self, Box<dyn std::error::Error>> {
-        println!("Regions: Fetching All");
-        let regions = Regions::new(client);
+    /// Like [`Regions::get_all_with_concurrency`], but persisted through `store`.
+    /// Regions already present in the store are not fetched again.
+    pub async fn get_all_with_store(
+        client: Arc<ESIClient>,
+        store: S,
+        concurrency: usize,
+    ) -> Result<Self, UniverseError> {
+        tracing::info!("Fetching all regions");
+        let regions = Regions::with_store(client, store);
+        regions.load_from_cache().await;
 
         let ids: Vec<RegionID> = regions
             .client
-            .esi_get("/universe/regions/")
-            .await?
-            .json::<Vec<RegionID>>()
+            .esi_get_json::<Vec<RegionID>>("/universe/regions/")
             .await?;
 
-        let regions_map: Arc<DashMap<RegionID, Region>> = Arc::new(DashMap::new());
-        let mut handles = Vec::new();
-
-        let regions = Arc::new(regions);
-        for id in ids {
-            let regions_map = regions_map.clone();
-            let regions = regions.clone();
-            let handle = tokio::spawn(async move {
-                let info = regions.get_region(id).await.expect("Failed to load region");
-
-                regions_map.insert(info.id, info);
-            });
+        let fetched: Vec<Region> = futures::stream::iter(ids)
+            .filter(|id| std::future::ready(!regions.region_map.contains_key(id)))
+            .map(|id| Self::request_region(&regions.client, id))
+            .buffer_unordered(concurrency.max(1))
+            .try_collect()
+            .await?;
 
-            handles.push(handle);
+        for region in fetched {
+            regions.region_map.insert(region.id, region);
         }
 
-        futures::future::try_join_all(handles)
-            .await
-            .expect("Error in one of the spawned tasks");
-
-        let regions = Arc::try_unwrap(regions).expect("Arc still has multiple strong counts");
+        regions.save_to_cache().await;
 
-        println!("Regions: Finished fetching all");
+        tracing::info!("Finished fetching all regions");
         Ok(regions)
     }
 
@@ -157,32 +457,47 @@ impl Regions {
     }
 
     async fn fetch_region(&self, id: RegionID) -> RegionResult {
-        let region: Region;
-
-        {
-            // locking the map at ID BECAUSE WHY
-            // this feels wrong
-            // TODO: research better ways to deal with this mess
-            self.region_map.get(&id);
-            region = self
-                .client
-                .esi_get(&format!("/universe/regions/{}/", id.get()))
-                .await?
-                .json::<Region>()
-                .await?;
-        }
+        let region = Self::request_region(&self.client, id).await?;
 
         self.region_map.insert(id, region.clone());
 
         Ok(region)
     }
 
-    pub fn from_map(map: DashMap<RegionID, Region>, client: Arc<ESIClient>) -> Self {
-        Regions {
-            region_map: Arc::new(map),
-            client,
+    async fn request_region(client: &ESIClient, id: RegionID) -> RegionResult {
+        Ok(client
+            .esi_get_json::<Region>(&format!("/universe/regions/{id}/"))
+            .await?)
+    }
+
+    /// Loads previously saved regions from the store, returning how many were loaded.
+    pub async fn load_from_cache(&self) -> usize {
+        load_map(
+            &*self.store,
+            "regions",
+            &self.region_map,
+            |region: &Region| region.id,
+        )
+        .await
+    }
+
+    /// Saves the current regions to the store.
+    pub async fn save_to_cache(&self) {
+        save_map(&*self.store, "regions", &self.region_map).await
+    }
+
+    /// Drops a cached region, so the next lookup fetches it from ESI again.
+    pub async fn invalidate(&self, id: RegionID) {
+        if self.region_map.remove(&id).is_some() {
+            self.save_to_cache().await;
         }
     }
+
+    /// Drops every cached region.
+    pub async fn invalidate_all(&self) {
+        self.region_map.clear();
+        self.save_to_cache().await;
+    }
 }
 
 /**
@@ -216,26 +531,41 @@ impl<'de> Deserialize<'de> for SystemID {
 impl Serialize for SystemID {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
-        S: serde::Serializer {
-            u32::serialize(&self.value, serializer)
-        }
+        S: serde::Serializer,
+    {
+        serializer.serialize_u32(self.value)
+    }
 }
 
+/// Every ID CCP allocates to solar systems. Known space systems sit in 30M-31M, wormhole systems
+/// in 31M-32M, abyssal pockets in 32M-33M and the void systems in 34M-35M.
+pub const SYSTEM_IDS: Range<u32> = 30_000_000..40_000_000;
+
 impl TryFrom<u32> for SystemID {
     type Error = InvalidIDError;
 
     fn try_from(value: u32) -> Result<Self, Self::Error> {
-        match value {
-            30_000_000..40_000_000 => Ok(SystemID { value }),
-            _ => Err(InvalidIDError {
-                value: value.into(),
-                acceptable: 30_000_000..40_000_000,
-            }),
+        if SYSTEM_IDS.contains(&value) {
+            Ok(SystemID { value })
+        } else {
+            Err(InvalidIDError::new(value, SYSTEM_IDS))
         }
     }
 }
 
-#[derive(Clone, Deserialize, PartialEq, Debug)]
+impl From<SystemID> for u32 {
+    fn from(id: SystemID) -> Self {
+        id.value
+    }
+}
+
+impl fmt::Display for SystemID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
 pub struct System {
     #[serde(rename = "system_id")]
     pub id: SystemID,
@@ -245,56 +575,147 @@ pub struct System {
     pub name: String,
 }
 
-type SystemResult = Result<System, Box<dyn Error>>;
+impl System {
+    pub fn security_band(&self) -> SecurityBand {
+        SecurityBand::from_status(self.security_status)
+    }
+}
+
+/// How dangerous a system is, as shown in game.
+#[derive(Clone, Copy, PartialEq, Eq, Debug, Hash, Serialize)]
+pub enum SecurityBand {
+    HighSec,
+    LowSec,
+    NullSec,
+}
+
+impl SecurityBand {
+    /// Classifies a raw `security_status` the way the game does: it is rounded half up to one
+    /// decimal, so 0.45 counts as 0.5 and is highsec, but anything above 0.0 stays lowsec even
+    /// when it rounds down to 0.0.
+    pub fn from_status(security_status: f32) -> Self {
+        // ESI's f32 statuses sit just under the halfway point (0.45 is 0.44999998), the
+        // epsilon puts them back on it
+        let rounded = (f64::from(security_status) * 10.0 + 0.5 + 1e-6).floor() / 10.0;
+
+        if rounded >= 0.5 {
+            SecurityBand::HighSec
+        } else if security_status > 0.0 {
+            SecurityBand::LowSec
+        } else {
+            SecurityBand::NullSec
+        }
+    }
+}
+
+/// The celestials of a system. Fetched separately from [`System`] through
+/// [`Systems::fetch_details`] so market-only users don't pay for parsing them.
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+pub struct SystemDetails {
+    pub system_id: SystemID,
+    pub star_id: Option<u32>,
+    #[serde(default)]
+    pub planets: Vec<PlanetInfo>,
+    #[serde(default)]
+    pub stargates: Vec<u32>,
+    #[serde(default)]
+    pub stations: Vec<StationID>,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+pub struct PlanetInfo {
+    pub planet_id: u32,
+    #[serde(default)]
+    pub moons: Vec<u32>,
+    #[serde(default)]
+    pub asteroid_belts: Vec<u32>,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+pub struct Planet {
+    #[serde(rename = "planet_id")]
+    pub id: u32,
+    pub name: String,
+    pub position: Point,
+    pub system_id: SystemID,
+    pub type_id: u32,
+}
+
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+pub struct Moon {
+    #[serde(rename = "moon_id")]
+    pub id: u32,
+    pub name: String,
+    pub position: Point,
+    pub system_id: SystemID,
+}
+
+pub type SystemResult = Result<System, UniverseError>;
 
 #[derive(Clone, Debug)]
-pub struct Systems {
+pub struct Systems<S = FileStore> {
     pub map: DashMap<SystemID, System>,
     client: Arc<ESIClient>,
+    store: Arc<S>,
 }
 
 impl Systems {
     pub fn new(client: Arc<ESIClient>) -> Self {
+        let store = FileStore::for_client(&client);
+        Systems::with_store(client, store)
+    }
+
+    /// Fetches all regions in the universe and returns a Regions object with all regions
+    pub async fn get_all(client: Arc<ESIClient>) -> Result<Self, UniverseError> {
+        Systems::get_all_with_concurrency(client, DEFAULT_FETCH_CONCURRENCY).await
+    }
+
+    /// Like [`Systems::get_all`], but with at most `concurrency` system requests in flight.
+    pub async fn get_all_with_concurrency(
+        client: Arc<ESIClient>,
+        concurrency: usize,
+    ) -> Result<Self, UniverseError> {
+        let store = FileStore::for_client(&client);
+        Systems::get_all_with_store(client, store, concurrency).await
+    }
+}
+
+impl<S: UniverseCacheStore + 'static> Systems<S> {
+    pub fn with_store(client: Arc<ESIClient>, store: S) -> Self {
         Systems {
             map: DashMap::new(),
             client,
+            store: Arc::new(store),
         }
     }
 
-    /// Fetches all regions in the universe and returns a Regions object with all regions
-    pub async fn get_all(client: Arc<ESIClient>) -> Result<Self, Box<dyn std::error::Error>> {
-        let systems = Systems::new(client);
+    /// Like [`Systems::get_all_with_concurrency`], but persisted through `store`.
+    /// Systems already present in the store are not fetched again.
+    pub async fn get_all_with_store(
+        client: Arc<ESIClient>,
+        store: S,
+        concurrency: usize,
+    ) -> Result<Self, UniverseError> {
+        let systems = Systems::with_store(client, store);
+        systems.load_from_cache().await;
         // WHY - is this really necessary
         let ids: Vec<SystemID> = systems
             .client
-            .esi_get("/universe/systems/")
-            .await?
-            .json::<Vec<SystemID>>()
+            .esi_get_json::<Vec<SystemID>>("/universe/systems/")
             .await?;
 
-        let systems_map: Arc<DashMap<SystemID, System>> = Arc::new(DashMap::new());
-        let mut handles = Vec::new();
-
-        let systems = Arc::new(systems);
-        for id in ids {
-            let systems_map = systems_map.clone();
-            let systems = systems.clone();
-            let handle = tokio::spawn(async move {
-                let info = systems.get_system(id).await.expect("Failed to load system");
-
-                systems_map.insert(info.id, info);
-            });
+        let fetched: Vec<System> = futures::stream::iter(ids)
+            .filter(|id| std::future::ready(!systems.map.contains_key(id)))
+            .map(|id| Self::request_system(&systems.client, id))
+            .buffer_unordered(concurrency.max(1))
+            .try_collect()
+            .await?;
 
-            handles.push(handle);
+        for system in fetched {
+            systems.map.insert(system.id, system);
         }
 
-        futures::future::try_join_all(handles)
-            .await
-            .expect("Error in one of the spawned tasks");
-
-        let mut systems = Arc::try_unwrap(systems).expect("Arc still has multiple strong counts");
-
-        systems.map = Arc::try_unwrap(systems_map).expect("Arc still has multiple strong counts");
+        systems.save_to_cache().await;
 
         Ok(systems)
     }
@@ -310,22 +731,66 @@ impl Systems {
     }
 
     async fn fetch_system(&self, id: SystemID) -> SystemResult {
-        let system: System;
-
-        {
-            self.map.get(&id);
-            system = self
-                .client
-                .esi_get(&format!("/universe/systems/{}/", id.get()))
-                .await?
-                .json::<System>()
-                .await?;
-        }
+        let system = Self::request_system(&self.client, id).await?;
 
         self.map.insert(id, system.clone());
 
         Ok(system)
     }
+
+    /// Fetches the planets, moons and other celestials of a system. These aren't cached.
+    pub async fn fetch_details(&self, id: SystemID) -> Result<SystemDetails, UniverseError> {
+        Ok(self
+            .client
+            .esi_get_json::<SystemDetails>(&format!("/universe/systems/{id}/"))
+            .await?)
+    }
+
+    pub async fn fetch_planet(&self, id: u32) -> Result<Planet, UniverseError> {
+        Ok(self
+            .client
+            .esi_get_json::<Planet>(&format!("/universe/planets/{id}/"))
+            .await?)
+    }
+
+    pub async fn fetch_moon(&self, id: u32) -> Result<Moon, UniverseError> {
+        Ok(self
+            .client
+            .esi_get_json::<Moon>(&format!("/universe/moons/{id}/"))
+            .await?)
+    }
+
+    async fn request_system(client: &ESIClient, id: SystemID) -> SystemResult {
+        Ok(client
+            .esi_get_json::<System>(&format!("/universe/systems/{id}/"))
+            .await?)
+    }
+
+    /// Loads previously saved systems from the store, returning how many were loaded.
+    pub async fn load_from_cache(&self) -> usize {
+        load_map(&*self.store, "systems", &self.map, |system: &System| {
+            system.id
+        })
+        .await
+    }
+
+    /// Saves the current systems to the store.
+    pub async fn save_to_cache(&self) {
+        save_map(&*self.store, "systems", &self.map).await
+    }
+
+    /// Drops a cached system, so the next lookup fetches it from ESI again.
+    pub async fn invalidate(&self, id: SystemID) {
+        if self.map.remove(&id).is_some() {
+            self.save_to_cache().await;
+        }
+    }
+
+    /// Drops every cached system.
+    pub async fn invalidate_all(&self) {
+        self.map.clear();
+        self.save_to_cache().await;
+    }
 }
 
 // ========================================
@@ -355,41 +820,347 @@ impl<'de> Deserialize<'de> for ConstellationID {
     }
 }
 
+impl Serialize for ConstellationID {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u32(self.value)
+    }
+}
+
+/// Every ID CCP allocates to constellations, banded like [`SYSTEM_IDS`] starting at 20M.
+pub const CONSTELLATION_IDS: Range<u32> = 20_000_000..30_000_000;
+
 impl TryFrom<u32> for ConstellationID {
     type Error = InvalidIDError;
 
     fn try_from(value: u32) -> Result<Self, Self::Error> {
-        match value {
-            20_000_000..30_000_000 => Ok(ConstellationID { value }),
-            _ => Err(InvalidIDError {
-                value: value.into(),
-                acceptable: 20_000_000..30_000_000,
-            }),
+        if CONSTELLATION_IDS.contains(&value) {
+            Ok(ConstellationID { value })
+        } else {
+            Err(InvalidIDError::new(value, CONSTELLATION_IDS))
         }
     }
 }
 
-// ========================================
-// STATION API
-// ========================================
-
-// TODO: structure API
-#[derive(Clone, PartialEq, Debug, Eq, Hash, Copy)]
-pub struct StationID {
-    value: u64,
-}
-impl StationID {
-    pub fn get(&self) -> u64 {
-        self.value
+impl From<ConstellationID> for u32 {
+    fn from(id: ConstellationID) -> Self {
+        id.value
     }
-    pub fn set(&mut self, new_val: u64) {
-        self.value = new_val
+}
+
+impl fmt::Display for ConstellationID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
     }
 }
 
-impl<'de> Deserialize<'de> for StationID {
-    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
-    where
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+pub struct Constellation {
+    #[serde(rename = "constellation_id")]
+    pub id: ConstellationID,
+    pub name: String,
+    pub region_id: RegionID,
+    pub systems: Vec<SystemID>,
+}
+
+pub type ConstellationResult = Result<Constellation, UniverseError>;
+
+#[derive(Clone, Debug)]
+pub struct Constellations<S = FileStore> {
+    pub map: DashMap<ConstellationID, Constellation>,
+    client: Arc<ESIClient>,
+    store: Arc<S>,
+}
+
+impl Constellations {
+    pub fn new(client: Arc<ESIClient>) -> Self {
+        let store = FileStore::for_client(&client);
+        Constellations::with_store(client, store)
+    }
+
+    /// Fetches every constellation in the universe.
+    pub async fn get_all(client: Arc<ESIClient>) -> Result<Self, UniverseError> {
+        Constellations::get_all_with_concurrency(client, DEFAULT_FETCH_CONCURRENCY).await
+    }
+
+    /// Like [`Constellations::get_all`], but with at most `concurrency` constellation requests
+    /// in flight.
+    pub async fn get_all_with_concurrency(
+        client: Arc<ESIClient>,
+        concurrency: usize,
+    ) -> Result<Self, UniverseError> {
+        let store = FileStore::for_client(&client);
+        Constellations::get_all_with_store(client, store, concurrency).await
+    }
+}
+
+impl<S: UniverseCacheStore + 'static> Constellations<S> {
+    pub fn with_store(client: Arc<ESIClient>, store: S) -> Self {
+        Constellations {
+            map: DashMap::new(),
+            client,
+            store: Arc::new(store),
+        }
+    }
+
+    /// Like [`Constellations::get_all_with_concurrency`], but persisted through `store`.
+    /// Constellations already present in the store are not fetched again.
+    pub async fn get_all_with_store(
+        client: Arc<ESIClient>,
+        store: S,
+        concurrency: usize,
+    ) -> Result<Self, UniverseError> {
+        let constellations = Constellations::with_store(client, store);
+        constellations.load_from_cache().await;
+        let ids: Vec<ConstellationID> = constellations
+            .client
+            .esi_get_json::<Vec<ConstellationID>>("/universe/constellations/")
+            .await?;
+
+        let fetched: Vec<Constellation> = futures::stream::iter(ids)
+            .filter(|id| std::future::ready(!constellations.map.contains_key(id)))
+            .map(|id| Self::request_constellation(&constellations.client, id))
+            .buffer_unordered(concurrency.max(1))
+            .try_collect()
+            .await?;
+
+        for constellation in fetched {
+            constellations.map.insert(constellation.id, constellation);
+        }
+
+        constellations.save_to_cache().await;
+
+        Ok(constellations)
+    }
+
+    pub async fn get_constellation(&self, id: ConstellationID) -> ConstellationResult {
+        {
+            if let Some(data) = self.map.get(&id) {
+                return Ok(data.clone());
+            }
+        }
+
+        self.fetch_constellation(id).await
+    }
+
+    async fn fetch_constellation(&self, id: ConstellationID) -> ConstellationResult {
+        let constellation = Self::request_constellation(&self.client, id).await?;
+
+        self.map.insert(id, constellation.clone());
+
+        Ok(constellation)
+    }
+
+    async fn request_constellation(client: &ESIClient, id: ConstellationID) -> ConstellationResult {
+        Ok(client
+            .esi_get_json::<Constellation>(&format!("/universe/constellations/{id}/"))
+            .await?)
+    }
+
+    /// Loads previously saved constellations from the store, returning how many were loaded.
+    pub async fn load_from_cache(&self) -> usize {
+        load_map(
+            &*self.store,
+            "constellations",
+            &self.map,
+            |c: &Constellation| c.id,
+        )
+        .await
+    }
+
+    /// Saves the current constellations to the store.
+    pub async fn save_to_cache(&self) {
+        save_map(&*self.store, "constellations", &self.map).await
+    }
+
+    /// Drops a cached constellation, so the next lookup fetches it from ESI again.
+    pub async fn invalidate(&self, id: ConstellationID) {
+        if self.map.remove(&id).is_some() {
+            self.save_to_cache().await;
+        }
+    }
+
+    /// Drops every cached constellation.
+    pub async fn invalidate_all(&self) {
+        self.map.clear();
+        self.save_to_cache().await;
+    }
+}
+
+/// Groups the loaded systems by their constellation. Each list is sorted by ID.
+pub fn systems_by_constellation<S>(
+    systems: &Systems<S>,
+) -> HashMap<ConstellationID, Vec<SystemID>> {
+    let mut grouped: HashMap<ConstellationID, Vec<SystemID>> = HashMap::new();
+
+    for system in systems.map.iter() {
+        grouped
+            .entry(system.constellation_id)
+            .or_default()
+            .push(system.id);
+    }
+
+    for ids in grouped.values_mut() {
+        ids.sort_by_key(SystemID::get);
+    }
+
+    grouped
+}
+
+/// Groups the loaded systems by region, joining through their constellations.
+/// Systems whose constellation isn't loaded in `constellations` are left out.
+/// Each list is sorted by ID.
+pub fn systems_by_region<S, C>(
+    systems: &Systems<S>,
+    constellations: &Constellations<C>,
+) -> HashMap<RegionID, Vec<SystemID>> {
+    let mut grouped: HashMap<RegionID, Vec<SystemID>> = HashMap::new();
+
+    for system in systems.map.iter() {
+        if let Some(constellation) = constellations.map.get(&system.constellation_id) {
+            grouped
+                .entry(constellation.region_id)
+                .or_default()
+                .push(system.id);
+        }
+    }
+
+    for ids in grouped.values_mut() {
+        ids.sort_by_key(SystemID::get);
+    }
+
+    grouped
+}
+
+// ========================================
+// STARGATE GRAPH
+// ========================================
+
+/// Stargate connections between systems, along with the region each system is in.
+#[derive(Clone, Debug, Default)]
+pub struct Graph {
+    adjacency: HashMap<SystemID, Vec<SystemID>>,
+    regions: HashMap<SystemID, RegionID>,
+}
+
+impl Graph {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds a system, or moves it to `region` if it was already added.
+    pub fn add_system(&mut self, system: SystemID, region: RegionID) -> &mut Self {
+        self.regions.insert(system, region);
+        self.adjacency.entry(system).or_default();
+        self
+    }
+
+    /// Connects two systems with a stargate. Stargates always go both ways.
+    pub fn add_stargate(&mut self, a: SystemID, b: SystemID) -> &mut Self {
+        self.adjacency.entry(a).or_default().push(b);
+        self.adjacency.entry(b).or_default().push(a);
+        self
+    }
+
+    pub fn region_of(&self, system: SystemID) -> Option<RegionID> {
+        self.regions.get(&system).copied()
+    }
+
+    /// The number of jumps between two systems, if `to` is at most `max_jumps` away.
+    pub fn jumps_within(&self, from: SystemID, to: SystemID, max_jumps: u32) -> Option<u32> {
+        let mut visited = HashSet::from([from]);
+        let mut queue = VecDeque::from([(from, 0)]);
+
+        while let Some((system, jumps)) = queue.pop_front() {
+            if system == to {
+                return Some(jumps);
+            }
+            if jumps == max_jumps {
+                continue;
+            }
+
+            for &next in self.adjacency.get(&system).into_iter().flatten() {
+                if visited.insert(next) {
+                    queue.push_back((next, jumps + 1));
+                }
+            }
+        }
+
+        None
+    }
+}
+
+// ========================================
+// ROUTE API
+// ========================================
+
+/// Which route `/route/` plans, as sent in its `flag` parameter.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum RouteFlag {
+    /// The fewest jumps.
+    #[default]
+    Shortest,
+    /// Avoiding low and null sec where possible.
+    Secure,
+    /// Preferring low and null sec.
+    Insecure,
+}
+
+impl RouteFlag {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RouteFlag::Shortest => "shortest",
+            RouteFlag::Secure => "secure",
+            RouteFlag::Insecure => "insecure",
+        }
+    }
+}
+
+impl fmt::Display for RouteFlag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl ESIClient {
+    /// Plans a stargate route, returning every system on it from `origin` to `dest`, both
+    /// included. The number of jumps is one less than its length.
+    ///
+    /// ESI answers 404 when there is no route, e.g. to or from wormhole space.
+    pub async fn route(
+        &self,
+        origin: SystemID,
+        dest: SystemID,
+        flag: RouteFlag,
+    ) -> Result<Vec<SystemID>, EsiError> {
+        self.esi_get_json(&format!("/route/{origin}/{dest}/?flag={flag}"))
+            .await
+    }
+}
+
+// ========================================
+// STATION API
+// ========================================
+
+// TODO: structure API
+#[derive(Clone, PartialEq, Debug, Eq, Hash, Copy)]
+pub struct StationID {
+    value: u64,
+}
+impl StationID {
+    pub fn get(&self) -> u64 {
+        self.value
+    }
+    pub fn set(&mut self, new_val: u64) {
+        self.value = new_val
+    }
+}
+
+impl<'de> Deserialize<'de> for StationID {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
         D: serde::Deserializer<'de>,
     {
         let value = u64::deserialize(deserializer)?;
@@ -412,16 +1183,62 @@ impl TryFrom<u64> for StationID {
     fn try_from(value: u64) -> Result<Self, Self::Error> {
         match value {
             60_000_000..64_000_000 => Ok(StationID { value }),
-            1_000_000_000_000..1_100_000_000_000 => Ok(StationID { value }),
+            1_000_000_000_000.. => Ok(StationID { value }),
             _ => Err(InvalidIDError {
                 value,
                 acceptable: 60_000_000..64_000_000,
+                acceptable_from: Some(1_000_000_000_000),
             }),
         }
     }
 }
 
-type StationResult = Result<Station, Box<dyn Error>>;
+impl From<StationID> for u64 {
+    fn from(id: StationID) -> Self {
+        id.value
+    }
+}
+
+impl fmt::Display for StationID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
+
+/// A market location, told apart by ID range: NPC stations live in 60M–64M,
+/// player-owned structures at 10^12 and above.
+#[derive(Clone, PartialEq, Debug, Eq, Hash, Copy)]
+pub enum LocationId {
+    Station(StationID),
+    Structure(StationID),
+}
+impl LocationId {
+    pub fn id(&self) -> StationID {
+        match self {
+            LocationId::Station(id) | LocationId::Structure(id) => *id,
+        }
+    }
+}
+
+impl TryFrom<u64> for LocationId {
+    type Error = InvalidIDError;
+
+    fn try_from(value: u64) -> Result<Self, Self::Error> {
+        Ok(StationID::try_from(value)?.into())
+    }
+}
+
+impl From<StationID> for LocationId {
+    fn from(id: StationID) -> Self {
+        if id.get() < 64_000_000 {
+            LocationId::Station(id)
+        } else {
+            LocationId::Structure(id)
+        }
+    }
+}
+
+pub type StationResult = Result<Station, UniverseError>;
 
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
 pub struct Station {
@@ -429,6 +1246,21 @@ pub struct Station {
     pub id: StationID,
     pub system_id: SystemID,
     pub name: String,
+    // the rest are optional so stations cached before they were added still load
+    pub type_id: Option<u32>,
+    /// The owning corporation.
+    pub owner: Option<u32>,
+    pub race_id: Option<u32>,
+    pub position: Option<Point>,
+    /// Services offered, such as `"market"` or `"repair-facilities"`.
+    #[serde(default)]
+    pub services: Vec<String>,
+}
+
+impl Station {
+    pub fn has_service(&self, service: &str) -> bool {
+        self.services.iter().any(|s| s == service)
+    }
 }
 
 #[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
@@ -447,20 +1279,40 @@ pub struct Structure {
     pub type_id: u32,
 }
 
+/// Least time between two saves of newly fetched stations, see [`Stations::get_station`].
+pub const STATION_SAVE_INTERVAL: Duration = Duration::from_secs(30);
+
 #[derive(Clone, Debug)]
-pub struct Stations {
+pub struct Stations<S = FileStore> {
     pub map: DashMap<StationID, Station>,
     client: Arc<ESIClient>,
+    store: Arc<S>,
+    /// When a fetched station last triggered a save.
+    last_saved: Arc<Mutex<Option<Instant>>>,
 }
 
 impl Stations {
     pub fn new(client: Arc<ESIClient>) -> Self {
+        let store = FileStore::for_client(&client);
+        Stations::with_store(client, store)
+    }
+}
+
+impl<S: UniverseCacheStore + 'static> Stations<S> {
+    pub fn with_store(client: Arc<ESIClient>, store: S) -> Self {
         Stations {
             map: DashMap::new(),
             client,
+            store: Arc::new(store),
+            last_saved: Arc::default(),
         }
     }
 
+    /// Looks a station up, fetching it from ESI if it isn't cached.
+    ///
+    /// Fetched stations are saved along with the whole map, at most once per
+    /// [`STATION_SAVE_INTERVAL`]. Stations fetched in between are written by the next save, or
+    /// by [`Stations::save_to_cache`].
     pub async fn get_station(&self, id: StationID) -> StationResult {
         {
             if let Some(data) = self.map.get(&id) {
@@ -472,31 +1324,297 @@ impl Stations {
     }
 
     async fn fetch_station(&self, id: StationID) -> StationResult {
-        let system: Station;
+        let station = self.fetch_station_unsaved(id).await?;
+        if self.save_due() {
+            self.save_to_cache().await;
+        }
 
-        {
-            self.map.get(&id);
-            system = self
-                .client
-                .esi_get(&format!("/universe/stations/{}/", id.get()))
-                .await?
-                .json::<Station>()
-                .await?;
+        Ok(station)
+    }
+
+    /// Whether enough time has passed since the last save of fetched stations, claiming the
+    /// save if so.
+    fn save_due(&self) -> bool {
+        let mut last_saved = self.last_saved.lock().unwrap();
+        let due = last_saved.is_none_or(|saved| saved.elapsed() >= STATION_SAVE_INTERVAL);
+        if due {
+            *last_saved = Some(Instant::now());
         }
+        due
+    }
 
-        self.map.insert(id, system.clone());
+    async fn fetch_station_unsaved(&self, id: StationID) -> StationResult {
+        // fetch without touching the map so concurrent lookups of other stations never wait
+        let station = self
+            .client
+            .esi_get_json::<Station>(&format!("/universe/stations/{id}/"))
+            .await?;
 
-        Ok(system)
+        self.map.insert(id, station.clone());
+
+        Ok(station)
+    }
+
+    /// Loads previously saved stations from the store, returning how many were loaded.
+    pub async fn load_from_cache(&self) -> usize {
+        load_map(&*self.store, "stations", &self.map, |station: &Station| {
+            station.id
+        })
+        .await
+    }
+
+    /// Saves the current stations to the store.
+    pub async fn save_to_cache(&self) {
+        save_map(&*self.store, "stations", &self.map).await
+    }
+
+    /// Drops a cached station, so the next lookup fetches it from ESI again.
+    pub async fn invalidate(&self, id: StationID) {
+        if self.map.remove(&id).is_some() {
+            self.save_to_cache().await;
+        }
+    }
+
+    /// Drops every cached station.
+    pub async fn invalidate_all(&self) {
+        self.map.clear();
+        self.save_to_cache().await;
     }
 }
 
+// ========================================
+// PREFETCH
+// ========================================
+
+/// How many entities [`prefetch_all`] requests at once before saving.
+const PREFETCH_CHUNK: usize = 200;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PrefetchCount {
+    /// Already in the cache before the prefetch.
+    pub cached: usize,
+    pub fetched: usize,
+    pub failed: usize,
+}
+
+/// What [`prefetch_all`] loaded, per entity.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct PrefetchReport {
+    pub regions: PrefetchCount,
+    pub constellations: PrefetchCount,
+    pub systems: PrefetchCount,
+    pub stations: PrefetchCount,
+    /// Non-marketable types count as fetched, they're remembered by [`Items`] all the same.
+    pub types: PrefetchCount,
+}
+
+/// Fetches the `missing` IDs a chunk at a time, calling `save` after each chunk so an
+/// interrupted prefetch keeps what it already fetched.
+async fn prefetch_missing<K, T, E, F, Fut, SF, SFut>(
+    missing: Vec<K>,
+    fetch: F,
+    save: SF,
+) -> PrefetchCount
+where
+    K: Copy + fmt::Display,
+    E: fmt::Display,
+    F: Fn(K) -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+    SF: Fn() -> SFut,
+    SFut: Future<Output = ()>,
+{
+    let mut count = PrefetchCount::default();
+
+    for chunk in missing.chunks(PREFETCH_CHUNK) {
+        let results = futures::future::join_all(chunk.iter().map(|&id| fetch(id))).await;
+        for (id, result) in chunk.iter().zip(results) {
+            match result {
+                Ok(_) => count.fetched += 1,
+                Err(err) => {
+                    tracing::warn!("Prefetch of {id} failed: {err}");
+                    count.failed += 1;
+                }
+            }
+        }
+
+        save().await;
+    }
+
+    count
+}
+
+/// Warms the on-disk caches of regions, constellations, systems, stations and types, fetching
+/// only what isn't cached yet. Progress is saved as it goes, so an interrupted run can be
+/// resumed by running it again.
+///
+/// Station IDs are discovered through each system's [`SystemDetails`], which are served from
+/// the HTTP cache on later runs.
+pub async fn prefetch_all(client: Arc<ESIClient>) -> anyhow::Result<PrefetchReport> {
+    let mut report = PrefetchReport::default();
+
+    let regions = Regions::new(client.clone());
+    let cached = regions.load_from_cache().await;
+    let ids: Vec<RegionID> = client.esi_get_json("/universe/regions/").await?;
+    let missing = ids
+        .into_iter()
+        .filter(|id| !regions.region_map.contains_key(id))
+        .collect();
+    report.regions = PrefetchCount {
+        cached,
+        ..prefetch_missing(
+            missing,
+            |id| regions.get_region(id),
+            || regions.save_to_cache(),
+        )
+        .await
+    };
+
+    let constellations = Constellations::new(client.clone());
+    let cached = constellations.load_from_cache().await;
+    let ids: Vec<ConstellationID> = client.esi_get_json("/universe/constellations/").await?;
+    let missing = ids
+        .into_iter()
+        .filter(|id| !constellations.map.contains_key(id))
+        .collect();
+    report.constellations = PrefetchCount {
+        cached,
+        ..prefetch_missing(
+            missing,
+            |id| constellations.get_constellation(id),
+            || constellations.save_to_cache(),
+        )
+        .await
+    };
+
+    let systems = Systems::new(client.clone());
+    let cached = systems.load_from_cache().await;
+    let ids: Vec<SystemID> = client.esi_get_json("/universe/systems/").await?;
+    let missing = ids
+        .iter()
+        .copied()
+        .filter(|id| !systems.map.contains_key(id))
+        .collect();
+    report.systems = PrefetchCount {
+        cached,
+        ..prefetch_missing(
+            missing,
+            |id| systems.get_system(id),
+            || systems.save_to_cache(),
+        )
+        .await
+    };
+
+    let stations = Stations::new(client.clone());
+    let cached = stations.load_from_cache().await;
+    let mut missing = Vec::new();
+    for chunk in ids.chunks(PREFETCH_CHUNK) {
+        let details =
+            futures::future::join_all(chunk.iter().map(|&id| systems.fetch_details(id))).await;
+        for details in details.into_iter().flatten() {
+            missing.extend(
+                details
+                    .stations
+                    .into_iter()
+                    .filter(|id| !stations.map.contains_key(id)),
+            );
+        }
+    }
+    report.stations = PrefetchCount {
+        cached,
+        ..prefetch_missing(
+            missing,
+            |id| stations.fetch_station_unsaved(id),
+            || stations.save_to_cache(),
+        )
+        .await
+    };
+
+    let items = Items::new(client.clone());
+    let cached = items.load_from_cache().await;
+    let missing = items
+        .fetch_type_ids()
+        .await?
+        .into_iter()
+        .filter(|&id| !items.is_known(id))
+        .collect();
+    report.types = PrefetchCount {
+        cached,
+        ..prefetch_missing(
+            missing,
+            |id| {
+                let items = &items;
+                async move {
+                    match items.get_item(id).await {
+                        Ok(_) | Err(UniverseError::NonMarketable(_)) => Ok(()),
+                        Err(err) => Err(err),
+                    }
+                }
+            },
+            || items.save_to_cache(),
+        )
+        .await
+    };
+
+    tracing::info!("Prefetch finished: {report:?}");
+    Ok(report)
+}
+
 // ========================================
 // TYPES API
 // ========================================
-// types are just u32s, so there isn't a dedicated struct for them
+
+/// An item type. Unlike the other IDs this has no range check, valid type IDs are too sparse.
+#[derive(Clone, PartialEq, Debug, Eq, Hash, Copy, PartialOrd, Ord)]
+pub struct TypeID {
+    value: u32,
+}
+impl TypeID {
+    pub fn get(&self) -> u32 {
+        self.value
+    }
+    pub fn set(&mut self, new_val: u32) {
+        self.value = new_val
+    }
+}
+
+impl<'de> Deserialize<'de> for TypeID {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        Ok(TypeID::from(u32::deserialize(deserializer)?))
+    }
+}
+
+impl Serialize for TypeID {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        serializer.serialize_u32(self.value)
+    }
+}
+
+impl From<u32> for TypeID {
+    fn from(value: u32) -> Self {
+        TypeID { value }
+    }
+}
+
+impl From<TypeID> for u32 {
+    fn from(id: TypeID) -> Self {
+        id.value
+    }
+}
+
+impl fmt::Display for TypeID {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.value)
+    }
+}
 
 #[derive(Debug)]
-pub struct NonMarketableTypeError(u32);
+pub struct NonMarketableTypeError(TypeID);
 impl fmt::Display for NonMarketableTypeError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "Type {} is not marketable", self.0)
@@ -505,9 +1623,9 @@ impl fmt::Display for NonMarketableTypeError {
 impl Error for NonMarketableTypeError {}
 
 /// A marketable item. For non-marketable items, see [`ItemRaw`].
-#[derive(Clone, Deserialize, PartialEq, Debug)]
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
 pub struct Item {
-    id: u32,
+    id: TypeID,
     group_id: u32,
     icon_id: u32,
     market_group_id: u32,
@@ -515,6 +1633,32 @@ pub struct Item {
     description: String,
 }
 
+impl Item {
+    pub fn id(&self) -> TypeID {
+        self.id
+    }
+
+    pub fn group_id(&self) -> u32 {
+        self.group_id
+    }
+
+    pub fn icon_id(&self) -> u32 {
+        self.icon_id
+    }
+
+    pub fn market_group_id(&self) -> u32 {
+        self.market_group_id
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+}
+
 impl TryFrom<ItemRaw> for Item {
     type Error = NonMarketableTypeError;
 
@@ -536,7 +1680,7 @@ impl TryFrom<ItemRaw> for Item {
 /// An optionally marketable item.
 #[derive(Deserialize)]
 pub struct ItemRaw {
-    type_id: u32,
+    type_id: TypeID,
     group_id: u32,
     icon_id: u32,
     market_group_id: Option<u32>,
@@ -544,40 +1688,1207 @@ pub struct ItemRaw {
     description: String,
 }
 
-type ItemResult = Result<Item, Box<dyn Error>>;
+pub type ItemResult = Result<Item, UniverseError>;
+
+/// One material a type reprocesses into, at a perfect yield per portion.
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Debug)]
+pub struct TypeMaterial {
+    #[serde(alias = "materialTypeID")]
+    pub material_type_id: TypeID,
+    pub quantity: u64,
+}
+
+/// An entry of the static data export's `typeMaterials` table.
+#[derive(Deserialize)]
+struct SdeTypeMaterials {
+    materials: Vec<TypeMaterial>,
+}
+
+/// The store key [`Items`] keeps its non-marketable type IDs under.
+const NON_MARKETABLE_KEY: &str = "non_marketable_types";
 
-pub struct Items {
-    pub map: DashMap<u32, Item>,
+/// How long [`Items::resolve_missing`] leaves a type alone after failing to fetch it.
+pub const UNRESOLVED_RETRY: Duration = Duration::from_secs(10 * 60);
+
+#[derive(Clone, Debug)]
+pub struct Items<S = FileStore> {
+    pub map: DashMap<TypeID, Item>,
+    /// Reprocessing materials by type, loaded through [`Items::load_materials`].
+    pub materials: DashMap<TypeID, Vec<TypeMaterial>>,
+    /// Types known to have no market group, stored alongside the items so they aren't fetched
+    /// again.
+    non_marketable: DashSet<TypeID>,
+    /// When fetching a type last failed, see [`Items::resolve_missing`]. Not persisted.
+    unresolved: DashMap<TypeID, Instant>,
     client: Arc<ESIClient>,
+    store: Arc<S>,
 }
 
 impl Items {
     pub fn new(client: Arc<ESIClient>) -> Self {
+        let store = FileStore::for_client(&client);
+        Items::with_store(client, store)
+    }
+
+    /// Fetches every marketable type in the game, paging through `/universe/types/`.
+    /// Types without a market group are skipped.
+    pub async fn get_all(client: Arc<ESIClient>) -> Result<Self, UniverseError> {
+        Items::get_all_with_concurrency(client, DEFAULT_FETCH_CONCURRENCY).await
+    }
+
+    /// Like [`Items::get_all`], but with at most `concurrency` type requests in flight.
+    pub async fn get_all_with_concurrency(
+        client: Arc<ESIClient>,
+        concurrency: usize,
+    ) -> Result<Self, UniverseError> {
+        let store = FileStore::for_client(&client);
+        Items::get_all_with_store(client, store, concurrency).await
+    }
+}
+
+impl<S: UniverseCacheStore + 'static> Items<S> {
+    pub fn with_store(client: Arc<ESIClient>, store: S) -> Self {
         Items {
             map: DashMap::new(),
+            materials: DashMap::new(),
+            non_marketable: DashSet::new(),
+            unresolved: DashMap::new(),
             client,
+            store: Arc::new(store),
+        }
+    }
+
+    /// Like [`Items::get_all_with_concurrency`], but persisted through `store`.
+    /// Items already present in the store are not fetched again, and neither are the types it
+    /// lists as non-marketable. A type that fails to fetch or decode is logged and skipped, so
+    /// it is tried again on the next run.
+    pub async fn get_all_with_store(
+        client: Arc<ESIClient>,
+        store: S,
+        concurrency: usize,
+    ) -> Result<Self, UniverseError> {
+        let items = Items::with_store(client, store);
+        items.load_from_cache().await;
+
+        let ids = items.fetch_type_ids().await?;
+        let known = |id: &TypeID| items.is_known(*id);
+        let raws: Vec<(TypeID, Result<ItemRaw, UniverseError>)> = futures::stream::iter(ids)
+            .filter(|id| std::future::ready(!known(id)))
+            .map(|id| {
+                let items = &items;
+                async move { (id, items.fetch_item_raw(id).await) }
+            })
+            .buffer_unordered(concurrency.max(1))
+            .collect()
+            .await;
+
+        let mut failed = 0;
+        for (id, raw) in raws {
+            let raw = match raw {
+                Ok(raw) => raw,
+                Err(err) => {
+                    tracing::warn!("Skipping type {id}: {err}");
+                    failed += 1;
+                    continue;
+                }
+            };
+            match Item::try_from(raw) {
+                Ok(item) => {
+                    items.map.insert(item.id, item);
+                }
+                Err(NonMarketableTypeError(id)) => {
+                    items.non_marketable.insert(id);
+                }
+            }
         }
+        tracing::info!(
+            "Fetched {} marketable types, skipped {} non-marketable ones and {failed} that failed",
+            items.map.len(),
+            items.non_marketable.len()
+        );
+        items.save_to_cache().await;
+
+        Ok(items)
+    }
+
+    /// Lists every type ID in the game, paging through `/universe/types/`.
+    pub async fn fetch_type_ids(&self) -> Result<Vec<TypeID>, UniverseError> {
+        let url = "/universe/types/";
+        let first_page = self.client.esi_get(url).await?;
+        let num_pages = page_count(first_page.headers());
+        let mut ids: Vec<TypeID> = decode_json(url, first_page).await?;
+        for page in 2..=num_pages {
+            let page: Vec<TypeID> = self
+                .client
+                .esi_get_json(&format!("/universe/types/?page={page}"))
+                .await?;
+            ids.extend(page);
+        }
+
+        Ok(ids)
+    }
+
+    /// Whether `id` is loaded, either as an item or as a known non-marketable type.
+    fn is_known(&self, id: TypeID) -> bool {
+        self.map.contains_key(&id) || self.non_marketable.contains(&id)
     }
 
     /// gets a marketable item from an item id
-    pub async fn get_item(&self, id: u32) -> ItemResult {
+    pub async fn get_item(&self, id: TypeID) -> ItemResult {
         {
             if let Some(data) = self.map.get(&id) {
                 return Ok(data.clone());
             }
         }
+        if self.non_marketable.contains(&id) {
+            return Err(NonMarketableTypeError(id).into());
+        }
+
+        let item = Item::try_from(self.fetch_item_raw(id).await?).inspect_err(|err| {
+            self.non_marketable.insert(err.0);
+        })?;
+        self.map.insert(id, item.clone());
+
+        Ok(item)
+    }
+
+    /// Fetches the types in `ids` that aren't loaded yet, all at once. Non-marketable types are
+    /// remembered as such, and a type that fails to fetch isn't tried again for
+    /// [`UNRESOLVED_RETRY`], so repeated calls don't keep asking ESI for it.
+    pub async fn resolve_missing(&self, ids: impl IntoIterator<Item = TypeID>) {
+        let missing = ids.into_iter().filter(|&id| {
+            !self.is_known(id)
+                && self
+                    .unresolved
+                    .get(&id)
+                    .is_none_or(|failed| failed.elapsed() >= UNRESOLVED_RETRY)
+        });
+        futures::future::join_all(missing.map(|id| async move {
+            match self.get_item(id).await {
+                Ok(_) | Err(UniverseError::NonMarketable(_)) => {
+                    self.unresolved.remove(&id);
+                }
+                Err(err) => {
+                    tracing::debug!("Could not resolve type {id}: {err}");
+                    self.unresolved.insert(id, Instant::now());
+                }
+            }
+        }))
+        .await;
+    }
+
+    /// Lists the loaded items whose name starts with `prefix`, ignoring case, sorted by name.
+    /// Only [`Items::map`] is searched, nothing is fetched.
+    pub fn search(&self, prefix: &str) -> Vec<(TypeID, String)> {
+        let prefix = prefix.to_lowercase();
+        let mut matches: Vec<(TypeID, String)> = self
+            .map
+            .iter()
+            .filter(|item| item.name.to_lowercase().starts_with(&prefix))
+            .map(|item| (item.id, item.name.clone()))
+            .collect();
+        matches.sort_by(|a, b| a.1.cmp(&b.1));
+
+        matches
+    }
+
+    /// What a type reprocesses into, or `None` for types that can't be reprocessed. ESI doesn't
+    /// serve this, so only types loaded through [`Items::load_materials`] are known.
+    pub fn get_materials(&self, id: TypeID) -> Option<Vec<TypeMaterial>> {
+        self.materials.get(&id).map(|materials| materials.clone())
+    }
+
+    /// Loads reprocessing materials from the static data export's `typeMaterials` table,
+    /// converted to JSON: an object of `{"materials": [{"materialTypeID", "quantity"}]}` keyed
+    /// by type ID. Returns how many types were loaded.
+    pub async fn load_materials(&self, path: impl AsRef<std::path::Path>) -> anyhow::Result<usize> {
+        let json = tokio::fs::read_to_string(path).await?;
+        self.load_materials_from_str(&json)
+    }
+
+    /// Like [`Items::load_materials`], from the JSON itself.
+    pub fn load_materials_from_str(&self, json: &str) -> anyhow::Result<usize> {
+        let table: HashMap<u32, SdeTypeMaterials> = serde_json::from_str(json)?;
+        let count = table.len();
+        for (id, entry) in table {
+            self.materials.insert(TypeID::from(id), entry.materials);
+        }
+
+        Ok(count)
+    }
+
+    /// Loads previously saved items, and the types known to be non-marketable, from the store.
+    /// Returns how many items were loaded.
+    pub async fn load_from_cache(&self) -> usize {
+        if let Some(blob) = self.store.load(NON_MARKETABLE_KEY).await {
+            match serde_json::from_str::<Vec<TypeID>>(&blob) {
+                Ok(ids) => {
+                    for id in ids {
+                        self.non_marketable.insert(id);
+                    }
+                }
+                Err(err) => tracing::warn!("Discarding unreadable {NON_MARKETABLE_KEY}: {err}"),
+            }
+        }
+
+        load_map(&*self.store, "items", &self.map, |item: &Item| item.id).await
+    }
+
+    /// Saves the current items, and the types known to be non-marketable, to the store.
+    pub async fn save_to_cache(&self) {
+        let ids: Vec<TypeID> = self.non_marketable.iter().map(|id| *id).collect();
+        match serde_json::to_string(&ids) {
+            Ok(blob) => self.store.store(NON_MARKETABLE_KEY, &blob).await,
+            Err(err) => tracing::error!("Failed to serialize {NON_MARKETABLE_KEY}: {err}"),
+        }
 
-        Ok(Item::try_from(self.fetch_item_raw(id).await?)?)
+        save_map(&*self.store, "items", &self.map).await
     }
 
-    pub async fn fetch_item_raw(&self, id: u32) -> Result<ItemRaw, Box<dyn Error>> {
+    /// Drops a cached item, so the next lookup fetches it from ESI again.
+    pub async fn invalidate(&self, id: TypeID) {
+        self.unresolved.remove(&id);
+        let item = self.map.remove(&id).is_some();
+        let non_marketable = self.non_marketable.remove(&id).is_some();
+        if item || non_marketable {
+            self.save_to_cache().await;
+        }
+    }
+
+    /// Drops every cached item.
+    pub async fn invalidate_all(&self) {
+        self.map.clear();
+        self.non_marketable.clear();
+        self.unresolved.clear();
+        self.save_to_cache().await;
+    }
+
+    pub async fn fetch_item_raw(&self, id: TypeID) -> Result<ItemRaw, UniverseError> {
         let raw: ItemRaw = self
             .client
-            .esi_get(&format!("/universe/types/{id}/"))
-            .await?
-            .json::<ItemRaw>()
+            .esi_get_json::<ItemRaw>(&format!("/universe/types/{id}/"))
             .await?;
 
         Ok(raw)
     }
 }
+
+// ========================================
+// NAMES API
+// ========================================
+
+/// Most IDs `/universe/names/` resolves per request.
+pub const NAMES_BATCH_LIMIT: usize = 1000;
+
+/// Most names `/universe/ids/` resolves per request.
+pub const IDS_BATCH_LIMIT: usize = 500;
+
+/// What kind of entity a [`ResolvedName`] belongs to.
+#[derive(Clone, Copy, Deserialize, Serialize, PartialEq, Eq, Hash, Debug)]
+#[serde(rename_all = "snake_case")]
+pub enum NameCategory {
+    Alliance,
+    Character,
+    Constellation,
+    Corporation,
+    InventoryType,
+    Region,
+    SolarSystem,
+    Station,
+    Faction,
+    /// A category added to ESI after this was written.
+    #[serde(other)]
+    Unknown,
+}
+
+/// One entry of a `/universe/names/` response.
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+pub struct ResolvedName {
+    pub id: u64,
+    pub name: String,
+    pub category: NameCategory,
+}
+
+/// One name matched by `/universe/ids/`.
+#[derive(Clone, Deserialize, Serialize, PartialEq, Debug)]
+pub struct NamedId {
+    pub id: u64,
+    pub name: String,
+}
+
+/// The IDs `/universe/ids/` matched, by category. Names are matched exactly, ignoring case,
+/// so one name can show up in several categories and names without a match are left out.
+#[derive(Clone, Default, Deserialize, Serialize, PartialEq, Debug)]
+#[serde(default)]
+pub struct ResolvedIds {
+    pub agents: Vec<NamedId>,
+    pub alliances: Vec<NamedId>,
+    pub characters: Vec<NamedId>,
+    pub constellations: Vec<NamedId>,
+    pub corporations: Vec<NamedId>,
+    pub factions: Vec<NamedId>,
+    pub inventory_types: Vec<NamedId>,
+    pub regions: Vec<NamedId>,
+    pub stations: Vec<NamedId>,
+    pub systems: Vec<NamedId>,
+}
+
+impl ResolvedIds {
+    /// The matches of one category. Agents have no [`NameCategory`], and
+    /// [`NameCategory::Unknown`] never matches.
+    pub fn in_category(&self, category: NameCategory) -> &[NamedId] {
+        match category {
+            NameCategory::Alliance => &self.alliances,
+            NameCategory::Character => &self.characters,
+            NameCategory::Constellation => &self.constellations,
+            NameCategory::Corporation => &self.corporations,
+            NameCategory::InventoryType => &self.inventory_types,
+            NameCategory::Region => &self.regions,
+            NameCategory::SolarSystem => &self.systems,
+            NameCategory::Station => &self.stations,
+            NameCategory::Faction => &self.factions,
+            NameCategory::Unknown => &[],
+        }
+    }
+
+    /// Every match, of any category.
+    pub fn into_all(self) -> Vec<NamedId> {
+        [
+            self.agents,
+            self.alliances,
+            self.characters,
+            self.constellations,
+            self.corporations,
+            self.factions,
+            self.inventory_types,
+            self.regions,
+            self.stations,
+            self.systems,
+        ]
+        .concat()
+    }
+
+    fn extend(&mut self, other: ResolvedIds) {
+        self.agents.extend(other.agents);
+        self.alliances.extend(other.alliances);
+        self.characters.extend(other.characters);
+        self.constellations.extend(other.constellations);
+        self.corporations.extend(other.corporations);
+        self.factions.extend(other.factions);
+        self.inventory_types.extend(other.inventory_types);
+        self.regions.extend(other.regions);
+        self.stations.extend(other.stations);
+        self.systems.extend(other.systems);
+    }
+}
+
+impl ESIClient {
+    /// Resolves `ids` of any kind to their names, [`NAMES_BATCH_LIMIT`] IDs per request.
+    /// Duplicate IDs are only sent once.
+    ///
+    /// ESI rejects the whole batch with a 404 if any ID in it doesn't exist, so only pass IDs
+    /// that came from ESI.
+    pub async fn resolve_names(&self, ids: &[u64]) -> Result<Vec<ResolvedName>, EsiError> {
+        let mut seen = HashSet::new();
+        let ids: Vec<u64> = ids.iter().copied().filter(|id| seen.insert(*id)).collect();
+
+        let mut names = Vec::with_capacity(ids.len());
+        for batch in ids.chunks(NAMES_BATCH_LIMIT) {
+            let resolved: Vec<ResolvedName> = self.esi_post_json("/universe/names/", batch).await?;
+            names.extend(resolved);
+        }
+
+        Ok(names)
+    }
+
+    /// Looks up the IDs of entities called exactly `names`, such as "Jita" or "Tritanium",
+    /// [`IDS_BATCH_LIMIT`] names per request. Duplicate names are only sent once.
+    pub async fn resolve_ids(&self, names: &[&str]) -> Result<ResolvedIds, EsiError> {
+        let mut seen = HashSet::new();
+        let names: Vec<&str> = names
+            .iter()
+            .copied()
+            .filter(|name| seen.insert(*name))
+            .collect();
+
+        let mut ids = ResolvedIds::default();
+        for batch in names.chunks(IDS_BATCH_LIMIT) {
+            ids.extend(self.esi_post_json("/universe/ids/", batch).await?);
+        }
+
+        Ok(ids)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+    use wiremock::{
+        Mock, MockServer, Request, ResponseTemplate,
+        matchers::{method, path, path_regex, query_param},
+    };
+
+    /// In-memory store standing in for e.g. Redis.
+    #[derive(Default, Debug)]
+    struct MemoryStore {
+        blobs: Mutex<std::collections::HashMap<String, String>>,
+    }
+
+    impl UniverseCacheStore for Arc<MemoryStore> {
+        async fn load(&self, key: &str) -> Option<String> {
+            self.blobs.lock().unwrap().get(key).cloned()
+        }
+
+        async fn store(&self, key: &str, val: &str) {
+            self.blobs
+                .lock()
+                .unwrap()
+                .insert(key.to_string(), val.to_string());
+        }
+    }
+
+    fn client() -> Arc<ESIClient> {
+        Arc::new(ESIClient::in_memory("test", "test", 1))
+    }
+
+    /// A client for `server`, its HTTP cache only kept in memory.
+    fn mock_client(server: &MockServer) -> ESIClient {
+        ESIClient::in_memory("test", "test", 4).with_base_url(&server.uri())
+    }
+
+    #[tokio::test]
+    async fn test_regions_round_trip_through_store() {
+        let store = Arc::new(MemoryStore::default());
+        let regions = Regions::with_store(client(), store.clone());
+        let id = RegionID::try_from(10_000_002).unwrap();
+        regions.region_map.insert(
+            id,
+            Region {
+                id,
+                name: String::from("The Forge"),
+            },
+        );
+        regions.save_to_cache().await;
+
+        let reloaded = Regions::with_store(client(), store);
+        assert_eq!(reloaded.load_from_cache().await, 1);
+        assert_eq!(reloaded.region_map.get(&id).unwrap().name, "The Forge");
+    }
+
+    #[tokio::test]
+    async fn test_client_cache_dir_is_used_by_file_store() {
+        let dir = std::env::temp_dir().join(format!("universe-cache-{}", std::process::id()));
+        let client = Arc::new(
+            ESIClient::builder()
+                .cache_mode(crate::ResponseCache::Memory)
+                .cache_dir(&dir)
+                .build(),
+        );
+        assert_eq!(client.cache_dir(), dir);
+
+        let regions = Regions::new(client.clone());
+        let id = RegionID::try_from(10_000_043).unwrap();
+        regions.region_map.insert(
+            id,
+            Region {
+                id,
+                name: String::from("Domain"),
+            },
+        );
+        regions.save_to_cache().await;
+        assert!(dir.join("esi").join("regions.json").exists());
+
+        let reloaded = Regions::new(client);
+        assert_eq!(reloaded.load_from_cache().await, 1);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_file_store_concurrent_stores_stay_whole() {
+        let dir = std::env::temp_dir().join(format!("universe-store-{}", std::process::id()));
+        let store = FileStore::new(&dir);
+        let blobs: Vec<String> = (0..16)
+            .map(|i| serde_json::to_string(&vec![i; 10_000]).unwrap())
+            .collect();
+
+        futures::future::join_all(blobs.iter().map(|blob| store.store("stations", blob))).await;
+
+        let stored = store.load("stations").await.unwrap();
+        assert!(blobs.contains(&stored));
+        let files: Vec<_> = std::fs::read_dir(&dir).unwrap().collect();
+        assert_eq!(files.len(), 1, "no partial files are left behind");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_station_saves_are_batched() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path_regex(r"^/universe/stations/\d+/$"))
+            .respond_with(|req: &Request| {
+                let id: u64 = req.url.path().split('/').nth(3).unwrap().parse().unwrap();
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "station_id": id, "system_id": 30000142, "name": format!("Station {id}"),
+                }))
+            })
+            .mount(&server)
+            .await;
+        let esi = mock_client(&server);
+        let store = Arc::new(MemoryStore::default());
+        let stations = Stations::with_store(Arc::new(esi), store.clone());
+
+        let saved = || async {
+            let reloaded = Stations::with_store(client(), store.clone());
+            reloaded.load_from_cache().await
+        };
+        for id in [60003760, 60008494, 60004588] {
+            let id = StationID::try_from(id).unwrap();
+            stations.get_station(id).await.unwrap();
+        }
+        // the first fetch saved right away, the others wait for the next save
+        assert_eq!(saved().await, 1);
+
+        stations.save_to_cache().await;
+        assert_eq!(saved().await, 3);
+    }
+
+    #[tokio::test]
+    async fn test_get_all_fetches_only_missing_regions() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/regions/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json([10000001, 10000002, 10000003]))
+            .mount(&server)
+            .await;
+        for (id, name) in [(10000001, "Derelik"), (10000003, "Vale of the Silent")] {
+            Mock::given(method("GET"))
+                .and(path(format!("/universe/regions/{id}/")))
+                .respond_with(
+                    ResponseTemplate::new(200)
+                        .set_body_json(serde_json::json!({ "region_id": id, "name": name })),
+                )
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+
+        // The Forge is already cached, so it must not be requested
+        let store = Arc::new(MemoryStore::default());
+        let forge = RegionID::try_from(10_000_002).unwrap();
+        let cached = Regions::with_store(client(), store.clone());
+        cached.region_map.insert(
+            forge,
+            Region {
+                id: forge,
+                name: String::from("The Forge"),
+            },
+        );
+        cached.save_to_cache().await;
+
+        let client = mock_client(&server);
+        let regions = Regions::get_all_with_store(Arc::new(client), store, 1)
+            .await
+            .unwrap();
+
+        assert_eq!(regions.region_map.len(), 3);
+        assert_eq!(regions.region_map.get(&forge).unwrap().name, "The Forge");
+    }
+
+    #[tokio::test]
+    async fn test_constellations_get_all_skips_stored() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/constellations/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json([20000020, 20000021]))
+            .mount(&server)
+            .await;
+        let other = make_constellation(20000021, 10000002, &[30000145]);
+        Mock::given(method("GET"))
+            .and(path("/universe/constellations/20000021/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(&other))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        // Kimotoro is already stored, so only the other constellation is fetched
+        let store = Arc::new(MemoryStore::default());
+        let cached = Constellations::with_store(client(), store.clone());
+        let kimotoro = make_constellation(20000020, 10000002, &[30000142, 30000144]);
+        cached.map.insert(kimotoro.id, kimotoro);
+        cached.save_to_cache().await;
+
+        let client = mock_client(&server);
+        let constellations = Constellations::get_all_with_store(Arc::new(client), store, 2)
+            .await
+            .unwrap();
+
+        assert_eq!(constellations.map.len(), 2);
+        assert_eq!(*constellations.map.get(&other.id).unwrap(), other);
+    }
+
+    #[tokio::test]
+    async fn test_items_get_all_pages_and_skips_non_marketable() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/types/"))
+            .and(query_param("page", "2"))
+            .respond_with(ResponseTemplate::new(200).set_body_json([35, 670, 671]))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(method("GET"))
+            .and(path("/universe/types/"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("x-pages", "2")
+                    .set_body_json([34]),
+            )
+            .mount(&server)
+            .await;
+        // the capsule has no market group
+        for (id, name, market_group_id) in [
+            (34, "Tritanium", Some(1857)),
+            (35, "Pyerite", Some(1857)),
+            (670, "Capsule", None),
+        ] {
+            Mock::given(method("GET"))
+                .and(path(format!("/universe/types/{id}/")))
+                .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                    "type_id": id,
+                    "group_id": 18,
+                    "icon_id": 22,
+                    "market_group_id": market_group_id,
+                    "name": name,
+                    "description": "",
+                })))
+                .expect(1)
+                .mount(&server)
+                .await;
+        }
+        // no icon_id, so it can't be decoded, and is fetched again by the next run
+        Mock::given(method("GET"))
+            .and(path("/universe/types/671/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "type_id": 671, "group_id": 18, "name": "Iconless", "description": "",
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+        let client = Arc::new(client);
+        let store = Arc::new(MemoryStore::default());
+        let items = Items::get_all_with_store(client.clone(), store.clone(), 2)
+            .await
+            .unwrap();
+
+        assert_eq!(items.map.len(), 2);
+        let pyerite = items.map.get(&TypeID::from(35)).unwrap();
+        assert_eq!(pyerite.name(), "Pyerite");
+        assert_eq!(pyerite.market_group_id(), 1857);
+        assert_eq!((pyerite.group_id(), pyerite.icon_id()), (18, 22));
+        assert!(!items.map.contains_key(&TypeID::from(670)));
+
+        let reloaded = Items::with_store(items.client.clone(), store.clone());
+        assert_eq!(reloaded.load_from_cache().await, 2);
+        let tritanium = TypeID::from(34);
+        assert_eq!(
+            *reloaded.map.get(&tritanium).unwrap(),
+            *items.map.get(&tritanium).unwrap()
+        );
+        // known to be non-marketable without asking ESI again
+        let capsule = reloaded.get_item(TypeID::from(670)).await;
+        assert!(matches!(capsule, Err(UniverseError::NonMarketable(_))));
+
+        // only the type that failed is fetched again, the mocks check the counts
+        let rerun = Items::get_all_with_store(client, store, 2).await.unwrap();
+        assert_eq!(rerun.map.len(), 2);
+    }
+
+    #[tokio::test]
+    async fn test_invalidate_forces_refetch() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/systems/30000142/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(make_system(30000142, 20000020)))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let esi = mock_client(&server);
+        let store = Arc::new(MemoryStore::default());
+        let systems = Systems::with_store(Arc::new(esi), store.clone());
+        for id in [30000142, 30000144] {
+            let system = make_system(id, 20000020);
+            systems.map.insert(system.id, system);
+        }
+        systems.save_to_cache().await;
+
+        // cached, so ESI isn't asked
+        let jita = SystemID::try_from(30000142).unwrap();
+        systems.get_system(jita).await.unwrap();
+
+        // dropped from memory and from the store, so get_system has to go back to ESI
+        systems.invalidate(jita).await;
+        assert!(!systems.map.contains_key(&jita));
+
+        let reloaded = Systems::with_store(client(), store.clone());
+        assert_eq!(reloaded.load_from_cache().await, 1);
+        assert!(!reloaded.map.contains_key(&jita));
+
+        assert_eq!(systems.get_system(jita).await.unwrap().id, jita);
+        assert!(systems.map.contains_key(&jita));
+
+        systems.invalidate_all().await;
+        let reloaded = Systems::with_store(client(), store);
+        assert_eq!(reloaded.load_from_cache().await, 0);
+    }
+
+    fn make_system(id: u32, constellation: u32) -> System {
+        System {
+            id: SystemID::try_from(id).unwrap(),
+            constellation_id: ConstellationID::try_from(constellation).unwrap(),
+            position: Point::new(0.0, 0.0, 0.0),
+            security_status: 0.5,
+            name: format!("System {id}"),
+        }
+    }
+
+    fn make_constellation(id: u32, region: u32, systems: &[u32]) -> Constellation {
+        Constellation {
+            id: ConstellationID::try_from(id).unwrap(),
+            name: format!("Constellation {id}"),
+            region_id: RegionID::try_from(region).unwrap(),
+            systems: systems
+                .iter()
+                .map(|id| SystemID::try_from(*id).unwrap())
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn test_systems_by_region_joins_constellations() {
+        let systems = Systems::new(client());
+        for system in [
+            make_system(30_000_001, 20_000_001),
+            make_system(30_000_002, 20_000_001),
+            make_system(30_000_003, 20_000_002),
+            // constellation not loaded, so it can't be placed in a region
+            make_system(30_000_004, 20_000_003),
+        ] {
+            systems.map.insert(system.id, system);
+        }
+
+        let constellations = Constellations::new(client());
+        for constellation in [
+            make_constellation(20_000_001, 10_000_001, &[30_000_001, 30_000_002]),
+            make_constellation(20_000_002, 10_000_001, &[30_000_003]),
+        ] {
+            constellations.map.insert(constellation.id, constellation);
+        }
+
+        let by_region = systems_by_region(&systems, &constellations);
+        assert_eq!(by_region.len(), 1);
+        let ids: Vec<u32> = by_region[&RegionID::try_from(10_000_001).unwrap()]
+            .iter()
+            .map(SystemID::get)
+            .collect();
+        assert_eq!(ids, vec![30_000_001, 30_000_002, 30_000_003]);
+
+        let by_constellation = systems_by_constellation(&systems);
+        assert_eq!(by_constellation.len(), 3);
+        assert_eq!(
+            by_constellation[&ConstellationID::try_from(20_000_001).unwrap()].len(),
+            2
+        );
+    }
+
+    #[test]
+    fn test_system_serializes_ids_as_numbers() {
+        let system = make_system(30000142, 20000020);
+        let json = serde_json::to_value(&system).unwrap();
+
+        assert_eq!(json["system_id"], 30000142);
+        assert_eq!(json["constellation_id"], 20000020);
+        assert_eq!(serde_json::from_value::<System>(json).unwrap(), system);
+    }
+
+    #[test]
+    fn test_system_details_from_esi() {
+        // trimmed /universe/systems/30000142/ response
+        let json = r#"{
+            "constellation_id": 20000020,
+            "name": "Jita",
+            "planets": [
+                {"planet_id": 40009077},
+                {"asteroid_belts": [40009079], "moons": [40009078, 40009080], "planet_id": 40009081}
+            ],
+            "position": {"x": -1.29e17, "y": 6.07e16, "z": 1.17e17},
+            "security_status": 0.9459131360054016,
+            "star_id": 40009076,
+            "stargates": [50001248, 50001249],
+            "stations": [60003760],
+            "system_id": 30000142
+        }"#;
+
+        let details: SystemDetails = serde_json::from_str(json).unwrap();
+        assert_eq!(details.star_id, Some(40009076));
+        assert_eq!(details.planets.len(), 2);
+        assert!(details.planets[0].moons.is_empty());
+        assert_eq!(details.planets[1].moons, vec![40009078, 40009080]);
+        assert_eq!(details.planets[1].asteroid_belts, vec![40009079]);
+        assert_eq!(
+            details.stations,
+            vec![StationID::try_from(60003760).unwrap()]
+        );
+
+        // the plain System still parses the same response
+        assert_eq!(serde_json::from_str::<System>(json).unwrap().name, "Jita");
+    }
+
+    #[tokio::test]
+    async fn test_universe_errors_by_status() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/universe/regions/10000001/"))
+            .respond_with(
+                ResponseTemplate::new(404)
+                    .insert_header("x-esi-error-limit-remain", "99")
+                    .insert_header("x-esi-error-limit-reset", "0"),
+            )
+            .mount(&server)
+            .await;
+        // a list where a region object belongs
+        Mock::given(method("GET"))
+            .and(path("/universe/regions/10000002/"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw("[]", "application/json"))
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+        let regions = Regions::new(Arc::new(client));
+
+        let missing = RegionID::try_from(10_000_001).unwrap();
+        let err = regions.get_region(missing).await.unwrap_err();
+        assert!(
+            matches!(&err, UniverseError::NotFound { url } if url == "/universe/regions/10000001/")
+        );
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+
+        let garbled = RegionID::try_from(10_000_002).unwrap();
+        let err = regions.get_region(garbled).await.unwrap_err();
+        assert!(matches!(err, UniverseError::Deserialize(_)));
+        assert!(regions.region_map.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_route_sends_flag() {
+        let server = MockServer::start().await;
+        Mock::given(method("GET"))
+            .and(path("/route/30000142/30002187/"))
+            .and(query_param("flag", "secure"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(serde_json::json!([30000142, 30000144, 30002187])),
+            )
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+
+        let jita = SystemID::try_from(30000142).unwrap();
+        let amarr = SystemID::try_from(30002187).unwrap();
+        let route = client.route(jita, amarr, RouteFlag::Secure).await.unwrap();
+        assert_eq!(route.len() - 1, 2);
+        assert_eq!(route.first(), Some(&jita));
+        assert_eq!(route.last(), Some(&amarr));
+
+        let err = client
+            .route(jita, amarr, RouteFlag::Shortest)
+            .await
+            .unwrap_err();
+        assert_eq!(err.status(), Some(StatusCode::NOT_FOUND));
+    }
+
+    #[test]
+    fn test_point_distance() {
+        let origin = Point::new(0.0, 0.0, 0.0);
+        let ly = METRES_PER_LIGHT_YEAR;
+        let far = Point::new(3.0 * ly, 4.0 * ly, 0.0);
+        assert_eq!(Point::new(1.0, 2.0, 2.0).distance(&origin), 3.0);
+        assert_eq!(origin.distance_ly(&far), 5.0);
+        assert_eq!((far.x(), far.y(), far.z()), (3.0 * ly, 4.0 * ly, 0.0));
+    }
+
+    #[tokio::test]
+    async fn test_resolve_names_in_batches() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/universe/names/"))
+            .respond_with(|req: &Request| {
+                let ids: Vec<u64> = req.body_json().unwrap();
+                assert!(ids.len() <= NAMES_BATCH_LIMIT);
+                let names: Vec<_> = ids
+                    .into_iter()
+                    .map(|id| {
+                        serde_json::json!({
+                            "id": id,
+                            "name": format!("name {id}"),
+                            "category": if id == 1 { "solar_system" } else { "mercenary_den" },
+                        })
+                    })
+                    .collect();
+                ResponseTemplate::new(200).set_body_json(names)
+            })
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+
+        let mut ids: Vec<u64> = (1..=NAMES_BATCH_LIMIT as u64 + 5).collect();
+        ids.push(1);
+        let names = client.resolve_names(&ids).await.unwrap();
+
+        assert_eq!(names.len(), NAMES_BATCH_LIMIT + 5);
+        assert_eq!(names[0].name, "name 1");
+        assert_eq!(names[0].category, NameCategory::SolarSystem);
+        assert_eq!(names[1].category, NameCategory::Unknown);
+    }
+
+    #[tokio::test]
+    async fn test_resolve_ids_merges_batches() {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .and(path("/universe/ids/"))
+            .respond_with(|req: &Request| {
+                let names: Vec<String> = req.body_json().unwrap();
+                assert!(names.len() <= IDS_BATCH_LIMIT);
+                // only the first batch has a match, categories without one are left out
+                let body = if names.contains(&String::from("Jita")) {
+                    serde_json::json!({
+                        "systems": [{"id": 30000142, "name": "Jita"}],
+                        "inventory_types": [{"id": 34, "name": "Tritanium"}],
+                    })
+                } else {
+                    serde_json::json!({"regions": [{"id": 10000002, "name": "The Forge"}]})
+                };
+                ResponseTemplate::new(200).set_body_json(body)
+            })
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let client = mock_client(&server);
+
+        let filler: Vec<String> = (0..IDS_BATCH_LIMIT).map(|n| format!("name {n}")).collect();
+        let mut names = vec!["Jita", "Tritanium", "Jita"];
+        names.extend(filler.iter().map(String::as_str));
+        let ids = client.resolve_ids(&names).await.unwrap();
+
+        assert_eq!(ids.systems.len(), 1);
+        assert_eq!(ids.systems[0].id, 30000142);
+        assert_eq!(ids.inventory_types[0].id, 34);
+        assert_eq!(ids.regions[0].name, "The Forge");
+        assert!(ids.stations.is_empty());
+        assert_eq!(ids.in_category(NameCategory::SolarSystem), &ids.systems[..]);
+        assert!(ids.in_category(NameCategory::Unknown).is_empty());
+        let total = ids.systems.len() + ids.inventory_types.len() + ids.regions.len();
+        assert_eq!(ids.into_all().len(), total);
+    }
+
+    #[test]
+    fn test_items_search_loaded_names() {
+        let items = Items::with_store(client(), Arc::new(MemoryStore::default()));
+        for (id, name) in [(34, "Tritanium"), (35, "Pyerite"), (41, "Trinity")] {
+            let item: Item = serde_json::from_value(serde_json::json!({
+                "id": id,
+                "group_id": 18,
+                "icon_id": 22,
+                "market_group_id": 1857,
+                "name": name,
+                "description": "",
+            }))
+            .unwrap();
+            items.map.insert(item.id(), item);
+        }
+
+        let names: Vec<String> = items.search("TRI").into_iter().map(|(_, n)| n).collect();
+        assert_eq!(names, vec!["Trinity", "Tritanium"]);
+        assert!(items.search("Zydrine").is_empty());
+    }
+
+    #[test]
+    fn test_items_load_materials() {
+        let items = Items::with_store(client(), Arc::new(MemoryStore::default()));
+        // typeMaterials entries in the shape of the SDE table, with made up quantities
+        let json = r#"{
+            "587": {"materials": [
+                {"materialTypeID": 34, "quantity": 24000},
+                {"materialTypeID": 35, "quantity": 6000}
+            ]},
+            "1230": {"materials": [{"materialTypeID": 34, "quantity": 400}]}
+        }"#;
+        assert_eq!(items.load_materials_from_str(json).unwrap(), 2);
+
+        let rifter = items.get_materials(TypeID::from(587)).unwrap();
+        assert_eq!(
+            rifter[1],
+            TypeMaterial {
+                material_type_id: TypeID::from(35),
+                quantity: 6000,
+            }
+        );
+        assert_eq!(items.get_materials(TypeID::from(1230)).unwrap().len(), 1);
+        assert_eq!(items.get_materials(TypeID::from(34)), None);
+        assert!(items.load_materials_from_str("[]").is_err());
+    }
+
+    #[test]
+    fn test_station_from_esi() {
+        // trimmed /universe/stations/60003760/ response
+        let json = r#"{
+            "max_dockable_ship_volume": 50000000,
+            "name": "Jita IV - Moon 4 - Caldari Navy Assembly Plant",
+            "owner": 1000035,
+            "position": {"x": -107302625280.0, "y": -18745221120.0, "z": 436489789440.0},
+            "race_id": 1,
+            "services": ["bounty-missions", "market", "repair-facilities"],
+            "station_id": 60003760,
+            "system_id": 30000142,
+            "type_id": 52678
+        }"#;
+
+        let station: Station = serde_json::from_str(json).unwrap();
+        assert_eq!(station.type_id, Some(52678));
+        assert_eq!(station.owner, Some(1000035));
+        assert!(station.has_service("market"));
+        assert!(!station.has_service("cloning"));
+
+        // stations cached before the optional fields existed
+        let cached = r#"{"id": 60003760, "system_id": 30000142, "name": "Jita IV - Moon 4"}"#;
+        let station: Station = serde_json::from_str(cached).unwrap();
+        assert_eq!(station.type_id, None);
+        assert!(station.services.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_saves_each_chunk() {
+        let saves = std::sync::atomic::AtomicUsize::new(0);
+        let ids: Vec<u32> = (0..450).collect();
+
+        let count = prefetch_missing(
+            ids,
+            |id| async move {
+                match id % 100 {
+                    0 => Err(format!("{id} unavailable")),
+                    _ => Ok(id),
+                }
+            },
+            || async {
+                saves.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+            },
+        )
+        .await;
+
+        assert_eq!(count.fetched, 445);
+        assert_eq!(count.failed, 5);
+        assert_eq!(saves.into_inner(), 3);
+    }
+
+    #[test]
+    fn test_security_band_rounding() {
+        let cases = [
+            (1.0, SecurityBand::HighSec),
+            (0.5, SecurityBand::HighSec),
+            (0.45, SecurityBand::HighSec),
+            (0.449, SecurityBand::LowSec),
+            (0.1, SecurityBand::LowSec),
+            (0.049, SecurityBand::LowSec),
+            (0.0, SecurityBand::NullSec),
+            (-0.01, SecurityBand::NullSec),
+            (-1.0, SecurityBand::NullSec),
+        ];
+        for (security_status, band) in cases {
+            assert_eq!(
+                SecurityBand::from_status(security_status),
+                band,
+                "security status {security_status}"
+            );
+        }
+
+        let mut system = make_system(30000142, 20000020);
+        system.security_status = 0.9459131;
+        assert_eq!(system.security_band(), SecurityBand::HighSec);
+    }
+
+    #[test]
+    fn test_id_conversions() {
+        let region = RegionID::try_from(10000002).unwrap();
+        assert_eq!(u32::from(region), 10000002);
+        assert_eq!(region.to_string(), "10000002");
+
+        let station = StationID::try_from(60003760).unwrap();
+        assert_eq!(u64::from(station), 60003760);
+        assert_eq!(
+            format!("/universe/stations/{station}/"),
+            "/universe/stations/60003760/"
+        );
+    }
+
+    #[test]
+    fn test_region_id_bands() {
+        // Pochven, the last known space region
+        let pochven = RegionID::try_from(10_000_070).unwrap();
+        assert!(KSPACE_REGION_IDS.contains(&pochven.get()));
+        for (id, band) in [
+            (11_000_033, WORMHOLE_REGION_IDS),
+            (12_000_005, ABYSSAL_REGION_IDS),
+            (14_000_005, VOID_REGION_IDS),
+        ] {
+            RegionID::try_from(id).unwrap();
+            assert!(band.contains(&id));
+        }
+        let region: RegionID = serde_json::from_str("11000001").unwrap();
+        assert_eq!(region.get(), 11_000_001);
+
+        assert!(RegionID::try_from(9_999_999).is_err());
+        assert!(RegionID::try_from(20_000_001).is_err());
+        assert!(serde_json::from_str::<RegionID>("20000001").is_err());
+
+        // constellations and systems follow the same banding, Thera is a wormhole system
+        assert!(ConstellationID::try_from(20_000_788).is_ok());
+        assert!(SystemID::try_from(31_000_005).is_ok());
+        assert!(SystemID::try_from(20_000_788).is_err());
+    }
+
+    #[test]
+    fn test_location_id_ranges() {
+        let jita = LocationId::try_from(60_003_760).unwrap();
+        assert!(matches!(jita, LocationId::Station(_)));
+
+        // a public structure from the backend's allowlist
+        let structure = LocationId::try_from(1_042_508_032_148).unwrap();
+        assert!(matches!(structure, LocationId::Structure(_)));
+        assert_eq!(structure.id().get(), 1_042_508_032_148);
+
+        assert!(LocationId::try_from(64_000_000).is_err());
+        let err = LocationId::try_from(999_999_999_999).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Value 999999999999 is outside the valid range [60000000, 64000000] \
+             and below 1000000000000"
+        );
+    }
+
+    #[tokio::test]
+    async fn test_unreadable_cache_is_ignored() {
+        let store = Arc::new(MemoryStore::default());
+        store.store("systems", "not json").await;
+
+        let systems = Systems::with_store(client(), store);
+        assert_eq!(systems.load_from_cache().await, 0);
+        assert!(systems.map.is_empty());
+    }
+}
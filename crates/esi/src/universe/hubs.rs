@@ -0,0 +1,115 @@
+use std::fmt;
+
+use super::{Region, RegionID, StationID, SystemID};
+
+pub const JITA_4_4: StationID = StationID { value: 60_003_760 };
+pub const AMARR: StationID = StationID { value: 60_008_494 };
+pub const DODIXIE: StationID = StationID { value: 60_011_866 };
+pub const RENS: StationID = StationID { value: 60_004_588 };
+pub const HEK: StationID = StationID { value: 60_005_686 };
+
+pub const THE_FORGE: RegionID = RegionID { value: 10_000_002 };
+pub const DOMAIN: RegionID = RegionID { value: 10_000_043 };
+pub const SINQ_LAISON: RegionID = RegionID { value: 10_000_032 };
+pub const HEIMATAR: RegionID = RegionID { value: 10_000_030 };
+pub const METROPOLIS: RegionID = RegionID { value: 10_000_042 };
+
+/// The main NPC trade hubs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum TradeHub {
+    Jita,
+    Amarr,
+    Dodixie,
+    Rens,
+    Hek,
+}
+
+impl TradeHub {
+    pub const ALL: [TradeHub; 5] = [
+        TradeHub::Jita,
+        TradeHub::Amarr,
+        TradeHub::Dodixie,
+        TradeHub::Rens,
+        TradeHub::Hek,
+    ];
+
+    /// The station the hub's trade happens in.
+    pub fn station_id(&self) -> StationID {
+        match self {
+            TradeHub::Jita => JITA_4_4,
+            TradeHub::Amarr => AMARR,
+            TradeHub::Dodixie => DODIXIE,
+            TradeHub::Rens => RENS,
+            TradeHub::Hek => HEK,
+        }
+    }
+
+    pub fn system_id(&self) -> SystemID {
+        let value = match self {
+            TradeHub::Jita => 30_000_142,
+            TradeHub::Amarr => 30_002_187,
+            TradeHub::Dodixie => 30_002_659,
+            TradeHub::Rens => 30_002_510,
+            TradeHub::Hek => 30_002_053,
+        };
+        SystemID { value }
+    }
+
+    pub fn region_id(&self) -> RegionID {
+        match self {
+            TradeHub::Jita => THE_FORGE,
+            TradeHub::Amarr => DOMAIN,
+            TradeHub::Dodixie => SINQ_LAISON,
+            TradeHub::Rens => HEIMATAR,
+            TradeHub::Hek => METROPOLIS,
+        }
+    }
+
+    /// The hub's region, named so it can be passed to the market fetchers directly.
+    pub fn region(&self) -> Region {
+        let name = match self {
+            TradeHub::Jita => "The Forge",
+            TradeHub::Amarr => "Domain",
+            TradeHub::Dodixie => "Sinq Laison",
+            TradeHub::Rens => "Heimatar",
+            TradeHub::Hek => "Metropolis",
+        };
+        Region {
+            id: self.region_id(),
+            name: String::from(name),
+        }
+    }
+}
+
+impl fmt::Display for TradeHub {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            TradeHub::Jita => "Jita",
+            TradeHub::Amarr => "Amarr",
+            TradeHub::Dodixie => "Dodixie",
+            TradeHub::Rens => "Rens",
+            TradeHub::Hek => "Hek",
+        };
+        f.write_str(name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hub_ids_are_valid() {
+        // the constants skip the range checks, so make sure they'd pass them
+        for hub in TradeHub::ALL {
+            let station = hub.station_id();
+            assert_eq!(StationID::try_from(station.get()).unwrap(), station);
+            let system = hub.system_id();
+            assert_eq!(SystemID::try_from(system.get()).unwrap(), system);
+            let region = hub.region();
+            assert_eq!(RegionID::try_from(region.id.get()).unwrap(), region.id);
+        }
+        assert_eq!(TradeHub::Jita.station_id().get(), 60_003_760);
+        assert_eq!(TradeHub::Amarr.region().name, "Domain");
+    }
+}